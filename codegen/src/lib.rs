@@ -0,0 +1,180 @@
+//! Build script helper generating Rust source for a static trie from a prefix list,
+//! so small curated lists can be kept as text in the repository without parsing them
+//! at runtime.
+//!
+//! Every line of the prefix list is a cidr block, optionally followed by whitespace
+//! and a label that becomes its value. Empty lines and lines starting with `#` are
+//! ignored:
+//!
+//! ```text
+//! # feeds/bogons.txt
+//! 10.0.0.0/8      private
+//! 100.64.0.0/10   shared
+//! 192.0.2.0/24
+//! ```
+//!
+//! In `build.rs`:
+//!
+//! ```ignore
+//! fn main() {
+//!     mm2rtrie_codegen::generate("feeds/bogons.txt", "bogons.rs").unwrap();
+//! }
+//! ```
+//!
+//! And in the crate, which depends on `mm2rtrie`:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/bogons.rs"));
+//!
+//! assert!(TABLE.contains_ip(Ipv4Addr::new(10, 0, 0, 1)));
+//! ```
+
+use mm2rtrie::radix_trie::{CidrBlock, CidrError};
+
+use std::env;
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub enum CodegenError {
+    /// Reading the prefix list or writing the source failed.
+    Io(io::Error),
+    /// The cidr block of the line is invalid.
+    Cidr { line: usize, error: CidrError },
+    /// The name of the generated static is not a valid identifier.
+    InvalidName(String),
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenError::Io(e) => write!(f, "{}", e),
+            CodegenError::Cidr { line, error } => write!(f, "line {}: {}", line, error),
+            CodegenError::InvalidName(name) => write!(f, "invalid name '{}'", name),
+        }
+    }
+}
+
+impl Error for CodegenError {}
+
+impl From<io::Error> for CodegenError {
+    fn from(e: io::Error) -> Self {
+        CodegenError::Io(e)
+    }
+}
+
+/// Generate a static named `TABLE` from the prefix list at `input` into `output`,
+/// see [`generate_named`].
+pub fn generate(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<(), CodegenError> {
+    generate_named(input, output, "TABLE")
+}
+
+/// Generate a static of the name from the prefix list at `input` into `output`.
+///
+/// A relative output path is resolved against `OUT_DIR` when run from a build
+/// script, and cargo is told to rerun the build script when the input changes.
+pub fn generate_named(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    name: &str,
+) -> Result<(), CodegenError> {
+    let input: &Path = input.as_ref();
+    let output: PathBuf = match env::var_os("OUT_DIR") {
+        Some(dir) if output.as_ref().is_relative() => Path::new(&dir).join(output),
+        _ => output.as_ref().to_path_buf(),
+    };
+
+    let source: String = generate_source(&fs::read_to_string(input)?, name)?;
+    fs::write(output, source)?;
+    if env::var_os("OUT_DIR").is_some() {
+        println!("cargo:rerun-if-changed={}", input.display());
+    }
+    Ok(())
+}
+
+/// Generate the source of a static of the name, a lazily built
+/// `Trie<&'static str>` holding the blocks of the prefix list.
+pub fn generate_source(text: &str, name: &str) -> Result<String, CodegenError> {
+    let mut chars = name.chars();
+    let valid: bool = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(CodegenError::InvalidName(name.to_string()));
+    }
+
+    let mut blocks: String = String::new();
+    for (i, line) in text.lines().enumerate() {
+        let line: &str = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (cidr, label) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(cidr, label)| (cidr, label.trim()));
+        let cidr: CidrBlock =
+            CidrBlock::from_str(cidr).map_err(|error| CodegenError::Cidr { line: i + 1, error })?;
+        writeln!(
+            blocks,
+            "    ({:#010x}, {}, {:?}),",
+            cidr.net, cidr.prefix, label
+        )
+        .unwrap();
+    }
+
+    Ok(format!(
+        "// Generated by mm2rtrie_codegen, do not edit.
+
+pub static {name}: ::std::sync::LazyLock<::mm2rtrie::radix_trie::Trie<&'static str>> =
+    ::std::sync::LazyLock::new(|| {{
+        let mut trie = ::mm2rtrie::radix_trie::Trie::empty();
+        for &(net, prefix, value) in {name}_BLOCKS {{
+            trie.insert_net_and_prefix(net, prefix, value);
+        }}
+        trie
+    }});
+
+const {name}_BLOCKS: &[(u32, u32, &str)] = &[
+{blocks}];
+"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_of_prefix_list() {
+        let source: String = generate_source(
+            "# bogons\n10.0.0.0/8\tprivate\n\n100.64.0.0/10   shared \"cgn\"\n192.0.2.0/24\n",
+            "BOGONS",
+        )
+        .unwrap();
+
+        assert!(source.contains("pub static BOGONS: ::std::sync::LazyLock<"));
+        assert!(source.contains("in BOGONS_BLOCKS {"));
+        assert!(source.contains(
+            "const BOGONS_BLOCKS: &[(u32, u32, &str)] = &[
+    (0x0a000000, 8, \"private\"),
+    (0x64400000, 10, \"shared \\\"cgn\\\"\"),
+    (0xc0000200, 24, \"\"),
+];"
+        ));
+
+        assert!(matches!(
+            generate_source("10.0.0.0/8\n10.0.0.0/33\n", "BOGONS"),
+            Err(CodegenError::Cidr { line: 2, .. })
+        ));
+        assert!(matches!(
+            generate_source("", "1BOGONS"),
+            Err(CodegenError::InvalidName(_))
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+//! Radix trie storing its nodes in a single vector and linking them by index rather
+//! than by pointer, trading the simplicity of boxed nodes for fewer allocations and
+//! a smaller, relocatable footprint.
+
+use crate::radix_trie::{CidrBlock, IntoIpKey, Trie, TrieError};
+
+/// Index of the root node, which is never the child of another node and therefore
+/// also marks a missing child.
+const NO_CHILD: u32 = 0;
+
+#[derive(Clone, Debug)]
+struct Node<V> {
+    children: [u32; 2],
+    values: Vec<V>,
+}
+
+impl<V> Node<V> {
+    fn empty() -> Self {
+        Node {
+            children: [NO_CHILD; 2],
+            values: Vec::new(),
+        }
+    }
+}
+
+/// A trie of values with index linked nodes, holding the same values at the same
+/// cidr blocks and returning the same lookups as [`Trie`].
+#[derive(Clone, Debug)]
+pub struct ArenaTrie<V> {
+    nodes: Vec<Node<V>>,
+}
+
+fn bit(ip: u32, depth: u32) -> usize {
+    ((ip >> (31 - depth)) & 1) as usize
+}
+
+impl<V> ArenaTrie<V> {
+    /// Create a new empty trie.
+    pub fn empty() -> Self {
+        ArenaTrie {
+            nodes: vec![Node::empty()],
+        }
+    }
+
+    /// Insert the value at the cidr block.
+    pub fn try_insert(&mut self, cidr: &CidrBlock, value: V) -> Result<(), TrieError> {
+        if cidr.prefix > 32 {
+            return Err(TrieError::InvalidPrefix(cidr.prefix));
+        }
+
+        let mut index: usize = 0;
+        for depth in 0..cidr.prefix {
+            let b: usize = bit(cidr.net, depth);
+            let child: u32 = self.nodes[index].children[b];
+            index = if child == NO_CHILD {
+                let child: usize = self.nodes.len();
+                self.nodes.push(Node::empty());
+                self.nodes[index].children[b] = child as u32;
+                child
+            } else {
+                child as usize
+            };
+        }
+
+        self.nodes[index].values.push(value);
+        Ok(())
+    }
+
+    /// Get the values associated with the provided ip address.
+    pub fn get<K: IntoIpKey>(&self, ip: K) -> Vec<&V> {
+        let mut buffer: Vec<&V> = Vec::new();
+        let Some(ip) = ip.into_ip_key() else {
+            return buffer;
+        };
+
+        let mut node: &Node<V> = &self.nodes[0];
+        for depth in 0..=32 {
+            buffer.extend(node.values.iter());
+            if depth == 32 || node.children[bit(ip, depth)] == NO_CHILD {
+                break;
+            }
+            node = &self.nodes[node.children[bit(ip, depth)] as usize];
+        }
+        buffer
+    }
+
+    /// Get whether or not the trie contains the provided ip address.
+    pub fn contains_ip<K: IntoIpKey>(&self, ip: K) -> bool {
+        let Some(ip) = ip.into_ip_key() else {
+            return false;
+        };
+
+        let mut node: &Node<V> = &self.nodes[0];
+        for depth in 0..=32 {
+            if !node.values.is_empty() {
+                return true;
+            }
+            if depth == 32 || node.children[bit(ip, depth)] == NO_CHILD {
+                return false;
+            }
+            node = &self.nodes[node.children[bit(ip, depth)] as usize];
+        }
+        false
+    }
+
+    /// Remove and return every value stored at exactly the cidr block. Nodes are
+    /// never freed, so the nodes of the block stay in the arena.
+    pub fn remove(&mut self, cidr: &CidrBlock) -> Vec<V> {
+        let mut index: usize = 0;
+        for depth in 0..cidr.prefix.min(32) {
+            match self.nodes[index].children[bit(cidr.net, depth)] {
+                NO_CHILD => return Vec::new(),
+                child => index = child as usize,
+            }
+        }
+        std::mem::take(&mut self.nodes[index].values)
+    }
+
+    /// Get the number of nodes, including the root.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Get the cidr block and the index of every node holding values.
+    fn blocks(&self) -> Vec<(CidrBlock, usize)> {
+        let mut blocks: Vec<(CidrBlock, usize)> = Vec::new();
+        let mut stack: Vec<(usize, u32, u32)> = vec![(0, 0, 0)];
+        while let Some((index, net, depth)) = stack.pop() {
+            let node: &Node<V> = &self.nodes[index];
+            if !node.values.is_empty() {
+                blocks.push((CidrBlock { net, prefix: depth }, index));
+            }
+            for (b, &child) in node.children.iter().enumerate() {
+                if child != NO_CHILD {
+                    stack.push((
+                        child as usize,
+                        net | ((b as u32) << (31 - depth)),
+                        depth + 1,
+                    ));
+                }
+            }
+        }
+        blocks
+    }
+
+    /// Move the values into a trie holding them at the same cidr blocks.
+    pub fn into_trie(mut self) -> Trie<V> {
+        let mut trie: Trie<V> = Trie::empty();
+        for (cidr, index) in self.blocks() {
+            for value in std::mem::take(&mut self.nodes[index].values) {
+                trie.try_insert(&cidr, value).unwrap();
+            }
+        }
+        trie
+    }
+}
+
+impl<V: Clone> ArenaTrie<V> {
+    /// Build an arena trie holding the same values at the same cidr blocks.
+    pub fn from_trie(trie: &Trie<V>) -> Self {
+        let mut arena: ArenaTrie<V> = ArenaTrie::empty();
+        for (cidr, values) in trie.iter() {
+            for value in values {
+                arena.try_insert(&cidr, value.clone()).unwrap();
+            }
+        }
+        arena
+    }
+
+    /// Build a trie holding the same values at the same cidr blocks.
+    pub fn to_trie(&self) -> Trie<V> {
+        let mut trie: Trie<V> = Trie::empty();
+        for (cidr, index) in self.blocks() {
+            for value in self.nodes[index].values.iter() {
+                trie.try_insert(&cidr, value.clone()).unwrap();
+            }
+        }
+        trie
+    }
+}
+
+impl<V> Default for ArenaTrie<V> {
+    fn default() -> Self {
+        ArenaTrie::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::util::{generate_cidr_blocks, generate_ips};
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn lookups_match_trie() {
+        let mut trie: Trie<u32> = Trie::empty();
+        for (i, (net, prefix)) in generate_cidr_blocks(2_000).into_iter().enumerate() {
+            trie.insert_net_and_prefix(net, prefix, i as u32);
+        }
+        trie.insert_net_and_prefix(0x0a010203, 32, 0);
+
+        let arena: ArenaTrie<u32> = ArenaTrie::from_trie(&trie);
+        for ip in generate_ips(2_000).into_iter().chain([0x0a010203]) {
+            assert_eq!(trie.get(ip), arena.get(ip));
+            assert_eq!(trie.contains_ip(ip), arena.contains_ip(ip));
+        }
+        assert_eq!(trie, arena.to_trie());
+
+        let mut arena: ArenaTrie<u32> = arena;
+        let host = CidrBlock {
+            net: 0x0a010203,
+            prefix: 32,
+        };
+        assert_eq!(vec![0], arena.remove(&host));
+        assert!(arena.remove(&host).is_empty());
+        trie.remove(&host);
+        assert_eq!(trie, arena.into_trie());
+
+        let mut arena: ArenaTrie<u32> = ArenaTrie::empty();
+        assert_eq!(
+            Err(TrieError::InvalidPrefix(33)),
+            arena.try_insert(&CidrBlock { net: 0, prefix: 33 }, 0)
+        );
+        assert!(!arena.contains_ip(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(1, arena.node_count());
+    }
+}
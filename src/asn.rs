@@ -0,0 +1,129 @@
+//! Ip to origin ASN tables built from announced or registered routes.
+//!
+//! The table is a plain `Trie<u32>` of origin ASNs, so it is persisted with
+//! [`Trie::write_to_file`] and [`Trie::read_from_file`] like any other trie.
+
+use crate::irr::RouteObject;
+use crate::radix_trie::{CidrBlock, Trie};
+
+use std::collections::BTreeSet;
+use std::net::Ipv4Addr;
+
+/// Build an ip to origin ASN table from `(prefix, origin)` routes.
+///
+/// Duplicate routes, e.g. the same route registered in several IRR databases, are
+/// inserted once. A prefix with several origins (MOAS) keeps all of them, in
+/// ascending order. Routes with a prefix length above 32 are skipped.
+pub fn build<I>(routes: I) -> Trie<u32>
+where
+    I: IntoIterator<Item = (CidrBlock, u32)>,
+{
+    let routes: BTreeSet<(CidrBlock, u32)> = routes
+        .into_iter()
+        .filter_map(|(block, asn)| Some((CidrBlock::new(block.network(), block.prefix).ok()?, asn)))
+        .collect();
+
+    let mut trie: Trie<u32> = Trie::empty();
+    trie.insert_many_sorted(routes);
+    trie
+}
+
+/// Build an ip to origin ASN table from the route objects of an IRR import.
+pub fn from_irr(routes: &Trie<RouteObject>) -> Trie<u32> {
+    build(
+        routes
+            .iter()
+            .flat_map(|(block, objects)| objects.iter().map(move |o| (block, o.origin))),
+    )
+}
+
+/// Get the origin ASNs of the most specific prefix containing the ip address.
+pub fn origins_of(table: &Trie<u32>, ip: Ipv4Addr) -> &[u32] {
+    table.get_longest(ip)
+}
+
+/// Get the origin ASN of the most specific prefix containing the ip address, the
+/// lowest one if the prefix has several origins.
+pub fn origin_of(table: &Trie<u32>, ip: Ipv4Addr) -> Option<u32> {
+    origins_of(table, ip).first().copied()
+}
+
+/// Get every prefix originated by the ASN, ordered by network and prefix length.
+///
+/// This walks the whole table, build a map once if many ASNs are looked up.
+pub fn prefixes_for(table: &Trie<u32>, asn: u32) -> Vec<CidrBlock> {
+    table
+        .iter()
+        .filter(|(_, origins)| origins.contains(&asn))
+        .map(|(block, _)| block)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::irr;
+
+    use std::str::FromStr;
+
+    const DUMP: &str = "\
+route:   1.0.0.0/8
+origin:  AS4608
+source:  APNIC
+
+route:   1.1.1.0/24
+origin:  AS13335
+source:  RADB
+
+route:   1.1.1.0/24
+origin:  AS13335
+source:  APNIC
+
+route:   1.1.1.0/24
+origin:  AS4608
+source:  APNIC
+
+route:   8.8.8.0/24
+origin:  AS15169
+source:  RADB
+";
+
+    #[test]
+    fn table_from_irr() {
+        let (routes, _) = irr::load(DUMP.as_bytes()).unwrap();
+        let table: Trie<u32> = from_irr(&routes);
+
+        assert_eq!(
+            &[4608, 13335],
+            origins_of(&table, Ipv4Addr::new(1, 1, 1, 1))
+        );
+        assert_eq!(Some(4608), origin_of(&table, Ipv4Addr::new(1, 2, 3, 4)));
+        assert_eq!(None, origin_of(&table, Ipv4Addr::new(9, 9, 9, 9)));
+
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        assert_eq!(
+            vec![cidr("1.0.0.0/8"), cidr("1.1.1.0/24")],
+            prefixes_for(&table, 4608)
+        );
+        assert_eq!(vec![cidr("8.8.8.0/24")], prefixes_for(&table, 15169));
+        assert!(prefixes_for(&table, 1).is_empty());
+
+        let mut buf: Vec<u8> = Vec::new();
+        table.encode_to_writer(&mut buf).unwrap();
+        let (decoded, _): (Trie<u32>, usize) =
+            bincode::decode_from_slice(&buf, bincode::config::standard()).unwrap();
+        assert_eq!(table, decoded);
+
+        let long = CidrBlock {
+            net: 0x01010101,
+            prefix: 40,
+        };
+        assert_eq!(
+            vec![(cidr("1.0.0.0/8"), &[1][..])],
+            build([(long, 2), (cidr("1.0.0.0/8"), 1)])
+                .iter()
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! Common interface of the longest prefix match representations, so that a backend
+//! can be picked by measuring it on real data rather than by guessing.
+
+use crate::arena::ArenaTrie;
+use crate::builder::FrozenTrie;
+use crate::multibit::MultibitTrie;
+use crate::radix_trie::Trie;
+
+use bincode::{Decode, Encode};
+use rayon::prelude::*;
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Read side of a longest prefix match table, implemented by every trie representation.
+pub trait LpmBackend<V> {
+    /// Get the short name of the backend, e.g. for benchmark output.
+    fn name(&self) -> &'static str;
+
+    /// Get the values associated with the provided ip address, from the least to the
+    /// most specific block.
+    fn lookup(&self, ip: u32) -> Vec<&V>;
+
+    /// Get whether or not any value is associated with the provided ip address.
+    fn contains(&self, ip: u32) -> bool {
+        !self.lookup(ip).is_empty()
+    }
+}
+
+impl<V> LpmBackend<V> for Trie<V> {
+    fn name(&self) -> &'static str {
+        "radix"
+    }
+
+    fn lookup(&self, ip: u32) -> Vec<&V> {
+        self.get(ip)
+    }
+
+    fn contains(&self, ip: u32) -> bool {
+        self.contains_ip(ip)
+    }
+}
+
+impl<V> LpmBackend<V> for FrozenTrie<V> {
+    fn name(&self) -> &'static str {
+        "frozen"
+    }
+
+    fn lookup(&self, ip: u32) -> Vec<&V> {
+        self.get(ip)
+    }
+
+    fn contains(&self, ip: u32) -> bool {
+        self.contains_ip(ip)
+    }
+}
+
+impl<V> LpmBackend<V> for MultibitTrie<V> {
+    fn name(&self) -> &'static str {
+        "multibit"
+    }
+
+    fn lookup(&self, ip: u32) -> Vec<&V> {
+        self.get(ip)
+    }
+
+    fn contains(&self, ip: u32) -> bool {
+        self.contains_ip(ip)
+    }
+}
+
+impl<V> LpmBackend<V> for ArenaTrie<V> {
+    fn name(&self) -> &'static str {
+        "arena"
+    }
+
+    fn lookup(&self, ip: u32) -> Vec<&V> {
+        self.get(ip)
+    }
+
+    fn contains(&self, ip: u32) -> bool {
+        self.contains_ip(ip)
+    }
+}
+
+/// The available backends.
+#[derive(Clone, Copy, Debug, Decode, Encode, Eq, Hash, PartialEq)]
+pub enum BackendKind {
+    Radix,
+    Frozen,
+    Multibit,
+    Arena,
+}
+
+impl BackendKind {
+    /// Every backend, in the order they are benchmarked.
+    pub const ALL: [BackendKind; 4] = [
+        BackendKind::Radix,
+        BackendKind::Frozen,
+        BackendKind::Multibit,
+        BackendKind::Arena,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackendKind::Radix => "radix",
+            BackendKind::Frozen => "frozen",
+            BackendKind::Multibit => "multibit",
+            BackendKind::Arena => "arena",
+        }
+    }
+
+    /// Build the backend holding the same values at the same cidr blocks as the trie.
+    pub fn build<V>(&self, trie: Trie<V>) -> Arc<dyn LpmBackend<V> + Send + Sync>
+    where
+        V: Clone + Send + Sync + 'static,
+    {
+        match self {
+            BackendKind::Radix => Arc::new(trie),
+            BackendKind::Frozen => Arc::new(FrozenTrie::from(trie)),
+            BackendKind::Multibit => Arc::new(MultibitTrie::from_trie(&trie)),
+            BackendKind::Arena => Arc::new(ArenaTrie::from_trie(&trie)),
+        }
+    }
+}
+
+/// The boxed node radix trie, or the arena trie with the `arena-backend` feature.
+///
+/// The feature only changes this default, and so the storage of tables created with
+/// [`Table::new`](crate::table::Table::new). [`Trie`] itself always has boxed nodes,
+/// and tables created on an explicit backend are not affected.
+impl Default for BackendKind {
+    fn default() -> Self {
+        if cfg!(feature = "arena-backend") {
+            BackendKind::Arena
+        } else {
+            BackendKind::Radix
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct UnknownBackend(pub String);
+
+impl fmt::Display for UnknownBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown backend '{}', expected one of radix, frozen, multibit, arena",
+            self.0
+        )
+    }
+}
+
+impl Error for UnknownBackend {}
+
+impl FromStr for BackendKind {
+    type Err = UnknownBackend;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BackendKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| UnknownBackend(s.to_string()))
+    }
+}
+
+/// Outcome of benchmarking the lookups of a backend.
+#[derive(Clone, Debug)]
+pub struct BenchReport {
+    pub backend: &'static str,
+    pub lookups: usize,
+    /// Number of values found across all lookups.
+    pub hits: usize,
+    pub elapsed: Duration,
+}
+
+impl BenchReport {
+    /// Get the average time of a single lookup in nanoseconds.
+    pub fn ns_per_lookup(&self) -> f64 {
+        self.elapsed.as_nanos() as f64 / self.lookups.max(1) as f64
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<8} {} lookups in {} ms, {:.2} ns per lookup, {} hits",
+            self.backend,
+            self.lookups,
+            self.elapsed.as_millis(),
+            self.ns_per_lookup(),
+            self.hits,
+        )
+    }
+}
+
+/// Look up every ip in parallel on the backend, timing the lookups.
+pub fn bench<V, B>(backend: &B, ips: &[u32]) -> BenchReport
+where
+    V: Sync,
+    B: LpmBackend<V> + Sync + ?Sized,
+{
+    let start = Instant::now();
+    let hits: usize = ips.par_iter().map(|ip| backend.lookup(*ip).len()).sum();
+    BenchReport {
+        backend: backend.name(),
+        lookups: ips.len(),
+        hits,
+        elapsed: start.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::util::{generate_cidr_blocks, generate_ips};
+
+    #[test]
+    fn backends_agree() {
+        let mut trie: Trie<u32> = Trie::empty();
+        for (i, (net, prefix)) in generate_cidr_blocks(2_000).into_iter().enumerate() {
+            trie.insert_net_and_prefix(net, prefix, i as u32);
+        }
+
+        let ips: Vec<u32> = generate_ips(2_000);
+        let backends: Vec<_> = BackendKind::ALL
+            .iter()
+            .map(|kind| (kind, kind.build(trie.clone())))
+            .collect();
+        for (kind, backend) in backends.iter() {
+            assert_eq!(kind.name(), backend.name());
+            for ip in ips.iter() {
+                assert_eq!(trie.get(*ip), backend.lookup(*ip));
+                assert_eq!(trie.contains_ip(*ip), backend.contains(*ip));
+            }
+
+            let report: BenchReport = bench(backend.as_ref(), &ips);
+            assert_eq!(2_000, report.lookups);
+        }
+    }
+
+    #[test]
+    fn parse_backend_kind() {
+        assert_eq!(Ok(BackendKind::Multibit), "multibit".parse());
+        assert_eq!(
+            Err(UnknownBackend("btree".to_string())),
+            "btree".parse::<BackendKind>()
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Batches of inserts and removes that are validated up front and then applied as a
+//! whole, so that a feed update is either applied completely or not at all.
+
+use crate::maintenance::SharedTrie;
+use crate::radix_trie::{CidrBlock, Trie, TrieError};
+
+use std::fmt;
+use std::sync::Arc;
+
+/// A single mutation of a batch.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BatchOp<V> {
+    /// Insert the value at the cidr block.
+    Insert(CidrBlock, V),
+    /// Remove every value stored at exactly the cidr block.
+    Remove(CidrBlock),
+    /// Remove the most recently inserted equal value from exactly the cidr block.
+    RemoveValue(CidrBlock, V),
+}
+
+impl<V> BatchOp<V> {
+    fn cidr(&self) -> &CidrBlock {
+        match self {
+            BatchOp::Insert(cidr, _) | BatchOp::Remove(cidr) | BatchOp::RemoveValue(cidr, _) => {
+                cidr
+            }
+        }
+    }
+}
+
+/// An ordered batch of mutations.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrieBatch<V> {
+    ops: Vec<BatchOp<V>>,
+}
+
+impl<V> TrieBatch<V> {
+    /// Create a new empty batch.
+    pub fn new() -> Self {
+        TrieBatch { ops: Vec::new() }
+    }
+
+    /// Add an insert of the value at the cidr block.
+    pub fn insert(mut self, cidr: CidrBlock, value: V) -> Self {
+        self.ops.push(BatchOp::Insert(cidr, value));
+        self
+    }
+
+    /// Add a removal of every value stored at exactly the cidr block.
+    pub fn remove(mut self, cidr: CidrBlock) -> Self {
+        self.ops.push(BatchOp::Remove(cidr));
+        self
+    }
+
+    /// Add a removal of the value from exactly the cidr block.
+    pub fn remove_value(mut self, cidr: CidrBlock, value: V) -> Self {
+        self.ops.push(BatchOp::RemoveValue(cidr, value));
+        self
+    }
+
+    pub fn ops(&self) -> &[BatchOp<V>] {
+        &self.ops
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Check every mutation of the batch, returning the first invalid one.
+    pub fn validate(&self) -> Result<(), BatchError> {
+        for (index, op) in self.ops.iter().enumerate() {
+            if op.cidr().prefix > 32 {
+                return Err(BatchError {
+                    index,
+                    error: TrieError::InvalidPrefix(op.cidr().prefix),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<V> Default for TrieBatch<V> {
+    fn default() -> Self {
+        TrieBatch::new()
+    }
+}
+
+impl<V> FromIterator<BatchOp<V>> for TrieBatch<V> {
+    fn from_iter<I: IntoIterator<Item = BatchOp<V>>>(iter: I) -> Self {
+        TrieBatch {
+            ops: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<V> Extend<BatchOp<V>> for TrieBatch<V> {
+    fn extend<I: IntoIterator<Item = BatchOp<V>>>(&mut self, iter: I) {
+        self.ops.extend(iter);
+    }
+}
+
+/// The mutation of a batch that failed validation.
+#[derive(Debug, Eq, PartialEq)]
+pub struct BatchError {
+    /// Index of the mutation in the batch.
+    pub index: usize,
+    pub error: TrieError,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "batch operation {}: {}", self.index, self.error)
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+/// Outcome of applying a batch.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct BatchSummary {
+    /// Number of values inserted.
+    pub inserted: usize,
+    /// Number of values removed.
+    pub removed: usize,
+}
+
+impl<V: PartialEq> Trie<V> {
+    /// Validate the batch and, only if every mutation is valid, apply them in order.
+    /// On a validation error the trie is left unchanged.
+    pub fn apply(&mut self, batch: TrieBatch<V>) -> Result<BatchSummary, BatchError> {
+        batch.validate()?;
+
+        let mut summary = BatchSummary::default();
+        for op in batch.ops {
+            match op {
+                BatchOp::Insert(cidr, value) => {
+                    self.try_insert(&cidr, value).unwrap();
+                    summary.inserted += 1;
+                }
+                BatchOp::Remove(cidr) => summary.removed += self.remove(&cidr).len(),
+                BatchOp::RemoveValue(cidr, value) => {
+                    if self.remove_value(&cidr, &value) {
+                        summary.removed += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(summary)
+    }
+}
+
+/// Apply the batch to a shared trie. Readers holding a snapshot keep seeing the trie
+/// from before the batch, and readers taking a snapshot afterwards see all of it.
+pub fn apply_shared<V: PartialEq + Clone>(
+    table: &SharedTrie<V>,
+    batch: TrieBatch<V>,
+) -> Result<BatchSummary, BatchError> {
+    batch.validate()?;
+    let mut table = table.write().unwrap();
+    Arc::make_mut(&mut table).apply(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+    use std::sync::RwLock;
+
+    fn cidr(s: &str) -> CidrBlock {
+        CidrBlock::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn apply_batch() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.1.0.0/16", 2);
+        t.insert_cidr("10.1.0.0/16", 3);
+
+        let batch = TrieBatch::new()
+            .remove_value(cidr("10.1.0.0/16"), 2)
+            .remove_value(cidr("10.1.0.0/16"), 9)
+            .insert(cidr("10.2.0.0/16"), 4)
+            .remove(cidr("10.0.0.0/8"));
+        assert_eq!(
+            Ok(BatchSummary {
+                inserted: 1,
+                removed: 2
+            }),
+            t.apply(batch)
+        );
+
+        let mut expected: Trie<u32> = Trie::empty();
+        expected.insert_cidr("10.1.0.0/16", 3);
+        expected.insert_cidr("10.2.0.0/16", 4);
+        assert!(t.content_eq(&expected));
+    }
+
+    #[test]
+    fn invalid_batch_changes_nothing() {
+        let invalid = CidrBlock { net: 0, prefix: 33 };
+        let table: SharedTrie<u32> = Arc::new(RwLock::new(Arc::new(Trie::empty())));
+        let snapshot: Arc<Trie<u32>> = Arc::clone(&table.read().unwrap());
+
+        let batch = TrieBatch::new()
+            .insert(cidr("10.0.0.0/8"), 1)
+            .remove(invalid);
+        assert_eq!(
+            Err(BatchError {
+                index: 1,
+                error: TrieError::InvalidPrefix(33)
+            }),
+            apply_shared(&table, batch.clone())
+        );
+        assert!(Arc::ptr_eq(&snapshot, &table.read().unwrap()));
+
+        let batch = batch.ops()[..1].iter().cloned().collect();
+        assert!(apply_shared(&table, batch).is_ok());
+        assert!(!snapshot.contains_ip(Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(
+            table
+                .read()
+                .unwrap()
+                .contains_ip(Ipv4Addr::new(10, 0, 0, 1))
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Trie with a memory budget, protecting co-located services from an unexpectedly
+//! large feed import.
+
+use crate::radix_trie::{CidrBlock, IntoIpKey, Trie, TrieError, TrieNode};
+use crate::ttl::{self, Expiring};
+
+/// A trie whose estimated memory usage, see [`Trie::memory_usage`], never exceeds a
+/// limit. Inserts beyond the limit are rejected with [`TrieError::MemoryLimit`], or
+/// make room by evicting values first with [`BudgetedTrie::insert_evicting`].
+#[derive(Clone, Debug)]
+pub struct BudgetedTrie<V> {
+    trie: Trie<V>,
+    limit: usize,
+    usage: usize,
+}
+
+impl<V> BudgetedTrie<V> {
+    /// Create a new empty trie holding at most `limit` bytes.
+    pub fn with_memory_limit(limit: usize) -> Self {
+        BudgetedTrie::new(Trie::empty(), limit)
+    }
+
+    /// Enforce the limit on inserts into the trie, which may already exceed it.
+    pub fn new(trie: Trie<V>, limit: usize) -> Self {
+        let usage: usize = trie.memory_usage();
+        BudgetedTrie { trie, limit, usage }
+    }
+
+    pub fn trie(&self) -> &Trie<V> {
+        &self.trie
+    }
+
+    pub fn into_inner(self) -> Trie<V> {
+        self.trie
+    }
+
+    pub fn memory_limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Get the estimated memory usage of the trie in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.usage
+    }
+
+    /// Get the number of bytes inserting a value at the cidr block would add.
+    fn insert_cost(&self, cidr: &CidrBlock) -> usize {
+        let existing: u32 = self.trie.walk_depth(cidr.net).min(cidr.prefix);
+        (cidr.prefix - existing) as usize * size_of::<TrieNode<V>>() + size_of::<V>()
+    }
+
+    /// Insert the value at the cidr block, unless it would grow the trie beyond its
+    /// memory limit.
+    pub fn try_insert(&mut self, cidr: &CidrBlock, value: V) -> Result<(), TrieError> {
+        if cidr.prefix > 32 {
+            return Err(TrieError::InvalidPrefix(cidr.prefix));
+        }
+
+        let cost: usize = self.insert_cost(cidr);
+        if self.usage + cost > self.limit {
+            return Err(TrieError::MemoryLimit(self.limit));
+        }
+
+        self.trie.try_insert(cidr, value)?;
+        self.usage += cost;
+        Ok(())
+    }
+
+    /// Get the values associated with the provided ip address.
+    pub fn get<K: IntoIpKey>(&self, ip: K) -> Vec<&V> {
+        self.trie.get(ip)
+    }
+
+    /// Get the number of nodes on the path to the cidr block, which are the only
+    /// nodes removing values at the block can prune.
+    fn path_nodes(&self, cidr: &CidrBlock) -> u32 {
+        self.trie.walk_depth(cidr.net).min(cidr.prefix)
+    }
+
+    /// Subtract the values and the nodes on the path to the cidr block that were
+    /// freed since the path had `nodes` nodes.
+    fn release(&mut self, cidr: &CidrBlock, nodes: u32, values: usize) {
+        let pruned: u32 = nodes - self.path_nodes(cidr);
+        self.usage -= pruned as usize * size_of::<TrieNode<V>>() + values * size_of::<V>();
+    }
+
+    /// Remove every value stored at exactly the cidr block and return them.
+    pub fn remove(&mut self, cidr: &CidrBlock) -> Vec<V> {
+        let nodes: u32 = self.path_nodes(cidr);
+        let removed: Vec<V> = self.trie.remove(cidr);
+        self.release(cidr, nodes, removed.len());
+        removed
+    }
+
+    /// Drop the values at exactly the cidr block rejected by the predicate,
+    /// returning the number of values dropped.
+    fn retain_at<F: FnMut(&V) -> bool>(&mut self, cidr: &CidrBlock, pred: F) -> usize {
+        let nodes: u32 = self.path_nodes(cidr);
+        let dropped: usize = self
+            .trie
+            .update_exact(cidr, |values| {
+                let before: usize = values.len();
+                values.retain(pred);
+                before - values.len()
+            })
+            .unwrap_or(0);
+        self.release(cidr, nodes, dropped);
+        dropped
+    }
+
+    /// Drop every value rejected by the predicate, returning the number of values
+    /// dropped.
+    fn retain<F: FnMut(&V) -> bool>(&mut self, mut pred: F) -> usize {
+        let blocks: Vec<CidrBlock> = self
+            .trie
+            .iter()
+            .filter(|(_, values)| !values.iter().all(&mut pred))
+            .map(|(cidr, _)| cidr)
+            .collect();
+        blocks
+            .iter()
+            .map(|cidr| self.retain_at(cidr, &mut pred))
+            .sum()
+    }
+}
+
+impl<V> BudgetedTrie<Expiring<V>> {
+    /// Insert the value at the cidr block, evicting values to make room if needed:
+    /// first every expired value, then the values expiring soonest. Returns the
+    /// number of values evicted.
+    ///
+    /// Fails with [`TrieError::MemoryLimit`] only if the value does not fit even
+    /// into an empty trie, in which case nothing is evicted.
+    pub fn insert_evicting(
+        &mut self,
+        cidr: &CidrBlock,
+        value: Expiring<V>,
+    ) -> Result<usize, TrieError> {
+        if cidr.prefix > 32 {
+            return Err(TrieError::InvalidPrefix(cidr.prefix));
+        }
+
+        let alone: usize = size_of::<Trie<Expiring<V>>>()
+            + cidr.prefix as usize * size_of::<TrieNode<Expiring<V>>>()
+            + size_of::<Expiring<V>>();
+        if alone > self.limit {
+            return Err(TrieError::MemoryLimit(self.limit));
+        }
+
+        let mut evicted: usize = 0;
+        if self.usage + self.insert_cost(cidr) > self.limit {
+            let now: i64 = ttl::now();
+            evicted += self.retain(|v| !v.is_expired(now));
+        }
+
+        while self.usage + self.insert_cost(cidr) > self.limit {
+            let excess: usize = self.usage + self.insert_cost(cidr) - self.limit;
+            let mut expiries: Vec<i64> = self
+                .trie
+                .iter()
+                .flat_map(|(_, values)| values.iter().map(|v| v.expires_at))
+                .collect();
+            if expiries.is_empty() {
+                return Err(TrieError::MemoryLimit(self.limit));
+            }
+
+            // Every evicted value frees at least its own size, so evict at most as
+            // many of the soonest expiring values as the excess needs, and repeat in
+            // case the insert now needs nodes that the eviction pruned.
+            let n: usize = excess
+                .div_ceil(size_of::<Expiring<V>>())
+                .min(expiries.len());
+            let (_, &mut threshold, _) = expiries.select_nth_unstable(n - 1);
+            evicted += self.retain(|v| v.expires_at > threshold);
+        }
+
+        let cost: usize = self.insert_cost(cidr);
+        self.trie.try_insert(cidr, value)?;
+        self.usage += cost;
+        Ok(evicted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    #[test]
+    fn reject_and_evict_beyond_limit() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+
+        let mut t: BudgetedTrie<u32> = BudgetedTrie::with_memory_limit(2_000);
+        let mut inserted: u32 = 0;
+        let error = loop {
+            match t.try_insert(&cidr(&format!("10.{}.0.0/16", inserted)), inserted) {
+                Ok(()) => inserted += 1,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(TrieError::MemoryLimit(2_000), error);
+        assert!(inserted > 0);
+        assert_eq!(t.trie().memory_usage(), t.memory_usage());
+        assert!(t.memory_usage() <= t.memory_limit());
+
+        // Values on existing nodes cost only their own size.
+        let usage: usize = t.memory_usage();
+        t.remove(&cidr("10.0.0.0/16"));
+        assert!(t.memory_usage() < usage);
+        assert_eq!(Ok(()), t.try_insert(&cidr("10.1.0.0/16"), 1));
+
+        let mut hosts: BudgetedTrie<u32> = BudgetedTrie::new(t.trie().clone(), usize::MAX);
+        hosts.try_insert(&cidr("255.255.255.255/32"), 2).unwrap();
+        hosts.try_insert(&cidr("255.255.255.254/32"), 3).unwrap();
+        assert_eq!(hosts.trie().memory_usage(), hosts.memory_usage());
+        assert_eq!(vec![2], hosts.remove(&cidr("255.255.255.255/32")));
+        assert_eq!(hosts.trie().memory_usage(), hosts.memory_usage());
+        let hosts: BudgetedTrie<u32> = BudgetedTrie::new(hosts.into_inner(), usize::MAX);
+        assert_eq!(hosts.trie().memory_usage(), hosts.memory_usage());
+
+        let expiring = |value: u32, expires_at: i64| Expiring { value, expires_at };
+        let mut t: BudgetedTrie<Expiring<u32>> = BudgetedTrie::with_memory_limit(2_000);
+        for i in 0..3 {
+            t.insert_evicting(&cidr(&format!("10.{}.0.0/16", i)), expiring(i, 1))
+                .unwrap();
+        }
+        let mut evicted: usize = 0;
+        for i in 3..100 {
+            let expires_at: i64 = i64::MAX - 100 + i as i64;
+            let block: CidrBlock = cidr(&format!("10.{}.0.0/16", i));
+            evicted += t.insert_evicting(&block, expiring(i, expires_at)).unwrap();
+            assert!(t.memory_usage() <= t.memory_limit());
+            assert_eq!(t.trie().memory_usage(), t.memory_usage());
+        }
+
+        // The expired values went first, then the ones expiring soonest.
+        assert!(evicted >= 3);
+        assert!(t.get(Ipv4Addr::new(10, 0, 0, 1)).is_empty());
+        assert_eq!(1, t.get(Ipv4Addr::new(10, 99, 0, 1)).len());
+        let kept: Vec<u32> = t.trie().iter().map(|(_, v)| v[0].value).collect();
+        assert_eq!((100 - kept.len() as u32..100).collect::<Vec<u32>>(), kept);
+
+        assert_eq!(
+            Err(TrieError::MemoryLimit(100)),
+            BudgetedTrie::with_memory_limit(100)
+                .insert_evicting(&cidr("10.0.0.0/16"), expiring(1, 1))
+        );
+
+        // A value that can never fit is rejected without evicting anything.
+        let mut t: BudgetedTrie<Expiring<u32>> = BudgetedTrie::with_memory_limit(1_000);
+        for block in ["10.0.0.0/8", "10.128.0.0/9", "11.0.0.0/8"] {
+            t.insert_evicting(&cidr(block), expiring(1, i64::MAX))
+                .unwrap();
+        }
+        assert_eq!(3, t.trie().iter().count());
+        let before: Trie<Expiring<u32>> = t.trie().clone();
+        assert_eq!(
+            Err(TrieError::MemoryLimit(1_000)),
+            t.insert_evicting(&cidr("192.168.0.0/31"), expiring(2, i64::MAX))
+        );
+        assert_eq!(&before, t.trie());
+    }
+}
@@ -0,0 +1,145 @@
+//! Build-then-serve lifecycle: blocks are collected by a [`TrieBuilder`] and sealed
+//! into a [`FrozenTrie`] that can be shared between threads but never mutated.
+
+use crate::radix_trie::{CidrBlock, Trie, TrieError};
+
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Collects cidr blocks and their values to build a [`FrozenTrie`].
+///
+/// Inserts only append to a buffer, so inserting duplicates is cheap. The blocks are
+/// sorted and inserted into the trie in one go by [`TrieBuilder::build`].
+#[derive(Clone, Debug)]
+pub struct TrieBuilder<V> {
+    items: Vec<(CidrBlock, V)>,
+}
+
+impl<V> TrieBuilder<V> {
+    /// Create a new empty builder.
+    pub fn new() -> Self {
+        TrieBuilder { items: Vec::new() }
+    }
+
+    /// Create a new empty builder with room for `capacity` blocks.
+    pub fn with_capacity(capacity: usize) -> Self {
+        TrieBuilder {
+            items: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Add the value at the cidr block, rejecting invalid prefix lengths right away.
+    pub fn insert(&mut self, cidr: &CidrBlock, value: V) -> Result<(), TrieError> {
+        if cidr.prefix > 32 {
+            return Err(TrieError::InvalidPrefix(cidr.prefix));
+        }
+        self.items.push((*cidr, value));
+        Ok(())
+    }
+
+    /// Add the value at the cidr block given as `a.b.c.d/len`.
+    pub fn insert_cidr(&mut self, cidr: &str, value: V) {
+        let cidr: CidrBlock = CidrBlock::from_str(cidr).unwrap();
+        self.items.push((cidr, value));
+    }
+
+    /// Add the value at the network and prefix length.
+    pub fn insert_net_and_prefix(&mut self, net: u32, prefix: u32, value: V) {
+        self.insert(&CidrBlock { net, prefix }, value).unwrap();
+    }
+
+    /// Get the number of blocks added so far, counting duplicates.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Build the trie and seal it for serving.
+    pub fn build(self) -> Arc<FrozenTrie<V>> {
+        let mut trie: Trie<V> = Trie::empty();
+        trie.insert_many_sorted(self.items);
+        Arc::new(FrozenTrie { trie })
+    }
+}
+
+impl<V> Default for TrieBuilder<V> {
+    fn default() -> Self {
+        TrieBuilder::new()
+    }
+}
+
+impl<V> Extend<(CidrBlock, V)> for TrieBuilder<V> {
+    fn extend<I: IntoIterator<Item = (CidrBlock, V)>>(&mut self, iter: I) {
+        for (cidr, value) in iter {
+            self.insert(&cidr, value).unwrap();
+        }
+    }
+}
+
+/// A trie that can no longer be mutated, dereferencing to [`Trie`] for lookups.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FrozenTrie<V> {
+    trie: Trie<V>,
+}
+
+impl<V> FrozenTrie<V> {
+    /// Unseal the trie to mutate it, e.g. to build the next version of a table.
+    pub fn thaw(self) -> Trie<V> {
+        self.trie
+    }
+}
+
+impl<V> From<Trie<V>> for FrozenTrie<V> {
+    fn from(trie: Trie<V>) -> Self {
+        FrozenTrie { trie }
+    }
+}
+
+impl<V> Deref for FrozenTrie<V> {
+    type Target = Trie<V>;
+
+    fn deref(&self) -> &Trie<V> {
+        &self.trie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+    use std::thread;
+
+    #[test]
+    fn build_and_serve() {
+        let mut builder: TrieBuilder<u32> = TrieBuilder::with_capacity(4);
+        builder.insert_cidr("10.1.0.0/16", 2);
+        builder.insert_cidr("10.0.0.0/8", 1);
+        builder.insert_cidr("10.1.0.0/16", 2);
+        builder.extend([(CidrBlock::from_str("192.168.0.0/16").unwrap(), 3)]);
+        assert_eq!(
+            Err(TrieError::InvalidPrefix(40)),
+            builder.insert(&CidrBlock { net: 0, prefix: 40 }, 4)
+        );
+        assert_eq!(4, builder.len());
+
+        let frozen: Arc<FrozenTrie<u32>> = builder.build();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let frozen = Arc::clone(&frozen);
+                thread::spawn(move || frozen.get(Ipv4Addr::new(10, 1, 2, 3)).len())
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(3, handle.join().unwrap());
+        }
+
+        let mut trie: Trie<u32> = Arc::try_unwrap(frozen).unwrap().thaw();
+        trie.insert_cidr("172.16.0.0/12", 5);
+        assert!(trie.contains_ip(Ipv4Addr::new(172, 16, 0, 1)));
+    }
+}
@@ -0,0 +1,325 @@
+//! Importers for the ip range feeds published by the major cloud providers.
+//!
+//! Supported feeds:
+//! - AWS `ip-ranges.json`
+//! - Google Cloud `cloud.json`
+//! - Azure `ServiceTags_Public_*.json`
+//! - Cloudflare `https://api.cloudflare.com/client/v4/ips`
+//!
+//! IPv6 prefixes are skipped, since the trie only holds IPv4 prefixes.
+
+use crate::json::{self, JsonError, JsonValue};
+use crate::radix_trie::{CidrBlock, Trie};
+
+use bincode::{Decode, Encode};
+
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Decode, Encode, Eq, Hash, PartialEq)]
+pub enum Provider {
+    Aws,
+    Gcp,
+    Azure,
+    Cloudflare,
+}
+
+/// A published ip range of a cloud provider.
+#[derive(Clone, Debug, Decode, Encode, Eq, Hash, PartialEq)]
+pub struct CloudRange {
+    pub provider: Provider,
+    /// Region of the range, empty for global ranges.
+    pub region: String,
+    /// Service using the range, e.g. `EC2` or `AzureFrontDoor.Frontend`.
+    pub service: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CloudError {
+    /// The feed is not valid JSON.
+    Json(JsonError),
+    /// The feed is valid JSON but does not follow the schema of the provider.
+    Schema(&'static str),
+    /// A prefix of the feed is not a valid cidr block.
+    Prefix(String),
+}
+
+impl fmt::Display for CloudError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloudError::Json(e) => write!(f, "{}", e),
+            CloudError::Schema(reason) => write!(f, "unexpected feed schema: {}", reason),
+            CloudError::Prefix(prefix) => write!(f, "invalid prefix '{}'", prefix),
+        }
+    }
+}
+
+impl std::error::Error for CloudError {}
+
+impl From<JsonError> for CloudError {
+    fn from(e: JsonError) -> Self {
+        CloudError::Json(e)
+    }
+}
+
+/// Build a trie of the IPv4 ranges of a single feed.
+pub fn load(provider: Provider, feed: &str) -> Result<Trie<CloudRange>, CloudError> {
+    let mut trie: Trie<CloudRange> = Trie::empty();
+    load_into(&mut trie, provider, feed)?;
+    Ok(trie)
+}
+
+/// Insert the IPv4 ranges of a feed into an existing trie, e.g. to combine the feeds
+/// of several providers. Returns the number of ranges inserted.
+///
+/// Nothing is inserted if the feed can not be parsed.
+pub fn load_into(
+    trie: &mut Trie<CloudRange>,
+    provider: Provider,
+    feed: &str,
+) -> Result<usize, CloudError> {
+    let feed: JsonValue = json::parse(feed)?;
+    let ranges: Vec<(CidrBlock, CloudRange)> = match provider {
+        Provider::Aws => aws(&feed)?,
+        Provider::Gcp => gcp(&feed)?,
+        Provider::Azure => azure(&feed)?,
+        Provider::Cloudflare => cloudflare(&feed)?,
+    };
+
+    let inserted: usize = ranges.len();
+    for (block, range) in ranges {
+        trie.try_insert(&block, range).unwrap();
+    }
+    Ok(inserted)
+}
+
+/// Get the range of the most specific prefix containing the ip address, or `None`
+/// if the ip address is not in any cloud range.
+///
+/// Ranges published for several services, e.g. AWS publishes every `EC2` range under
+/// the catch-all `AMAZON` service as well, resolve to the one inserted last.
+pub fn classify(ranges: &Trie<CloudRange>, ip: Ipv4Addr) -> Option<&CloudRange> {
+    ranges.get(ip).pop()
+}
+
+fn range(provider: Provider, region: &str, service: &str) -> CloudRange {
+    CloudRange {
+        provider,
+        region: region.to_string(),
+        service: service.to_string(),
+    }
+}
+
+fn block(prefix: &str) -> Result<CidrBlock, CloudError> {
+    CidrBlock::from_str(prefix).map_err(|_| CloudError::Prefix(prefix.to_string()))
+}
+
+fn str_member<'a>(value: &'a JsonValue, key: &str) -> &'a str {
+    value.get(key).and_then(JsonValue::as_str).unwrap_or("")
+}
+
+fn aws(feed: &JsonValue) -> Result<Vec<(CidrBlock, CloudRange)>, CloudError> {
+    let prefixes: &[JsonValue] = feed
+        .get("prefixes")
+        .and_then(JsonValue::as_array)
+        .ok_or(CloudError::Schema("missing 'prefixes' array"))?;
+
+    prefixes
+        .iter()
+        .map(|p| {
+            let prefix: &str = p
+                .get("ip_prefix")
+                .and_then(JsonValue::as_str)
+                .ok_or(CloudError::Schema("missing 'ip_prefix'"))?;
+            let range = range(
+                Provider::Aws,
+                str_member(p, "region"),
+                str_member(p, "service"),
+            );
+            Ok((block(prefix)?, range))
+        })
+        .collect()
+}
+
+fn gcp(feed: &JsonValue) -> Result<Vec<(CidrBlock, CloudRange)>, CloudError> {
+    let prefixes: &[JsonValue] = feed
+        .get("prefixes")
+        .and_then(JsonValue::as_array)
+        .ok_or(CloudError::Schema("missing 'prefixes' array"))?;
+
+    // IPv4 and IPv6 prefixes share the array, only distinguished by their member name.
+    prefixes
+        .iter()
+        .filter_map(|p| {
+            p.get("ipv4Prefix")
+                .and_then(JsonValue::as_str)
+                .map(|s| (p, s))
+        })
+        .map(|(p, prefix)| {
+            let range = range(
+                Provider::Gcp,
+                str_member(p, "scope"),
+                str_member(p, "service"),
+            );
+            Ok((block(prefix)?, range))
+        })
+        .collect()
+}
+
+fn azure(feed: &JsonValue) -> Result<Vec<(CidrBlock, CloudRange)>, CloudError> {
+    let tags: &[JsonValue] = feed
+        .get("values")
+        .and_then(JsonValue::as_array)
+        .ok_or(CloudError::Schema("missing 'values' array"))?;
+
+    let mut ranges: Vec<(CidrBlock, CloudRange)> = Vec::new();
+    for tag in tags.iter() {
+        let properties: &JsonValue = tag
+            .get("properties")
+            .ok_or(CloudError::Schema("missing 'properties'"))?;
+        let prefixes: &[JsonValue] = properties
+            .get("addressPrefixes")
+            .and_then(JsonValue::as_array)
+            .ok_or(CloudError::Schema("missing 'addressPrefixes' array"))?;
+
+        // Service tags are named `<service>.<region>` for regional tags and `<service>`
+        // for the tag with the ranges of all regions.
+        let service: &str = str_member(properties, "systemService");
+        let service: &str = if service.is_empty() {
+            str_member(tag, "name")
+        } else {
+            service
+        };
+        let range = range(Provider::Azure, str_member(properties, "region"), service);
+
+        for prefix in prefixes.iter().filter_map(JsonValue::as_str) {
+            if !prefix.contains(':') {
+                ranges.push((block(prefix)?, range.clone()));
+            }
+        }
+    }
+
+    Ok(ranges)
+}
+
+fn cloudflare(feed: &JsonValue) -> Result<Vec<(CidrBlock, CloudRange)>, CloudError> {
+    let prefixes: &[JsonValue] = feed
+        .get("result")
+        .and_then(|r| r.get("ipv4_cidrs"))
+        .and_then(JsonValue::as_array)
+        .ok_or(CloudError::Schema("missing 'result.ipv4_cidrs' array"))?;
+
+    prefixes
+        .iter()
+        .map(|p| {
+            let prefix: &str = p
+                .as_str()
+                .ok_or(CloudError::Schema("'ipv4_cidrs' entry is not a string"))?;
+            Ok((block(prefix)?, range(Provider::Cloudflare, "", "")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AWS: &str = r#"{
+  "syncToken": "1700000000",
+  "createDate": "2024-01-01-00-00-00",
+  "prefixes": [
+    {"ip_prefix": "3.5.140.0/22", "region": "ap-northeast-2", "service": "AMAZON", "network_border_group": "ap-northeast-2"},
+    {"ip_prefix": "3.5.140.0/22", "region": "ap-northeast-2", "service": "EC2", "network_border_group": "ap-northeast-2"}
+  ],
+  "ipv6_prefixes": [
+    {"ipv6_prefix": "2600:1f14::/35", "region": "us-west-2", "service": "EC2", "network_border_group": "us-west-2"}
+  ]
+}"#;
+
+    const GCP: &str = r#"{
+  "syncToken": "1700000000",
+  "creationTime": "2024-01-01T00:00:00",
+  "prefixes": [
+    {"ipv4Prefix": "34.80.0.0/15", "service": "Google Cloud", "scope": "asia-east1"},
+    {"ipv6Prefix": "2600:1900:4030::/44", "service": "Google Cloud", "scope": "asia-east1"}
+  ]
+}"#;
+
+    const AZURE: &str = r#"{
+  "changeNumber": 1,
+  "cloud": "Public",
+  "values": [
+    {
+      "name": "AzureCloud.westeurope",
+      "id": "AzureCloud.westeurope",
+      "properties": {
+        "changeNumber": 1,
+        "region": "westeurope",
+        "regionId": 18,
+        "platform": "Azure",
+        "systemService": "",
+        "addressPrefixes": ["13.69.0.0/17", "2603:1020:200::/46"]
+      }
+    }
+  ]
+}"#;
+
+    const CLOUDFLARE: &str = r#"{
+  "result": {"ipv4_cidrs": ["104.16.0.0/13"], "ipv6_cidrs": ["2606:4700::/32"], "etag": "x"},
+  "success": true,
+  "errors": [],
+  "messages": []
+}"#;
+
+    #[test]
+    fn load_feeds() {
+        let mut trie: Trie<CloudRange> = Trie::empty();
+        assert_eq!(Ok(2), load_into(&mut trie, Provider::Aws, AWS));
+        assert_eq!(Ok(1), load_into(&mut trie, Provider::Gcp, GCP));
+        assert_eq!(Ok(1), load_into(&mut trie, Provider::Azure, AZURE));
+        assert_eq!(
+            Ok(1),
+            load_into(&mut trie, Provider::Cloudflare, CLOUDFLARE)
+        );
+
+        let classify = |ip: [u8; 4]| classify(&trie, Ipv4Addr::from(ip)).cloned();
+        assert_eq!(
+            Some(range(Provider::Aws, "ap-northeast-2", "EC2")),
+            classify([3, 5, 141, 1])
+        );
+        assert_eq!(
+            Some(range(Provider::Gcp, "asia-east1", "Google Cloud")),
+            classify([34, 81, 0, 1])
+        );
+        assert_eq!(
+            Some(range(
+                Provider::Azure,
+                "westeurope",
+                "AzureCloud.westeurope"
+            )),
+            classify([13, 69, 1, 1])
+        );
+        assert_eq!(
+            Some(range(Provider::Cloudflare, "", "")),
+            classify([104, 17, 0, 1])
+        );
+        assert_eq!(None, classify([8, 8, 8, 8]));
+    }
+
+    #[test]
+    fn invalid_feeds() {
+        assert_eq!(
+            Err(CloudError::Schema("missing 'prefixes' array")),
+            load(Provider::Aws, CLOUDFLARE)
+        );
+        assert_eq!(
+            Err(CloudError::Prefix("1.2.3.4/33".to_string())),
+            load(
+                Provider::Cloudflare,
+                r#"{"result": {"ipv4_cidrs": ["1.2.3.4/33"]}}"#
+            )
+        );
+        assert!(matches!(load(Provider::Gcp, "{"), Err(CloudError::Json(_))));
+    }
+}
@@ -0,0 +1,274 @@
+//! Thread-safe trie for services with many concurrent readers and occasional writers.
+
+use crate::maintenance::SharedTrie;
+use crate::radix_trie::Trie;
+
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A trie shared between threads, cloning the handle shares the same trie.
+///
+/// Readers work on a snapshot, so they only hold the lock for as long as it takes to
+/// clone an `Arc` and never observe a partially applied write. Writers mutate the
+/// current trie in place, copying it first if readers still hold a snapshot of it.
+pub struct ConcurrentTrie<V> {
+    table: SharedTrie<V>,
+}
+
+impl<V> Clone for ConcurrentTrie<V> {
+    fn clone(&self) -> Self {
+        ConcurrentTrie {
+            table: Arc::clone(&self.table),
+        }
+    }
+}
+
+impl<V> ConcurrentTrie<V> {
+    /// Share the trie between threads.
+    pub fn new(trie: Trie<V>) -> Self {
+        ConcurrentTrie {
+            table: Arc::new(RwLock::new(Arc::new(trie))),
+        }
+    }
+
+    /// Get the underlying shared trie, e.g. for code still working on `SharedTrie`.
+    pub fn shared(&self) -> SharedTrie<V> {
+        Arc::clone(&self.table)
+    }
+
+    /// Get a snapshot of the current trie, unaffected by later writes.
+    pub fn snapshot(&self) -> Arc<Trie<V>> {
+        Arc::clone(&self.table.read().unwrap())
+    }
+
+    /// Run the closure on a snapshot of the current trie.
+    pub fn read<R>(&self, f: impl FnOnce(&Trie<V>) -> R) -> R {
+        f(&self.snapshot())
+    }
+
+    /// Replace the current trie, returning the previous one. Readers holding a
+    /// snapshot keep seeing the previous trie.
+    pub fn swap(&self, trie: Trie<V>) -> Arc<Trie<V>> {
+        let trie: Arc<Trie<V>> = Arc::new(trie);
+        std::mem::replace(&mut *self.table.write().unwrap(), trie)
+    }
+
+    /// Replace the current trie and wait for up to `timeout` until readers released
+    /// every snapshot of the previous one, returning it once no reader holds it.
+    ///
+    /// In-flight lookups finish on the previous trie without errors, and once this
+    /// returns at most the current trie is resident again. Returns `None` if readers
+    /// still held a snapshot at the timeout, the previous trie is then dropped when
+    /// the last of them releases it.
+    pub fn swap_drained(&self, trie: Trie<V>, timeout: Duration) -> Option<Trie<V>> {
+        let start = Instant::now();
+        let mut previous: Arc<Trie<V>> = self.swap(trie);
+        loop {
+            match Arc::try_unwrap(previous) {
+                Ok(trie) => return Some(trie),
+                Err(_) if start.elapsed() >= timeout => return None,
+                Err(snapshot) => previous = snapshot,
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Build a new trie without holding the lock, e.g. by reading it from a file, and
+    /// swap it in if it was built successfully.
+    pub fn reload<E>(&self, load: impl FnOnce() -> Result<Trie<V>, E>) -> Result<(), E> {
+        let trie: Trie<V> = load()?;
+        self.swap(trie);
+        Ok(())
+    }
+
+    /// Replace the current trie only if it still is the snapshot, i.e. if neither
+    /// another swap nor a write happened since the snapshot was taken. Returns
+    /// whether or not the trie was replaced.
+    pub fn compare_and_swap(&self, snapshot: &Arc<Trie<V>>, trie: Trie<V>) -> bool {
+        let mut table = self.table.write().unwrap();
+        if !Arc::ptr_eq(&table, snapshot) {
+            return false;
+        }
+
+        *table = Arc::new(trie);
+        true
+    }
+}
+
+impl<V: Clone> ConcurrentTrie<V> {
+    /// Run the closure on the current trie mutably, while holding the write lock.
+    ///
+    /// The trie is copied first if readers still hold a snapshot of it, so long
+    /// running readers make writes more expensive but never see them.
+    pub fn write<R>(&self, f: impl FnOnce(&mut Trie<V>) -> R) -> R {
+        let mut table = self.table.write().unwrap();
+        f(Arc::make_mut(&mut table))
+    }
+}
+
+impl<V> From<SharedTrie<V>> for ConcurrentTrie<V> {
+    fn from(table: SharedTrie<V>) -> Self {
+        ConcurrentTrie { table }
+    }
+}
+
+impl<V> Default for ConcurrentTrie<V> {
+    fn default() -> Self {
+        ConcurrentTrie::new(Trie::empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::radix_trie::CidrBlock;
+
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Barrier, Weak};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn is_send_and_sync() {
+        assert_send_sync::<ConcurrentTrie<u32>>();
+        assert_send_sync::<ConcurrentTrie<String>>();
+    }
+
+    #[test]
+    fn readers_never_see_partial_writes() {
+        let table: ConcurrentTrie<u32> = ConcurrentTrie::default();
+
+        let writers: Vec<_> = (0..4u32)
+            .map(|w| {
+                let table = table.clone();
+                thread::spawn(move || {
+                    for i in 0..200u32 {
+                        // Every write inserts a pair of blocks, readers must see both or neither.
+                        let net: u32 = (w << 24) | (i << 8);
+                        table.write(|t| {
+                            t.insert_net_and_prefix(net, 24, i);
+                            t.insert_net_and_prefix(net | 0x80, 25, i);
+                        });
+                    }
+                })
+            })
+            .collect();
+
+        let readers: Vec<_> = (0..4u32)
+            .map(|_| {
+                let table = table.clone();
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        table.read(|t| {
+                            for (cidr, values) in t.iter() {
+                                let pair = if cidr.prefix == 24 {
+                                    CidrBlock {
+                                        net: cidr.net | 0x80,
+                                        prefix: 25,
+                                    }
+                                } else {
+                                    CidrBlock {
+                                        net: cidr.net & !0xff,
+                                        prefix: 24,
+                                    }
+                                };
+                                assert_eq!(values, t.get_exact(&pair));
+                            }
+                        });
+                    }
+                })
+            })
+            .collect();
+
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(1600, table.read(|t| t.iter().count()));
+    }
+
+    #[test]
+    fn swap_and_compare_and_swap() {
+        let table: ConcurrentTrie<u32> = ConcurrentTrie::default();
+        let snapshot: Arc<Trie<u32>> = table.snapshot();
+
+        let mut reloaded: Trie<u32> = Trie::empty();
+        reloaded.insert_cidr("10.0.0.0/8", 1);
+        assert_eq!(Ok(()), table.reload(|| Ok::<_, ()>(reloaded)));
+        assert_eq!(Err("broken"), table.reload(|| Err("broken")));
+
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        assert!(!snapshot.contains_ip(ip));
+        assert!(table.read(|t| t.contains_ip(ip)));
+
+        assert!(!table.compare_and_swap(&snapshot, Trie::empty()));
+        let current: Arc<Trie<u32>> = table.snapshot();
+        assert!(table.compare_and_swap(&current, Trie::empty()));
+        assert!(!table.read(|t| t.contains_ip(ip)));
+
+        let previous: Arc<Trie<u32>> = table.swap(current.as_ref().clone());
+        assert!(previous.get(ip).is_empty());
+        assert!(table.read(|t| t.contains_ip(ip)));
+    }
+
+    #[test]
+    fn swap_drained_while_reading() {
+        let release = |generation: u32| {
+            let mut trie: Trie<u32> = Trie::empty();
+            trie.insert_cidr("10.0.0.0/8", generation);
+            trie
+        };
+        let table: ConcurrentTrie<u32> = ConcurrentTrie::new(release(0));
+        let done = Arc::new(AtomicBool::new(false));
+        let started = Arc::new(Barrier::new(5));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (table, done) = (table.clone(), Arc::clone(&done));
+                let started = Arc::clone(&started);
+                thread::spawn(move || {
+                    let mut first: Option<Arc<Trie<u32>>> = Some(table.snapshot());
+                    started.wait();
+                    while !done.load(Ordering::Relaxed) {
+                        // Every lookup finishes on one table, old or new, and never misses.
+                        let snapshot: Arc<Trie<u32>> =
+                            first.take().unwrap_or_else(|| table.snapshot());
+                        assert_eq!(1, snapshot.get(Ipv4Addr::new(10, 1, 2, 3)).len());
+                    }
+                })
+            })
+            .collect();
+
+        let mut resident: Vec<Weak<Trie<u32>>> = vec![Arc::downgrade(&table.snapshot())];
+        started.wait();
+        for generation in 1..20 {
+            let previous: Option<Trie<u32>> =
+                table.swap_drained(release(generation), Duration::from_secs(10));
+            assert_eq!(
+                Some(vec![&(generation - 1)]),
+                previous.as_ref().map(|t| t.get(0x0a000001))
+            );
+            drop(previous);
+
+            // Once drained, only the current table is resident.
+            resident.push(Arc::downgrade(&table.snapshot()));
+            let alive: usize = resident.iter().filter(|t| t.upgrade().is_some()).count();
+            assert_eq!(1, alive);
+        }
+
+        done.store(true, Ordering::Relaxed);
+        for handle in readers {
+            handle.join().unwrap();
+        }
+
+        let held: Arc<Trie<u32>> = table.snapshot();
+        assert_eq!(
+            None,
+            table.swap_drained(Trie::empty(), Duration::from_millis(5))
+        );
+        assert_eq!(vec![&19], held.get(0x0a000001));
+    }
+}
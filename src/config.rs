@@ -0,0 +1,219 @@
+//! Configuration files in a subset of TOML.
+//!
+//! Supported are `[section]` headers, `key = value` pairs with basic strings, integers
+//! and booleans as values, and `#` comments. Keys before the first header belong to
+//! the section named `""`.
+//!
+//! ```text
+//! # mm2rtrie.toml
+//! [enrich]
+//! trie = "geo.bin"
+//! ip_column = 3
+//! header = true
+//! ```
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConfigValue {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+}
+
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigValue::String(s) => f.write_str(s),
+            ConfigValue::Integer(n) => write!(f, "{}", n),
+            ConfigValue::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// Line and reason of a syntax error.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ConfigError {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Sections of key value pairs, both ordered by name.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Config {
+    sections: BTreeMap<String, BTreeMap<String, ConfigValue>>,
+}
+
+impl Config {
+    /// Get the key value pairs of the section, if the config has it.
+    pub fn section(&self, name: &str) -> Option<&BTreeMap<String, ConfigValue>> {
+        self.sections.get(name)
+    }
+
+    /// Get the names of every section.
+    pub fn sections(&self) -> impl Iterator<Item = &str> {
+        self.sections.keys().map(String::as_str)
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&ConfigValue> {
+        self.section(section)?.get(key)
+    }
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// Parse a basic string starting after its opening quote, returning the string and
+/// the rest of the line after the closing quote.
+fn parse_string(s: &str) -> Option<(String, &str)> {
+    let mut out: String = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((out, &s[i + 1..])),
+            '\\' => out.push(match chars.next()?.1 {
+                '"' => '"',
+                '\\' => '\\',
+                'n' => '\n',
+                't' => '\t',
+                _ => return None,
+            }),
+            c => out.push(c),
+        }
+    }
+    None
+}
+
+fn parse_value(s: &str) -> Result<ConfigValue, &'static str> {
+    let (value, rest): (ConfigValue, &str) = if let Some(s) = s.strip_prefix('"') {
+        let (string, rest) = parse_string(s).ok_or("invalid string")?;
+        (ConfigValue::String(string), rest)
+    } else {
+        let (token, rest) = s.split_once('#').map_or((s, ""), |(t, r)| (t, r));
+        let token: &str = token.trim();
+        let value: ConfigValue = match token {
+            "true" => ConfigValue::Bool(true),
+            "false" => ConfigValue::Bool(false),
+            _ => ConfigValue::Integer(
+                token
+                    .replace('_', "")
+                    .parse()
+                    .map_err(|_| "unsupported value")?,
+            ),
+        };
+        (value, if rest.is_empty() { "" } else { "#" })
+    };
+
+    let rest: &str = rest.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err("trailing characters");
+    }
+    Ok(value)
+}
+
+/// Parse a complete config file.
+pub fn parse(text: &str) -> Result<Config, ConfigError> {
+    let mut config = Config::default();
+    let mut section: String = String::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let error = |reason: &'static str| ConfigError {
+            line: i + 1,
+            reason,
+        };
+        let line: &str = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let (name, rest) = header.split_once(']').ok_or(error("unclosed header"))?;
+            let rest: &str = rest.trim();
+            if !is_bare_key(name.trim()) || !(rest.is_empty() || rest.starts_with('#')) {
+                return Err(error("invalid header"));
+            }
+            section = name.trim().to_string();
+            config.sections.entry(section.clone()).or_default();
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or(error("expected '='"))?;
+        let key: &str = key.trim();
+        if !is_bare_key(key) {
+            return Err(error("invalid key"));
+        }
+
+        let value: ConfigValue = parse_value(value.trim()).map_err(error)?;
+        let members = config.sections.entry(section.clone()).or_default();
+        if members.insert(key.to_string(), value).is_some() {
+            return Err(error("duplicate key"));
+        }
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sections() {
+        let config: Config = parse(
+            r#"
+            # defaults
+            verbose = false
+
+            [enrich]
+            trie = "geo \"v2\".bin" # the table
+            ip_column = 1_000
+            header = true
+
+            [bench] # comment
+            backend = "all"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec!["", "bench", "enrich"],
+            config.sections().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(&ConfigValue::Bool(false)), config.get("", "verbose"));
+        assert_eq!(
+            Some(&ConfigValue::String("geo \"v2\".bin".to_string())),
+            config.get("enrich", "trie")
+        );
+        assert_eq!(
+            Some(&ConfigValue::Integer(1000)),
+            config.get("enrich", "ip_column")
+        );
+        assert_eq!(
+            Some(&ConfigValue::Bool(true)),
+            config.get("enrich", "header")
+        );
+        assert_eq!(None, config.get("bench", "blocks"));
+
+        let error = |reason: &'static str, line: usize| Err(ConfigError { line, reason });
+        assert_eq!(error("expected '='", 1), parse("trie"));
+        assert_eq!(error("invalid string", 1), parse("trie = \"geo"));
+        assert_eq!(error("unsupported value", 1), parse("tables = [1, 2]"));
+        assert_eq!(error("trailing characters", 1), parse("trie = \"a\" b"));
+        assert_eq!(error("unclosed header", 2), parse("a = 1\n[enrich"));
+        assert_eq!(error("duplicate key", 2), parse("a = 1\na = 2"));
+    }
+}
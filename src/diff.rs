@@ -0,0 +1,296 @@
+//! Change analytics between two releases of a table, e.g. to quantify how much each
+//! monthly GeoLite2 release actually moves.
+
+use crate::json::JsonValue;
+use crate::radix_trie::{CidrBlock, Trie, TrieNode};
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// Number of blocks added, removed and changed within some part of the table.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PrefixChurn {
+    pub added: usize,
+    pub removed: usize,
+    /// Blocks in both releases, but with different values.
+    pub changed: usize,
+}
+
+/// Number of addresses that had the value as one of their most specific values in
+/// each release.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValueShift<V> {
+    pub value: V,
+    pub old_addresses: u64,
+    pub new_addresses: u64,
+}
+
+impl<V> ValueShift<V> {
+    /// Get the number of addresses the value gained, negative if it lost addresses.
+    pub fn shift(&self) -> i64 {
+        self.new_addresses as i64 - self.old_addresses as i64
+    }
+}
+
+/// Changes of a new release of a table compared to the old one, see [`report`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChangeReport<V> {
+    pub blocks: PrefixChurn,
+    /// Churn of the blocks by their first octet, leaving out octets without changes.
+    pub by_octet: BTreeMap<u8, PrefixChurn>,
+    /// Number of addresses whose most specific values differ between the releases.
+    pub retagged_addresses: u64,
+    /// Address counts of every value in either release, ordered by value.
+    pub distribution: Vec<ValueShift<V>>,
+}
+
+impl<V> ChangeReport<V> {
+    /// Get the percentage of the ipv4 address space that was re-tagged.
+    pub fn retagged_percent(&self) -> f64 {
+        self.retagged_addresses as f64 / (1u64 << 32) as f64 * 100.0
+    }
+}
+
+impl<V: Display> ChangeReport<V> {
+    /// Get the report as a JSON object, with values written by their `Display`.
+    pub fn to_json(&self) -> JsonValue {
+        let churn = |c: &PrefixChurn| {
+            JsonValue::Object(BTreeMap::from([
+                ("added".to_string(), JsonValue::Number(c.added as f64)),
+                ("removed".to_string(), JsonValue::Number(c.removed as f64)),
+                ("changed".to_string(), JsonValue::Number(c.changed as f64)),
+            ]))
+        };
+        let by_octet = self
+            .by_octet
+            .iter()
+            .map(|(octet, c)| (format!("{}.0.0.0/8", octet), churn(c)))
+            .collect();
+        let distribution = self
+            .distribution
+            .iter()
+            .map(|shift| {
+                JsonValue::Object(BTreeMap::from([
+                    (
+                        "value".to_string(),
+                        JsonValue::String(shift.value.to_string()),
+                    ),
+                    (
+                        "old".to_string(),
+                        JsonValue::Number(shift.old_addresses as f64),
+                    ),
+                    (
+                        "new".to_string(),
+                        JsonValue::Number(shift.new_addresses as f64),
+                    ),
+                ]))
+            })
+            .collect();
+
+        JsonValue::Object(BTreeMap::from([
+            ("blocks".to_string(), churn(&self.blocks)),
+            ("by_octet".to_string(), JsonValue::Object(by_octet)),
+            (
+                "retagged_addresses".to_string(),
+                JsonValue::Number(self.retagged_addresses as f64),
+            ),
+            (
+                "retagged_percent".to_string(),
+                JsonValue::Number(self.retagged_percent()),
+            ),
+            ("distribution".to_string(), JsonValue::Array(distribution)),
+        ]))
+    }
+}
+
+/// A range of addresses, end exclusive, and its most specific values.
+type Range<'a, V> = (u64, u64, &'a [V]);
+
+/// Append the disjoint ranges of addresses below the node that match any values, in
+/// order, together with their most specific values.
+fn ranges<'a, V>(
+    node: &'a TrieNode<V>,
+    net: u64,
+    depth: u32,
+    inherited: &'a [V],
+    out: &mut Vec<Range<'a, V>>,
+) {
+    let values: &[V] = match node.values() {
+        [] => inherited,
+        values => values,
+    };
+    let half: u64 = 1 << (32 - depth) >> 1;
+    if depth == 32 {
+        out.extend((!values.is_empty()).then_some((net, net + 1, values)));
+        return;
+    }
+
+    for (child, start) in [(node.left(), net), (node.right(), net + half)] {
+        match child {
+            Some(child) => ranges(child, start, depth + 1, values, out),
+            None if !values.is_empty() => out.push((start, start + half, values)),
+            None => {}
+        }
+    }
+}
+
+fn address_ranges<V>(trie: &Trie<V>) -> Vec<Range<'_, V>> {
+    let mut out: Vec<Range<'_, V>> = Vec::new();
+    ranges(trie.root(), 0, 0, &[], &mut out);
+    out
+}
+
+/// Get the end of the segment starting at `at` and its values, which is the range
+/// `k` if it started already, or else the gap before it.
+fn segment<'a, V>(ranges: &[Range<'a, V>], k: usize, at: u64) -> (u64, &'a [V]) {
+    match ranges.get(k) {
+        Some(&(start, end, values)) if start <= at => (end, values),
+        Some(&(start, _, _)) => (start, &[]),
+        None => (1 << 32, &[]),
+    }
+}
+
+/// Compare two releases of a table, by their blocks and by the most specific values
+/// of every address.
+pub fn report<V: Ord + Clone>(old: &Trie<V>, new: &Trie<V>) -> ChangeReport<V> {
+    let mut blocks = PrefixChurn::default();
+    let mut by_octet: BTreeMap<u8, PrefixChurn> = BTreeMap::new();
+    let mut count = |cidr: &CidrBlock, f: fn(&mut PrefixChurn)| {
+        f(&mut blocks);
+        f(by_octet.entry((cidr.net >> 24) as u8).or_default());
+    };
+
+    // Both iterators are ordered by network and prefix length.
+    let (mut old_blocks, mut new_blocks) = (old.iter().peekable(), new.iter().peekable());
+    loop {
+        let order: Ordering = match (old_blocks.peek(), new_blocks.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((a, _)), Some((b, _))) => (a.net, a.prefix).cmp(&(b.net, b.prefix)),
+        };
+        match order {
+            Ordering::Less => {
+                let (cidr, _) = old_blocks.next().unwrap();
+                count(&cidr, |c| c.removed += 1);
+            }
+            Ordering::Greater => {
+                let (cidr, _) = new_blocks.next().unwrap();
+                count(&cidr, |c| c.added += 1);
+            }
+            Ordering::Equal => {
+                let ((cidr, a), (_, b)) = (old_blocks.next().unwrap(), new_blocks.next().unwrap());
+                if a != b {
+                    count(&cidr, |c| c.changed += 1);
+                }
+            }
+        }
+    }
+
+    // Sweep both partitions of the address space at once.
+    let (old_ranges, new_ranges) = (address_ranges(old), address_ranges(new));
+    let mut distribution: BTreeMap<&V, (u64, u64)> = BTreeMap::new();
+    let mut retagged_addresses: u64 = 0;
+    let (mut i, mut j, mut at): (usize, usize, u64) = (0, 0, 0);
+    while i < old_ranges.len() || j < new_ranges.len() {
+        let (old_end, old_values) = segment(&old_ranges, i, at);
+        let (new_end, new_values) = segment(&new_ranges, j, at);
+        let end: u64 = old_end.min(new_end);
+        let len: u64 = end - at;
+
+        if old_values != new_values {
+            retagged_addresses += len;
+        }
+        for value in old_values {
+            distribution.entry(value).or_default().0 += len;
+        }
+        for value in new_values {
+            distribution.entry(value).or_default().1 += len;
+        }
+
+        at = end;
+        i += usize::from(old_ranges.get(i).is_some_and(|r| r.1 <= at));
+        j += usize::from(new_ranges.get(j).is_some_and(|r| r.1 <= at));
+    }
+
+    ChangeReport {
+        blocks,
+        by_octet,
+        retagged_addresses,
+        distribution: distribution
+            .into_iter()
+            .map(|(value, (old_addresses, new_addresses))| ValueShift {
+                value: value.clone(),
+                old_addresses,
+                new_addresses,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_between_releases() {
+        let mut old: Trie<&str> = Trie::empty();
+        old.insert_cidr("10.0.0.0/8", "se");
+        old.insert_cidr("10.1.0.0/16", "no");
+        old.insert_cidr("81.2.69.0/24", "gb");
+        old.insert_cidr("81.2.70.0/24", "gb");
+
+        let mut new: Trie<&str> = Trie::empty();
+        new.insert_cidr("10.0.0.0/8", "se");
+        new.insert_cidr("10.1.0.0/16", "dk");
+        new.insert_cidr("81.2.69.0/24", "gb");
+        new.insert_cidr("192.0.2.0/24", "us");
+
+        let report: ChangeReport<&str> = report(&old, &new);
+        let churn = |added: usize, removed: usize, changed: usize| PrefixChurn {
+            added,
+            removed,
+            changed,
+        };
+        assert_eq!(churn(1, 1, 1), report.blocks);
+        assert_eq!(
+            BTreeMap::from([
+                (10, churn(0, 0, 1)),
+                (81, churn(0, 1, 0)),
+                (192, churn(1, 0, 0))
+            ]),
+            report.by_octet
+        );
+
+        // The /16 changed hands, one /24 was dropped and another one added.
+        assert_eq!(65536 + 256 + 256, report.retagged_addresses);
+        assert!((report.retagged_percent() - 66048.0 / 2f64.powi(32) * 100.0).abs() < 1e-12);
+
+        let shift = |value, old_addresses, new_addresses| ValueShift {
+            value,
+            old_addresses,
+            new_addresses,
+        };
+        assert_eq!(
+            vec![
+                shift("dk", 0, 65536),
+                shift("gb", 512, 256),
+                shift("no", 65536, 0),
+                shift("se", (1 << 24) - 65536, (1 << 24) - 65536),
+                shift("us", 0, 256),
+            ],
+            report.distribution
+        );
+        assert_eq!(-256, report.distribution[1].shift());
+
+        let json: String = report.to_json().to_string();
+        assert!(json.contains("\"blocks\":{\"added\":1,\"changed\":1,\"removed\":1}"));
+        assert!(json.contains("\"81.0.0.0/8\":{\"added\":0,\"changed\":0,\"removed\":1}"));
+        assert!(json.contains("{\"new\":65536,\"old\":0,\"value\":\"dk\"}"));
+
+        let same: ChangeReport<&str> = super::report(&new, &new.clone());
+        assert_eq!(0, same.retagged_addresses);
+        assert!(same.by_octet.is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! Tries of string values in a flat byte layout that is queried in place, e.g. a
+//! built-in bogon or cloud range table embedded with `include_bytes!`, without
+//! decoding anything at startup.
+//!
+//! ```ignore
+//! static BOGONS: &[u8] = include_bytes!("bogons.bin");
+//!
+//! let bogons = EmbeddedTrie::new(BOGONS).unwrap();
+//! assert!(bogons.contains_ip(Ipv4Addr::new(10, 0, 0, 1)));
+//! ```
+//!
+//! All integers are little endian `u32`s. The layout is a header of the magic, the
+//! version, the number of nodes and the number of values, followed by the nodes as
+//! `(left, right, first value, number of values)` with the root first and 0 as no
+//! child, the values as `(offset, length)` into the strings, and the strings.
+
+use crate::radix_trie::{IntoIpKey, Trie, TrieNode};
+
+use std::error::Error;
+use std::fmt;
+
+const MAGIC: [u8; 4] = *b"MMRE";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 16;
+const NODE_LEN: usize = 16;
+const VALUE_LEN: usize = 8;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum EmbeddedError {
+    /// The bytes do not start with the magic of an embedded trie.
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    /// The bytes are shorter than the header says.
+    Truncated,
+}
+
+impl fmt::Display for EmbeddedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbeddedError::InvalidMagic => write!(f, "not an embedded trie"),
+            EmbeddedError::UnsupportedVersion(v) => write!(f, "unsupported version {}", v),
+            EmbeddedError::Truncated => write!(f, "embedded trie is truncated"),
+        }
+    }
+}
+
+impl Error for EmbeddedError {}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+    Some(u32::from_le_bytes(bytes))
+}
+
+/// Encode the trie into the layout read by [`EmbeddedTrie`].
+pub fn encode<V: AsRef<str>>(trie: &Trie<V>) -> Vec<u8> {
+    let mut nodes: Vec<[u32; 4]> = Vec::new();
+    let mut values: Vec<&str> = Vec::new();
+    encode_node(trie.root(), &mut nodes, &mut values);
+
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend(MAGIC);
+    for field in [VERSION, nodes.len() as u32, values.len() as u32] {
+        bytes.extend(field.to_le_bytes());
+    }
+    for field in nodes.into_iter().flatten() {
+        bytes.extend(field.to_le_bytes());
+    }
+    let mut offset: u32 = 0;
+    for value in values.iter() {
+        bytes.extend(offset.to_le_bytes());
+        bytes.extend((value.len() as u32).to_le_bytes());
+        offset += value.len() as u32;
+    }
+    for value in values {
+        bytes.extend(value.as_bytes());
+    }
+    bytes
+}
+
+/// Append the node and its subtree in preorder, returning the number of the node.
+fn encode_node<'a, V: AsRef<str>>(
+    node: &'a TrieNode<V>,
+    nodes: &mut Vec<[u32; 4]>,
+    values: &mut Vec<&'a str>,
+) -> u32 {
+    let index: usize = nodes.len();
+    nodes.push([0, 0, values.len() as u32, node.values().len() as u32]);
+    values.extend(node.values().iter().map(AsRef::as_ref));
+
+    if let Some(left) = node.left() {
+        nodes[index][0] = encode_node(left, nodes, values);
+    }
+    if let Some(right) = node.right() {
+        nodes[index][1] = encode_node(right, nodes, values);
+    }
+    index as u32
+}
+
+/// A trie of string values read in place from bytes written by [`encode`].
+#[derive(Clone, Copy, Debug)]
+pub struct EmbeddedTrie<'a> {
+    bytes: &'a [u8],
+    nodes: usize,
+    values: usize,
+}
+
+impl<'a> EmbeddedTrie<'a> {
+    /// Check the header of the bytes and wrap them, without reading any nodes.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, EmbeddedError> {
+        if bytes.get(..4) != Some(&MAGIC[..]) {
+            return Err(EmbeddedError::InvalidMagic);
+        }
+
+        let header = |i: usize| read_u32(bytes, 4 * i).ok_or(EmbeddedError::Truncated);
+        let version: u32 = header(1)?;
+        if version != VERSION {
+            return Err(EmbeddedError::UnsupportedVersion(version));
+        }
+
+        let (nodes, values): (usize, usize) = (header(2)? as usize, header(3)? as usize);
+        if bytes.len() < HEADER_LEN + nodes * NODE_LEN + values * VALUE_LEN {
+            return Err(EmbeddedError::Truncated);
+        }
+
+        Ok(EmbeddedTrie {
+            bytes,
+            nodes,
+            values,
+        })
+    }
+
+    fn node(&self, node: u32, field: usize) -> u32 {
+        read_u32(
+            self.bytes,
+            HEADER_LEN + node as usize * NODE_LEN + 4 * field,
+        )
+        .unwrap_or(0)
+    }
+
+    fn value(&self, value: u32) -> Option<&'a str> {
+        let entry: usize = HEADER_LEN + self.nodes * NODE_LEN + value as usize * VALUE_LEN;
+        let strings: usize = HEADER_LEN + self.nodes * NODE_LEN + self.values * VALUE_LEN;
+        let offset: usize = strings + read_u32(self.bytes, entry)? as usize;
+        let len: usize = read_u32(self.bytes, entry + 4)? as usize;
+        std::str::from_utf8(self.bytes.get(offset..offset + len)?).ok()
+    }
+
+    /// Get every value associated with the provided ip address, from the least to the
+    /// most specific block, like [`Trie::get`].
+    pub fn get<K: IntoIpKey>(&self, ip: K) -> Vec<&'a str> {
+        let mut buffer: Vec<&'a str> = Vec::new();
+        let Some(ip) = ip.into_ip_key() else {
+            return buffer;
+        };
+        if self.nodes == 0 {
+            return buffer;
+        }
+
+        let mut node: u32 = 0;
+        for depth in 0..=32 {
+            let first: u32 = self.node(node, 2);
+            for value in first..first.saturating_add(self.node(node, 3)) {
+                buffer.extend(self.value(value));
+            }
+            if depth == 32 {
+                break;
+            }
+
+            let bit: usize = ((ip >> (31 - depth)) & 1) as usize;
+            node = self.node(node, bit);
+            if node == 0 || node as usize >= self.nodes {
+                break;
+            }
+        }
+        buffer
+    }
+
+    /// Get whether or not any block contains the provided ip address.
+    pub fn contains_ip<K: IntoIpKey>(&self, ip: K) -> bool {
+        !self.get(ip).is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn lookup_in_place() {
+        let mut t: Trie<String> = Trie::empty();
+        t.insert_cidr("0.0.0.0/8", "this-network".to_string());
+        t.insert_cidr("10.0.0.0/8", "private".to_string());
+        t.insert_cidr("10.1.0.0/16", "lab".to_string());
+        t.insert_cidr("10.1.0.0/16", "staging".to_string());
+        t.insert_cidr("100.64.0.0/10", "shared".to_string());
+        t.insert_cidr("192.168.1.1/32", "router".to_string());
+        t.insert_cidr("255.255.255.255/32", "broadcast".to_string());
+
+        let bytes: Vec<u8> = encode(&t);
+        let embedded: EmbeddedTrie = EmbeddedTrie::new(&bytes).unwrap();
+        for ip in [
+            Ipv4Addr::new(0, 1, 2, 3),
+            Ipv4Addr::new(10, 1, 2, 3),
+            Ipv4Addr::new(10, 2, 0, 0),
+            Ipv4Addr::new(100, 100, 0, 1),
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+            Ipv4Addr::new(255, 255, 255, 255),
+            Ipv4Addr::new(8, 8, 8, 8),
+        ] {
+            assert_eq!(t.get(ip), embedded.get(ip), "{}", ip);
+        }
+        assert!(!embedded.contains_ip(Ipv4Addr::new(8, 8, 8, 8)));
+
+        assert_eq!(
+            Vec::<&str>::new(),
+            EmbeddedTrie::new(&encode(&Trie::<String>::empty()))
+                .unwrap()
+                .get(0u32)
+        );
+        assert_eq!(
+            Err(EmbeddedError::InvalidMagic),
+            EmbeddedTrie::new(b"MMRT").map(|_| ())
+        );
+        assert_eq!(
+            Err(EmbeddedError::Truncated),
+            EmbeddedTrie::new(&bytes[..40]).map(|_| ())
+        );
+    }
+}
@@ -0,0 +1,401 @@
+//! Enrichment of files of ip addresses with the values of the blocks they match.
+//!
+//! Csv input is streamed in chunks of lines, the lines of a chunk are looked up in
+//! parallel and written in their original order. Newline delimited JSON input is
+//! enriched line by line and flushed after every line, so that it can be used as a
+//! filter stage of a log pipeline.
+//!
+//! Every JSON output describes the matches of an ip with the same record, holding one
+//! entry per value from the least to the most specific block:
+//!
+//! ```text
+//! {"ip":"10.1.2.3","matches":[{"cidr":"10.0.0.0/8","prefix_len":8,"value":"..."}]}
+//! ```
+
+use crate::json::{self, JsonValue};
+use crate::radix_trie::{CidrBlock, Trie};
+
+use rayon::prelude::*;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+/// Number of lines looked up in parallel at a time.
+const CHUNK_LINES: usize = 8192;
+
+/// Separator of the values of a block within the appended value column.
+const VALUE_SEPARATOR: &str = "|";
+
+#[derive(Debug)]
+pub enum EnrichError {
+    /// Reading the input or writing the output failed.
+    Io(io::Error),
+    /// The row has no column at the ip column index.
+    MissingColumn { line: usize, column: usize },
+}
+
+impl fmt::Display for EnrichError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnrichError::Io(e) => write!(f, "failed to enrich: {}", e),
+            EnrichError::MissingColumn { line, column } => {
+                write!(f, "line {}: missing column {}", line, column)
+            }
+        }
+    }
+}
+
+impl Error for EnrichError {}
+
+impl From<io::Error> for EnrichError {
+    fn from(e: io::Error) -> Self {
+        EnrichError::Io(e)
+    }
+}
+
+/// Outcome of an enrichment.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct EnrichSummary {
+    /// Number of rows read, not counting a header.
+    pub rows: usize,
+    /// Number of rows whose ip matched a block.
+    pub matched: usize,
+    /// Number of rows whose ip column does not hold an ipv4 address, or that are not
+    /// JSON objects.
+    pub invalid: usize,
+}
+
+/// Options of [`enrich_csv`].
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    /// Index of the column holding the ip address, starting at 0.
+    pub ip_column: usize,
+    /// Whether or not the first line is a header, extended with the new column names.
+    pub header: bool,
+}
+
+/// Options of [`enrich_ndjson`].
+#[derive(Clone, Debug)]
+pub struct NdjsonOptions {
+    /// Name of the member holding the ip address.
+    pub ip_field: String,
+    /// Name of the member added to every event.
+    pub field: String,
+}
+
+/// Split a csv row into its fields, honouring double quoted fields.
+fn split_csv(row: &str) -> Vec<&str> {
+    let mut fields: Vec<&str> = Vec::new();
+    let mut start: usize = 0;
+    let mut quoted: bool = false;
+    for (i, c) in row.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                fields.push(&row[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(&row[start..]);
+    fields
+}
+
+/// Quote the field if it holds a separator, quote or line break.
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Parse the ip address of a field, with surrounding quotes and whitespace removed.
+pub fn parse_ip(field: &str) -> Option<Ipv4Addr> {
+    Ipv4Addr::from_str(field.trim().trim_matches('"').trim()).ok()
+}
+
+/// Get the most specific block containing the ip address and its values, if any.
+pub fn longest_match<V>(trie: &Trie<V>, ip: Ipv4Addr) -> Option<(CidrBlock, &[V])> {
+    trie.get_blocks(ip).pop()
+}
+
+/// Get the JSON record of the matches of the ip address, see the module documentation.
+pub fn match_record<V: fmt::Display>(trie: &Trie<V>, ip: Ipv4Addr) -> JsonValue {
+    let matches: Vec<JsonValue> = trie
+        .get_blocks(ip)
+        .into_iter()
+        .flat_map(|(cidr, values)| values.iter().map(move |v| (cidr, v)))
+        .map(|(cidr, v)| {
+            JsonValue::Object(BTreeMap::from([
+                ("cidr".to_string(), JsonValue::String(cidr.to_string())),
+                (
+                    "prefix_len".to_string(),
+                    JsonValue::Number(cidr.prefix as f64),
+                ),
+                ("value".to_string(), JsonValue::String(v.to_string())),
+            ]))
+        })
+        .collect();
+
+    JsonValue::Object(BTreeMap::from([
+        ("ip".to_string(), JsonValue::String(ip.to_string())),
+        ("matches".to_string(), JsonValue::Array(matches)),
+    ]))
+}
+
+/// Enrich every row of the csv input with two columns holding the most specific block
+/// matching the ip of the row and the values of that block, separated by `|`. Rows
+/// without a match or without a valid ip get empty columns.
+pub fn enrich_csv<V, R, W>(
+    trie: &Trie<V>,
+    reader: R,
+    writer: &mut W,
+    options: &CsvOptions,
+) -> Result<EnrichSummary, EnrichError>
+where
+    V: fmt::Display + Sync,
+    R: BufRead,
+    W: Write,
+{
+    let mut summary = EnrichSummary::default();
+    let mut lines = reader.lines().enumerate().peekable();
+
+    if options.header
+        && let Some((_, line)) = lines.next()
+    {
+        writeln!(writer, "{},cidr,value", line?)?;
+    }
+
+    let mut chunk: Vec<(usize, String)> = Vec::with_capacity(CHUNK_LINES);
+    while lines.peek().is_some() {
+        chunk.clear();
+        for (i, line) in lines.by_ref().take(CHUNK_LINES) {
+            chunk.push((i + 1, line?));
+        }
+
+        let rows: Vec<Result<(String, Option<bool>), EnrichError>> = chunk
+            .par_iter()
+            .map(|(line, row)| {
+                let fields: Vec<&str> = split_csv(row);
+                let field: &str =
+                    fields
+                        .get(options.ip_column)
+                        .ok_or(EnrichError::MissingColumn {
+                            line: *line,
+                            column: options.ip_column,
+                        })?;
+
+                let Some(ip) = parse_ip(field) else {
+                    return Ok((format!("{},,", row), None));
+                };
+                Ok(match longest_match(trie, ip) {
+                    Some((cidr, values)) => {
+                        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                        let values: String = escape_csv(&values.join(VALUE_SEPARATOR));
+                        (format!("{},{},{}", row, cidr, values), Some(true))
+                    }
+                    None => (format!("{},,", row), Some(false)),
+                })
+            })
+            .collect();
+
+        for row in rows {
+            let (row, matched) = row?;
+            summary.rows += 1;
+            match matched {
+                Some(true) => summary.matched += 1,
+                Some(false) => {}
+                None => summary.invalid += 1,
+            }
+            writeln!(writer, "{}", row)?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(summary)
+}
+
+/// Enrich every JSON object of the newline delimited input with a member holding the
+/// match record of the ip of the event, see the module documentation. Lines that are
+/// not JSON objects or hold no valid ip are written unchanged.
+///
+/// The member is appended to the original text of the event, so every other member
+/// is passed through byte for byte. A member of the same name already in the event
+/// is kept, and precedes the new one.
+pub fn enrich_ndjson<V, R, W>(
+    trie: &Trie<V>,
+    reader: R,
+    writer: &mut W,
+    options: &NdjsonOptions,
+) -> Result<EnrichSummary, EnrichError>
+where
+    V: fmt::Display,
+    R: BufRead,
+    W: Write,
+{
+    let mut summary = EnrichSummary::default();
+    for line in reader.lines() {
+        let line: String = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        summary.rows += 1;
+
+        // The event is only parsed to find its ip, an object with a member has at
+        // least one member before its closing brace.
+        let ip: Option<Ipv4Addr> = match json::parse(&line) {
+            Ok(event @ JsonValue::Object(_)) => event
+                .get(&options.ip_field)
+                .and_then(JsonValue::as_str)
+                .and_then(parse_ip),
+            _ => None,
+        };
+
+        match ip {
+            Some(ip) => {
+                if trie.contains_ip(ip) {
+                    summary.matched += 1;
+                }
+                let event: &str = line.trim_end().strip_suffix('}').unwrap().trim_end();
+                let field = JsonValue::String(options.field.clone());
+                writeln!(writer, "{},{}:{}}}", event, field, match_record(trie, ip))?;
+            }
+            None => {
+                summary.invalid += 1;
+                writeln!(writer, "{}", line)?;
+            }
+        }
+        writer.flush()?;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enrich_csv_rows() {
+        let mut trie: Trie<String> = Trie::empty();
+        trie.insert_cidr("10.0.0.0/8", "private".to_string());
+        trie.insert_cidr("81.2.69.0/24", "GB".to_string());
+        trie.insert_cidr("81.2.69.0/24", "AS20712, Andrews".to_string());
+
+        let input = "time,user,ip\n\
+                     1,\"doe, jane\",81.2.69.142\n\
+                     2,bob,\"10.1.2.3\"\n\
+                     3,eve,192.168.0.1\n\
+                     4,mallory,not-an-ip\n";
+        let options = CsvOptions {
+            ip_column: 2,
+            header: true,
+        };
+
+        let mut output: Vec<u8> = Vec::new();
+        let summary = enrich_csv(&trie, input.as_bytes(), &mut output, &options).unwrap();
+        assert_eq!(
+            EnrichSummary {
+                rows: 4,
+                matched: 2,
+                invalid: 1,
+            },
+            summary
+        );
+        assert_eq!(
+            "time,user,ip,cidr,value\n\
+             1,\"doe, jane\",81.2.69.142,81.2.69.0/24,\"GB|AS20712, Andrews\"\n\
+             2,bob,\"10.1.2.3\",10.0.0.0/8,private\n\
+             3,eve,192.168.0.1,,\n\
+             4,mallory,not-an-ip,,\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        let options = CsvOptions {
+            ip_column: 5,
+            header: false,
+        };
+        assert!(matches!(
+            enrich_csv(&trie, input.as_bytes(), &mut Vec::new(), &options),
+            Err(EnrichError::MissingColumn { line: 1, column: 5 })
+        ));
+    }
+
+    #[test]
+    fn match_records() {
+        let mut trie: Trie<u32> = Trie::empty();
+        trie.insert_cidr("10.0.0.0/8", 1);
+        trie.insert_cidr("10.1.0.0/16", 2);
+        trie.insert_cidr("10.1.0.0/16", 3);
+
+        assert_eq!(
+            concat!(
+                r#"{"ip":"10.1.2.3","matches":["#,
+                r#"{"cidr":"10.0.0.0/8","prefix_len":8,"value":"1"},"#,
+                r#"{"cidr":"10.1.0.0/16","prefix_len":16,"value":"2"},"#,
+                r#"{"cidr":"10.1.0.0/16","prefix_len":16,"value":"3"}]}"#,
+            ),
+            match_record(&trie, Ipv4Addr::new(10, 1, 2, 3)).to_string()
+        );
+        assert_eq!(
+            r#"{"ip":"8.8.8.8","matches":[]}"#,
+            match_record(&trie, Ipv4Addr::new(8, 8, 8, 8)).to_string()
+        );
+    }
+
+    #[test]
+    fn enrich_ndjson_events() {
+        let mut trie: Trie<String> = Trie::empty();
+        trie.insert_cidr("81.2.69.0/24", "GB".to_string());
+
+        let input = r#"{"src_ip":"81.2.69.142","n":1}
+{ "z": 9007199254740993, "a": 1.50, "z": null, "src_ip": "10.0.0.1" }
+
+{"src_ip":"192.168.0.1"}
+{"src_ip":"nope"}
+not json
+"#;
+        let options = NdjsonOptions {
+            ip_field: "src_ip".to_string(),
+            field: "geo".to_string(),
+        };
+
+        let mut output: Vec<u8> = Vec::new();
+        let summary = enrich_ndjson(&trie, input.as_bytes(), &mut output, &options).unwrap();
+        assert_eq!(
+            EnrichSummary {
+                rows: 5,
+                matched: 1,
+                invalid: 2,
+            },
+            summary
+        );
+        assert_eq!(
+            concat!(
+                r#"{"src_ip":"81.2.69.142","n":1,"geo":{"ip":"81.2.69.142","matches":[{"cidr":"81.2.69.0/24","prefix_len":24,"value":"GB"}]}}"#,
+                "\n",
+                r#"{ "z": 9007199254740993, "a": 1.50, "z": null, "src_ip": "10.0.0.1","geo":{"ip":"10.0.0.1","matches":[]}}"#,
+                "\n",
+                r#"{"src_ip":"192.168.0.1","geo":{"ip":"192.168.0.1","matches":[]}}"#,
+                "\n",
+                r#"{"src_ip":"nope"}"#,
+                "\n",
+                "not json\n",
+            ),
+            String::from_utf8(output).unwrap()
+        );
+
+        // Lines nested too deeply to parse are passed through as invalid.
+        let deep: String = "[".repeat(200_000) + "\n";
+        let mut output: Vec<u8> = Vec::new();
+        let summary = enrich_ndjson(&trie, deep.as_bytes(), &mut output, &options).unwrap();
+        assert_eq!(1, summary.invalid);
+        assert_eq!(deep.as_bytes(), output);
+    }
+}
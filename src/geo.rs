@@ -0,0 +1,138 @@
+//! Geolocation lookups on a trie of MaxMind GeoLite2 city records.
+
+use crate::mmdb::{self, CityRecord};
+use crate::radix_trie::{CidrBlock, IntoIpKey, Trie, TrieError};
+
+use bincode::{Decode, Encode};
+use maxminddb::{MaxMindDBError, Reader};
+
+use std::path::Path;
+use std::str::FromStr;
+
+/// Location a network is assigned to.
+#[derive(Clone, Copy, Debug, Decode, Encode, Eq, Hash, PartialEq)]
+pub struct GeoRecord {
+    /// GeoNames identifier of the location.
+    pub geoname_id: u32,
+    /// Whether or not the record is from the most recent database release, records
+    /// of older releases are kept to answer lookups for networks that were dropped.
+    pub is_latest: bool,
+}
+
+impl GeoRecord {
+    /// Get the record of the city, located by the city or else by its country, or
+    /// `None` if the city has neither a GeoNames identifier nor a country with one.
+    pub fn from_city(city: &CityRecord) -> Option<Self> {
+        Some(GeoRecord {
+            geoname_id: city.geoname_id.or(city.country.geoname_id)?,
+            is_latest: true,
+        })
+    }
+}
+
+/// A trie of geolocation records.
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct GeoTrie {
+    trie: Trie<GeoRecord>,
+}
+
+impl GeoTrie {
+    /// Create a new empty trie.
+    pub fn empty() -> Self {
+        GeoTrie {
+            trie: Trie::empty(),
+        }
+    }
+
+    /// Build a trie of every ipv4 network of the MaxMind City or Country database at
+    /// the path, leaving out the networks without a location, see
+    /// [`GeoRecord::from_city`].
+    pub fn from_mmdb<P: AsRef<Path>>(path: P) -> Result<Self, MaxMindDBError> {
+        GeoTrie::from_mmdb_reader(&Reader::open_readfile(path)?)
+    }
+
+    /// Like [`GeoTrie::from_mmdb`], on an opened database.
+    pub fn from_mmdb_reader<S: AsRef<[u8]>>(reader: &Reader<S>) -> Result<Self, MaxMindDBError> {
+        let mut geo: GeoTrie = GeoTrie::empty();
+        for network in mmdb::networks::<CityRecord, S>(reader)? {
+            let (cidr, city) = network?;
+            if let Some(record) = GeoRecord::from_city(&city) {
+                geo.insert(&cidr, record).unwrap();
+            }
+        }
+        Ok(geo)
+    }
+
+    pub fn trie(&self) -> &Trie<GeoRecord> {
+        &self.trie
+    }
+
+    /// Insert the record at the cidr block.
+    pub fn insert(&mut self, cidr: &CidrBlock, record: GeoRecord) -> Result<(), TrieError> {
+        self.trie.try_insert(cidr, record)
+    }
+
+    /// Insert the record at the cidr block given as `a.b.c.d/len`.
+    pub fn insert_cidr(&mut self, cidr: &str, record: GeoRecord) {
+        let cidr: CidrBlock = CidrBlock::from_str(cidr).unwrap();
+        self.insert(&cidr, record).unwrap();
+    }
+
+    /// Get every record of the networks containing the provided ip address, from the
+    /// least to the most specific network.
+    pub fn lookup<K: IntoIpKey>(&self, ip: K) -> Vec<&GeoRecord> {
+        self.trie.get(ip)
+    }
+
+    /// Get the most specific record from the most recent database release of the
+    /// networks containing the provided ip address, if any.
+    pub fn lookup_latest<K: IntoIpKey>(&self, ip: K) -> Option<&GeoRecord> {
+        self.lookup(ip).into_iter().rfind(|record| record.is_latest)
+    }
+}
+
+impl From<Trie<GeoRecord>> for GeoTrie {
+    fn from(trie: Trie<GeoRecord>) -> Self {
+        GeoTrie { trie }
+    }
+}
+
+impl Default for GeoTrie {
+    fn default() -> Self {
+        GeoTrie::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn lookup_records() {
+        let stale = GeoRecord {
+            geoname_id: 2643743,
+            is_latest: false,
+        };
+        let country = GeoRecord {
+            geoname_id: 2635167,
+            is_latest: true,
+        };
+        let city = GeoRecord {
+            geoname_id: 2655045,
+            is_latest: true,
+        };
+
+        let mut t: GeoTrie = GeoTrie::empty();
+        t.insert_cidr("81.0.0.0/8", country);
+        t.insert_cidr("81.2.69.0/24", city);
+        t.insert_cidr("81.2.69.128/25", stale);
+
+        let ip = Ipv4Addr::new(81, 2, 69, 142);
+        assert_eq!(vec![&country, &city, &stale], t.lookup(ip));
+        assert_eq!(Some(&city), t.lookup_latest(ip));
+        assert_eq!(Some(&country), t.lookup_latest(Ipv4Addr::new(81, 9, 0, 1)));
+        assert_eq!(None, t.lookup_latest(Ipv4Addr::new(10, 0, 0, 1)));
+    }
+}
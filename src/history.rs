@@ -0,0 +1,144 @@
+//! Trie keeping the full history of values inserted at every cidr block.
+
+use crate::radix_trie::{CidrBlock, IntoIpKey, Trie, TrieError};
+
+use bincode::{Decode, Encode};
+
+/// A value together with when it was inserted.
+#[derive(Clone, Debug, Decode, Encode, Eq, Hash, PartialEq)]
+pub struct Version<V> {
+    pub value: V,
+    /// Unix timestamp, in milliseconds, of the insert.
+    pub timestamp: i64,
+    /// Sequence number of the insert, unique and increasing within the trie.
+    pub seq: u64,
+}
+
+/// A trie where every insert at a cidr block appends a new version of the value of
+/// that block rather than adding a value next to the existing ones.
+///
+/// Lookups see only the current, i.e. latest, version of every block, while
+/// [`HistoryTrie::history`] and [`HistoryTrie::get_at`] give access to earlier ones.
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct HistoryTrie<V> {
+    trie: Trie<Version<V>>,
+    seq: u64,
+}
+
+impl<V> HistoryTrie<V> {
+    /// Create a new empty trie.
+    pub fn empty() -> Self {
+        HistoryTrie {
+            trie: Trie::empty(),
+            seq: 0,
+        }
+    }
+
+    /// Get the underlying trie holding every version.
+    pub fn versions(&self) -> &Trie<Version<V>> {
+        &self.trie
+    }
+
+    /// Insert a new version of the value of the cidr block, timestamped now.
+    pub fn insert(&mut self, cidr: &CidrBlock, value: V) -> Result<u64, TrieError> {
+        self.insert_at(cidr, value, chrono::Utc::now().timestamp_millis())
+    }
+
+    /// Insert a new version of the value of the cidr block with the provided timestamp,
+    /// returning its sequence number.
+    ///
+    /// Versions are ordered by sequence number, so a version inserted with an older
+    /// timestamp than the current one still becomes current.
+    pub fn insert_at(
+        &mut self,
+        cidr: &CidrBlock,
+        value: V,
+        timestamp: i64,
+    ) -> Result<u64, TrieError> {
+        let version = Version {
+            value,
+            timestamp,
+            seq: self.seq + 1,
+        };
+        self.trie.try_insert(cidr, version)?;
+        self.seq += 1;
+        Ok(self.seq)
+    }
+
+    /// Get the current values of every cidr block containing the ip address, from
+    /// the least to the most specific block.
+    pub fn get<K: IntoIpKey>(&self, ip: K) -> Vec<&V> {
+        self.trie
+            .get_blocks(ip)
+            .into_iter()
+            .filter_map(|(_, versions)| versions.last())
+            .map(|version| &version.value)
+            .collect()
+    }
+
+    /// Get the values that were current at the timestamp for every cidr block
+    /// containing the ip address, from the least to the most specific block.
+    pub fn get_at<K: IntoIpKey>(&self, ip: K, timestamp: i64) -> Vec<&V> {
+        self.trie
+            .get_blocks(ip)
+            .into_iter()
+            .filter_map(|(_, versions)| {
+                versions
+                    .iter()
+                    .filter(|version| version.timestamp <= timestamp)
+                    .max_by_key(|version| version.seq)
+            })
+            .map(|version| &version.value)
+            .collect()
+    }
+
+    /// Get every version of the value of exactly the cidr block, oldest first.
+    pub fn history(&self, cidr: &CidrBlock) -> &[Version<V>] {
+        self.trie.get_exact(cidr)
+    }
+}
+
+impl<V> Default for HistoryTrie<V> {
+    fn default() -> Self {
+        HistoryTrie::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    #[test]
+    fn history_and_current_values() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut t: HistoryTrie<&str> = HistoryTrie::empty();
+        assert_eq!(Ok(1), t.insert_at(&cidr("10.0.0.0/8"), "corp", 100));
+        assert_eq!(Ok(2), t.insert_at(&cidr("10.1.0.0/16"), "clean", 100));
+        assert_eq!(Ok(3), t.insert_at(&cidr("10.1.0.0/16"), "botnet", 200));
+        assert_eq!(Ok(4), t.insert_at(&cidr("10.1.0.0/16"), "clean", 300));
+
+        let ip = Ipv4Addr::new(10, 1, 2, 3);
+        assert_eq!(vec![&"corp", &"clean"], t.get(ip));
+        assert_eq!(vec![&"corp", &"botnet"], t.get_at(ip, 250));
+        assert_eq!(vec![&"corp", &"clean"], t.get_at(ip, 199));
+        assert!(t.get_at(ip, 99).is_empty());
+
+        let history: Vec<(&str, i64, u64)> = t
+            .history(&cidr("10.1.0.0/16"))
+            .iter()
+            .map(|v| (v.value, v.timestamp, v.seq))
+            .collect();
+        assert_eq!(
+            vec![("clean", 100, 2), ("botnet", 200, 3), ("clean", 300, 4)],
+            history
+        );
+        assert!(t.history(&cidr("10.1.0.0/24")).is_empty());
+
+        let long = CidrBlock { net: 0, prefix: 33 };
+        assert_eq!(Err(TrieError::InvalidPrefix(33)), t.insert(&long, "x"));
+        assert_eq!(Ok(5), t.insert_at(&cidr("10.0.0.0/8"), "corp", 400));
+    }
+}
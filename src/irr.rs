@@ -0,0 +1,270 @@
+//! Importer for route objects from IRR databases (RADB, RIPE, ...) in RPSL format.
+
+use crate::radix_trie::{CidrBlock, Trie};
+
+use bincode::{Decode, Encode};
+
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+/// A registered route, i.e. an RPSL `route` object.
+#[derive(Clone, Debug, Decode, Encode, Eq, Hash, PartialEq)]
+pub struct RouteObject {
+    pub prefix: CidrBlock,
+    pub origin: u32,
+    pub source: String,
+}
+
+/// Result of validating an announcement against the registered routes.
+#[derive(Debug, Eq, PartialEq)]
+pub enum RouteValidation {
+    /// A registered route covering the announced prefix has the announced origin.
+    Valid,
+    /// Registered routes cover the announced prefix, but none with the announced origin.
+    InvalidOrigin,
+    /// No registered route covers the announced prefix.
+    NotFound,
+}
+
+/// A route object that could not be imported.
+#[derive(Debug, Eq, PartialEq)]
+pub struct IrrError {
+    /// Line on which the object starts.
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for IrrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "object at line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for IrrError {}
+
+/// Outcome of importing an IRR dump.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct ImportSummary {
+    /// Number of route objects inserted into the trie.
+    pub imported: usize,
+    /// Number of route6 objects skipped, since the trie only holds IPv4 prefixes.
+    pub skipped_ipv6: usize,
+    /// Route objects that could not be parsed.
+    pub errors: Vec<IrrError>,
+}
+
+/// Build a trie of every route object in the RPSL dump, skipping objects of other classes.
+pub fn load<R: BufRead>(reader: R) -> io::Result<(Trie<RouteObject>, ImportSummary)> {
+    let mut trie: Trie<RouteObject> = Trie::empty();
+    let mut summary = ImportSummary::default();
+
+    for object in Objects::new(reader) {
+        let (line, attributes) = object?;
+        match attributes.first().map(|(key, _)| key.as_str()) {
+            Some("route") => match route_object(&attributes) {
+                Ok(route) => {
+                    let prefix: CidrBlock = route.prefix;
+                    trie.try_insert(&prefix, route).unwrap();
+                    summary.imported += 1;
+                }
+                Err(reason) => summary.errors.push(IrrError { line, reason }),
+            },
+            Some("route6") => summary.skipped_ipv6 += 1,
+            _ => {}
+        }
+    }
+
+    Ok((trie, summary))
+}
+
+/// Validate that the announced prefix and origin are covered by a registered route.
+pub fn validate_origin(
+    routes: &Trie<RouteObject>,
+    announced: &CidrBlock,
+    origin: u32,
+) -> RouteValidation {
+    let mut covering = routes
+        .get(announced.network())
+        .into_iter()
+        .filter(|route| route.prefix.prefix <= announced.prefix)
+        .peekable();
+
+    if covering.peek().is_none() {
+        RouteValidation::NotFound
+    } else if covering.any(|route| route.origin == origin) {
+        RouteValidation::Valid
+    } else {
+        RouteValidation::InvalidOrigin
+    }
+}
+
+/// Parse an `AS<number>` autonomous system number.
+pub fn parse_asn(s: &str) -> Option<u32> {
+    let s: &str = s.trim();
+    let digits: &str = s.strip_prefix("AS").or_else(|| s.strip_prefix("as"))?;
+    digits.parse().ok()
+}
+
+fn route_object(attributes: &[(String, String)]) -> Result<RouteObject, String> {
+    let attribute = |name: &str| {
+        attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .ok_or_else(|| format!("missing '{}' attribute", name))
+    };
+
+    let prefix: CidrBlock = CidrBlock::from_str(attribute("route")?).map_err(|e| e.to_string())?;
+    let origin: &str = attribute("origin")?;
+    let origin: u32 = parse_asn(origin).ok_or_else(|| format!("invalid origin '{}'", origin))?;
+    let source: String = attribute("source")?.to_uppercase();
+
+    Ok(RouteObject {
+        prefix,
+        origin,
+        source,
+    })
+}
+
+/// Iterator over the RPSL objects of a dump, yielding the line each object starts
+/// on and its attributes with lowercased names and continuation lines joined.
+struct Objects<R> {
+    lines: io::Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> Objects<R> {
+    fn new(reader: R) -> Self {
+        Objects {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Objects<R> {
+    type Item = io::Result<(usize, Vec<(String, String)>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut start: usize = 0;
+        let mut attributes: Vec<(String, String)> = Vec::new();
+
+        for line in self.lines.by_ref() {
+            self.line += 1;
+            let line: String = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if line.starts_with('%') || line.starts_with('#') {
+                continue;
+            }
+
+            let content: &str = line.split('#').next().unwrap().trim_end();
+            if content.trim().is_empty() {
+                if !attributes.is_empty() {
+                    return Some(Ok((start, attributes)));
+                }
+                continue;
+            }
+
+            if content.starts_with([' ', '\t', '+']) {
+                if let Some((_, value)) = attributes.last_mut() {
+                    let continued: &str = content[1..].trim();
+                    if !continued.is_empty() {
+                        value.push(' ');
+                        value.push_str(continued);
+                    }
+                }
+                continue;
+            }
+
+            if let Some((key, value)) = content.split_once(':') {
+                if attributes.is_empty() {
+                    start = self.line;
+                }
+                attributes.push((key.trim().to_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        (!attributes.is_empty()).then_some(Ok((start, attributes)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    const DUMP: &str = "\
+% RADb dump
+
+route:          1.1.1.0/24
+descr:          APNIC and Cloudflare DNS Resolver project
+                Routed globally by AS13335
+origin:         AS13335
+mnt-by:         MAINT-APNIC-AP
+source:         radb
+
+route6:         2606:4700::/32
+origin:         AS13335
+source:         RADB
+
+aut-num:        AS13335
+as-name:        CLOUDFLARENET
+source:         RADB
+
+route:          1.0.0.0/8
+origin:         AS4608 # APNIC
+source:         APNIC
+
+route:          10.0.0.0/8
+source:         RADB
+";
+
+    #[test]
+    fn load_dump() {
+        let (trie, summary) = load(DUMP.as_bytes()).unwrap();
+        assert_eq!(2, summary.imported);
+        assert_eq!(1, summary.skipped_ipv6);
+        assert_eq!(
+            vec![IrrError {
+                line: 22,
+                reason: "missing 'origin' attribute".to_string()
+            }],
+            summary.errors
+        );
+
+        let routes = trie.get(Ipv4Addr::new(1, 1, 1, 1));
+        assert_eq!(2, routes.len());
+        assert_eq!(4608, routes[0].origin);
+        assert_eq!("APNIC", routes[0].source);
+        assert_eq!(13335, routes[1].origin);
+        assert_eq!("RADB", routes[1].source);
+    }
+
+    #[test]
+    fn validate_announcements() {
+        let (trie, _) = load(DUMP.as_bytes()).unwrap();
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+
+        assert_eq!(
+            RouteValidation::Valid,
+            validate_origin(&trie, &cidr("1.1.1.0/24"), 13335)
+        );
+        assert_eq!(
+            RouteValidation::Valid,
+            validate_origin(&trie, &cidr("1.2.0.0/16"), 4608)
+        );
+        assert_eq!(
+            RouteValidation::InvalidOrigin,
+            validate_origin(&trie, &cidr("1.2.0.0/16"), 13335)
+        );
+        assert_eq!(
+            RouteValidation::NotFound,
+            validate_origin(&trie, &cidr("8.8.8.0/24"), 15169)
+        );
+    }
+}
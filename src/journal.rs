@@ -0,0 +1,216 @@
+//! Trie with a journal of recent mutations that can be rolled back, e.g. after
+//! loading the wrong feed into a live table.
+
+use crate::radix_trie::{CidrBlock, Trie, TrieError};
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// How to undo a journaled mutation.
+#[derive(Clone, Debug)]
+enum Undo<V> {
+    /// A value was appended to the block.
+    Insert(CidrBlock),
+    /// Every value of the block was removed.
+    Remove(CidrBlock, Vec<V>),
+    /// The value at the index of the block was removed.
+    RemoveValue(CidrBlock, usize, V),
+}
+
+/// A trie recording the last mutations, up to a capacity, so that they can be
+/// reversed with [`JournaledTrie::rollback`] or [`JournaledTrie::rollback_to`].
+#[derive(Clone, Debug)]
+pub struct JournaledTrie<V> {
+    trie: Trie<V>,
+    journal: VecDeque<(u64, Undo<V>)>,
+    capacity: usize,
+    seq: u64,
+}
+
+impl<V> JournaledTrie<V> {
+    /// Wrap the trie, journaling up to `capacity` of the following mutations.
+    pub fn new(trie: Trie<V>, capacity: usize) -> Self {
+        JournaledTrie {
+            trie,
+            journal: VecDeque::with_capacity(capacity),
+            capacity,
+            seq: 0,
+        }
+    }
+
+    pub fn trie(&self) -> &Trie<V> {
+        &self.trie
+    }
+
+    /// Unwrap the trie, dropping the journal.
+    pub fn into_inner(self) -> Trie<V> {
+        self.trie
+    }
+
+    /// Get the sequence number of the last mutation, 0 if nothing was mutated yet.
+    /// The sequence numbers of rolled back mutations are handed out again.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Get the number of mutations that can currently be rolled back.
+    pub fn journaled(&self) -> usize {
+        self.journal.len()
+    }
+
+    fn record(&mut self, undo: Undo<V>) -> u64 {
+        self.seq += 1;
+        if self.capacity > 0 {
+            if self.journal.len() == self.capacity {
+                self.journal.pop_front();
+            }
+            self.journal.push_back((self.seq, undo));
+        }
+        self.seq
+    }
+
+    /// Insert the value at the cidr block, returning the sequence number of the insert.
+    pub fn insert(&mut self, cidr: &CidrBlock, value: V) -> Result<u64, TrieError> {
+        self.trie.try_insert(cidr, value)?;
+        Ok(self.record(Undo::Insert(*cidr)))
+    }
+
+    /// Remove every value stored at exactly the cidr block, returning the sequence
+    /// number of the removal, or `None` if the block held no values.
+    pub fn remove(&mut self, cidr: &CidrBlock) -> Option<u64> {
+        let values: Vec<V> = self.trie.remove(cidr);
+        (!values.is_empty()).then(|| self.record(Undo::Remove(*cidr, values)))
+    }
+
+    /// Undo the last `n` journaled mutations, newest first, returning the number of
+    /// mutations undone. Fewer are undone if fewer are journaled.
+    pub fn rollback(&mut self, n: usize) -> usize {
+        let mut undone: usize = 0;
+        while undone < n {
+            let Some((seq, undo)) = self.journal.pop_back() else {
+                break;
+            };
+            self.undo(undo);
+            self.seq = seq - 1;
+            undone += 1;
+        }
+        undone
+    }
+
+    /// Undo every mutation after the one with the sequence number, returning the
+    /// number of mutations undone. Nothing is undone if some of those mutations are
+    /// no longer journaled.
+    pub fn rollback_to(&mut self, seq: u64) -> Result<usize, JournalError> {
+        let oldest: u64 = self.journal.front().map_or(self.seq + 1, |(seq, _)| *seq);
+        if seq < self.seq && seq + 1 < oldest {
+            return Err(JournalError::NotJournaled {
+                seq,
+                oldest: oldest - 1,
+            });
+        }
+
+        let n: usize = self.journal.iter().filter(|(s, _)| *s > seq).count();
+        Ok(self.rollback(n))
+    }
+
+    fn undo(&mut self, undo: Undo<V>) {
+        let restored = match undo {
+            Undo::Insert(cidr) => self.trie.update_exact(&cidr, |values| {
+                values.pop();
+            }),
+            Undo::Remove(cidr, removed) => self.trie.update_exact(&cidr, |values| {
+                values.extend(removed);
+            }),
+            Undo::RemoveValue(cidr, i, value) => self
+                .trie
+                .update_exact(&cidr, |values| values.insert(i, value)),
+        };
+        // Journaled blocks were valid when the mutation was applied.
+        restored.unwrap();
+    }
+}
+
+impl<V: PartialEq> JournaledTrie<V> {
+    /// Remove the most recently inserted equal value from exactly the cidr block,
+    /// returning the sequence number of the removal, or `None` if there is no such value.
+    pub fn remove_value(&mut self, cidr: &CidrBlock, value: &V) -> Option<u64> {
+        let i: usize = self.trie.get_exact(cidr).iter().rposition(|v| v == value)?;
+        let removed: V = self
+            .trie
+            .update_exact(cidr, |values| values.remove(i))
+            .unwrap();
+        Some(self.record(Undo::RemoveValue(*cidr, i, removed)))
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum JournalError {
+    /// The mutations after `seq` can not be rolled back since only the ones after
+    /// `oldest` are still journaled.
+    NotJournaled { seq: u64, oldest: u64 },
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::NotJournaled { seq, oldest } => write!(
+                f,
+                "can not roll back to {}, only mutations after {} are journaled",
+                seq, oldest
+            ),
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    fn cidr(s: &str) -> CidrBlock {
+        CidrBlock::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn rollback_mutations() {
+        let mut base: Trie<u32> = Trie::empty();
+        base.insert_cidr("10.0.0.0/8", 1);
+        base.insert_cidr("10.0.0.0/8", 2);
+        base.insert_cidr("10.0.0.0/8", 3);
+
+        let mut t = JournaledTrie::new(base.clone(), 16);
+        assert_eq!(Some(1), t.remove_value(&cidr("10.0.0.0/8"), &2));
+        assert_eq!(Ok(2), t.insert(&cidr("10.1.0.0/16"), 4));
+        let checkpoint: u64 = t.seq();
+        assert_eq!(Some(3), t.remove(&cidr("10.0.0.0/8")));
+        assert_eq!(None, t.remove(&cidr("10.0.0.0/8")));
+        assert_eq!(Ok(4), t.insert(&cidr("10.2.0.0/16"), 5));
+
+        assert_eq!(Ok(2), t.rollback_to(checkpoint));
+        assert_eq!(checkpoint, t.seq());
+        assert_eq!(&[1, 3], t.trie().get_exact(&cidr("10.0.0.0/8")));
+
+        assert_eq!(2, t.rollback(5));
+        assert_eq!(&base, t.trie());
+        assert_eq!(0, t.journaled());
+    }
+
+    #[test]
+    fn rollback_beyond_journal() {
+        let mut t: JournaledTrie<u32> = JournaledTrie::new(Trie::empty(), 2);
+        for i in 0..4 {
+            t.insert(&cidr("10.0.0.0/8"), i).unwrap();
+        }
+
+        assert_eq!(
+            Err(JournalError::NotJournaled { seq: 1, oldest: 2 }),
+            t.rollback_to(1)
+        );
+        assert_eq!(Ok(0), t.rollback_to(4));
+        assert_eq!(Ok(2), t.rollback_to(2));
+        assert_eq!(&[0, 1], t.trie().get_exact(&cidr("10.0.0.0/8")));
+    }
+}
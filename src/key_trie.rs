@@ -0,0 +1,172 @@
+//! Longest prefix match on small fixed width keys other than ip addresses, e.g. port
+//! ranges or vlan tags, reusing the radix trie by left aligning the keys.
+
+use crate::radix_trie::{CidrBlock, Trie, TrieError};
+
+use std::marker::PhantomData;
+
+/// Keys of at most 32 bits that can be matched by prefix, most significant bit first.
+pub trait PrefixKey: Copy {
+    /// Number of significant bits of the key.
+    const BITS: u32;
+
+    /// Get the significant bits of the key, right aligned.
+    fn to_bits(self) -> u32;
+}
+
+impl PrefixKey for u8 {
+    const BITS: u32 = 8;
+
+    fn to_bits(self) -> u32 {
+        self as u32
+    }
+}
+
+impl PrefixKey for u16 {
+    const BITS: u32 = 16;
+
+    fn to_bits(self) -> u32 {
+        self as u32
+    }
+}
+
+/// A 12 bit IEEE 802.1Q vlan identifier.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct VlanId(u16);
+
+impl VlanId {
+    /// Create a vlan identifier, `None` if it does not fit in 12 bits.
+    pub fn new(id: u16) -> Option<Self> {
+        (id < 4096).then_some(VlanId(id))
+    }
+
+    pub fn get(&self) -> u16 {
+        self.0
+    }
+}
+
+impl PrefixKey for VlanId {
+    const BITS: u32 = 12;
+
+    fn to_bits(self) -> u32 {
+        self.0 as u32
+    }
+}
+
+/// A trie of values associated with prefixes of keys, with the same longest prefix
+/// semantics as [`Trie`] for ip addresses.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeyTrie<K, V> {
+    trie: Trie<V>,
+    key: PhantomData<K>,
+}
+
+/// A trie of values associated with prefixes of 16 bit ports.
+pub type PortTrie<V> = KeyTrie<u16, V>;
+
+/// A trie of values associated with prefixes of vlan identifiers.
+pub type VlanTrie<V> = KeyTrie<VlanId, V>;
+
+impl<K: PrefixKey, V> KeyTrie<K, V> {
+    /// Create a new empty trie.
+    pub fn empty() -> Self {
+        KeyTrie {
+            trie: Trie::empty(),
+            key: PhantomData,
+        }
+    }
+
+    fn align(key: K) -> u32 {
+        key.to_bits().checked_shl(32 - K::BITS).unwrap_or(0)
+    }
+
+    fn block(key: K, prefix: u32) -> Result<CidrBlock, TrieError> {
+        if prefix > K::BITS {
+            return Err(TrieError::InvalidPrefix(prefix));
+        }
+        Ok(CidrBlock {
+            net: Self::align(key),
+            prefix,
+        })
+    }
+
+    /// Insert the value at the first `prefix` bits of the key.
+    pub fn insert(&mut self, key: K, prefix: u32, value: V) -> Result<(), TrieError> {
+        self.trie.try_insert(&Self::block(key, prefix)?, value)
+    }
+
+    /// Get the values of every prefix of the key, from the least to the most specific.
+    pub fn get(&self, key: K) -> Vec<&V> {
+        self.trie.get(Self::align(key))
+    }
+
+    /// Get the values of the most specific prefix of the key, or an empty slice if
+    /// no prefix holds any values.
+    pub fn get_longest(&self, key: K) -> &[V] {
+        self.trie.get_longest(Self::align(key))
+    }
+
+    /// Get the values stored at exactly the first `prefix` bits of the key.
+    pub fn get_exact(&self, key: K, prefix: u32) -> &[V] {
+        match Self::block(key, prefix) {
+            Ok(cidr) => self.trie.get_exact(&cidr),
+            Err(_) => &[],
+        }
+    }
+
+    /// Get whether or not any prefix of the key holds a value.
+    pub fn contains(&self, key: K) -> bool {
+        self.trie.contains_ip(Self::align(key))
+    }
+
+    /// Remove and return every value stored at exactly the first `prefix` bits of the key.
+    pub fn remove(&mut self, key: K, prefix: u32) -> Vec<V> {
+        match Self::block(key, prefix) {
+            Ok(cidr) => self.trie.remove(&cidr),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl<K: PrefixKey, V> Default for KeyTrie<K, V> {
+    fn default() -> Self {
+        KeyTrie::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn port_and_vlan_prefixes() {
+        let mut ports: PortTrie<&str> = PortTrie::empty();
+        // 0-1023 are the well known ports, 1024-2047 a registered block.
+        ports.insert(0, 6, "well-known").unwrap();
+        ports.insert(1024, 6, "registered").unwrap();
+        ports.insert(443, 16, "https").unwrap();
+        assert_eq!(
+            Err(TrieError::InvalidPrefix(17)),
+            ports.insert(0, 17, "invalid")
+        );
+
+        assert_eq!(vec![&"well-known", &"https"], ports.get(443));
+        assert_eq!(&["https"], ports.get_longest(443));
+        assert_eq!(&["well-known"], ports.get_longest(1023));
+        assert_eq!(&["registered"], ports.get_longest(2047));
+        assert!(!ports.contains(2048));
+        assert_eq!(&["registered"], ports.get_exact(1500, 6));
+        assert_eq!(vec!["https"], ports.remove(443, 16));
+        assert_eq!(vec![&"well-known"], ports.get(443));
+
+        let vlan = |id: u16| VlanId::new(id).unwrap();
+        let mut vlans: VlanTrie<u32> = VlanTrie::empty();
+        vlans.insert(vlan(0x100), 4, 1).unwrap();
+        vlans.insert(vlan(0x120), 8, 2).unwrap();
+        assert_eq!(vec![&1, &2], vlans.get(vlan(0x12f)));
+        assert_eq!(vec![&1], vlans.get(vlan(0x1ff)));
+        assert!(!vlans.contains(vlan(0x200)));
+        assert_eq!(None, VlanId::new(4096));
+        assert!(vlans.insert(vlan(0), 13, 3).is_err());
+    }
+}
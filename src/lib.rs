@@ -1,2 +1,34 @@
+pub mod arena;
+pub mod asn;
+pub mod backend;
+pub mod batch;
+pub mod budget;
+pub mod builder;
+pub mod cloud;
+pub mod concurrent;
+pub mod config;
+pub mod diff;
+pub mod embedded;
+pub mod enrich;
+pub mod geo;
+pub mod history;
+pub mod irr;
+pub mod journal;
+pub mod json;
+pub mod key_trie;
+pub mod maintenance;
+pub mod mmdb;
+pub mod multibit;
+pub mod observe;
+pub mod persistent;
+pub mod profile;
 pub mod radix_trie;
+pub mod registry;
+pub mod reputation;
+pub mod rir;
+pub mod table;
+pub mod text;
+pub mod tombstone;
+pub mod ttl;
 pub mod util;
+pub mod validate;
@@ -1,52 +1,440 @@
-pub mod radix_trie;
-pub mod util;
-
-use radix_trie::Trie;
-use util::{generate_cidr_blocks, generate_ips};
+use mm2rtrie::backend::{BackendKind, LpmBackend, bench};
+use mm2rtrie::builder::{FrozenTrie, TrieBuilder};
+use mm2rtrie::config::{self, Config, ConfigValue};
+use mm2rtrie::diff;
+use mm2rtrie::enrich::{
+    CsvOptions, EnrichSummary, NdjsonOptions, enrich_csv, enrich_ndjson, match_record, parse_ip,
+};
+use mm2rtrie::radix_trie::Trie;
+use mm2rtrie::util::{generate_cidr_blocks, generate_ips};
+use mm2rtrie::validate::{ValidateOptions, ValidationReport, validate};
 
 use rand::{Rng, rngs::ThreadRng};
-use rayon::iter::ParallelIterator;
-use rayon::prelude::IntoParallelRefIterator;
 
-use std::time::Instant;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process;
+use std::sync::Arc;
 
-fn main() {
-    let n_cidr_blocks: usize = 64_000;
+const USAGE: &str = "usage:
+    mm2rtrie [--config FILE] COMMAND [OPTION...]
+    mm2rtrie [bench [--backend radix|frozen|multibit|arena|all] [--blocks N] [--lookups N]]
+    mm2rtrie lookup --trie FILE [IP...]
+    mm2rtrie enrich --trie FILE (--input FILE | --stdin) [--output FILE]
+                    [--format csv] --ip-column N [--header | --no-header]
+    mm2rtrie enrich --trie FILE (--input FILE | --stdin) [--output FILE]
+                    --format ndjson --ip-field NAME [--field NAME]
+    mm2rtrie diff OLD NEW
+    mm2rtrie validate [--column N] [--header | --no-header] FILE
+
+The trie is a bincode encoded trie of string values. `lookup` reads ips from
+stdin if none are given and writes a JSON record of the matches per ip, the same
+record `enrich --format ndjson` adds to every event. N counts columns from 1, the
+ndjson field defaults to 'match' and the output defaults to stdout. `diff` writes
+a JSON report of the changes from the OLD to the NEW trie. `validate` reports
+malformed, duplicate and overlapping blocks of a prefix list with one block per
+line in column N of comma separated fields, and fails on malformed or duplicate
+blocks.
+
+The config file sets default options per command in sections named after the
+command, with keys named after the options, e.g. `ip_column = 3` in `[enrich]`.
+Options given on the command line override the config, and later options override
+earlier ones, e.g. `--stdin` replaces an `input` of the config.";
+
+/// Commands that can be configured in a section of the config file.
+const CONFIG_SECTIONS: [&str; 4] = ["bench", "lookup", "enrich", "validate"];
+
+enum Command {
+    Bench(BenchOptions),
+    Lookup {
+        trie: String,
+        ips: Vec<String>,
+    },
+    Enrich(EnrichOptions),
+    Diff {
+        old: String,
+        new: String,
+    },
+    Validate {
+        path: String,
+        options: ValidateOptions,
+    },
+}
+
+/// Options of the `bench` command.
+struct BenchOptions {
+    backends: Vec<BackendKind>,
+    n_cidr_blocks: usize,
+    n_ips: usize,
+}
+
+/// Options of the `enrich` command, reading stdin and writing stdout when no input
+/// or output file is given.
+struct EnrichOptions {
+    trie: String,
+    input: Option<String>,
+    output: Option<String>,
+    format: EnrichFormat,
+}
+
+enum EnrichFormat {
+    Csv(CsvOptions),
+    Ndjson(NdjsonOptions),
+}
+
+fn parse_bench(mut args: impl Iterator<Item = String>) -> Result<BenchOptions, String> {
+    let mut options = BenchOptions {
+        backends: vec![BackendKind::Frozen],
+        n_cidr_blocks: 64_000,
+        n_ips: 50_000_000,
+    };
+
+    while let Some(arg) = args.next() {
+        let value: String = args
+            .next()
+            .ok_or_else(|| format!("missing value for '{}'", arg))?;
+        match arg.as_str() {
+            "--backend" if value == "all" => options.backends = BackendKind::ALL.to_vec(),
+            "--backend" => options.backends = vec![value.parse().map_err(|e| format!("{}", e))?],
+            "--blocks" => options.n_cidr_blocks = value.parse().map_err(|_| "invalid --blocks")?,
+            "--lookups" => options.n_ips = value.parse().map_err(|_| "invalid --lookups")?,
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_enrich(mut args: impl Iterator<Item = String>) -> Result<EnrichOptions, String> {
+    let (mut trie, mut input, mut output) = (None, None, None);
+    let (mut format, mut ip_column, mut ip_field, mut field) = (None, None, None, None);
+    let (mut header, mut stdin) = (false, false);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--header" => header = true,
+            "--no-header" => header = false,
+            "--stdin" => (stdin, input) = (true, None),
+            _ => {
+                let value: String = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for '{}'", arg))?;
+                match arg.as_str() {
+                    "--trie" => trie = Some(value),
+                    "--input" => (input, stdin) = (Some(value), false),
+                    "--output" => output = Some(value),
+                    "--format" => format = Some(value),
+                    "--ip-field" => ip_field = Some(value),
+                    "--field" => field = Some(value),
+                    "--ip-column" => match value.parse::<usize>() {
+                        Ok(n) if n > 0 => ip_column = Some(n - 1),
+                        _ => return Err("invalid --ip-column".to_string()),
+                    },
+                    _ => return Err(format!("unknown option '{}'", arg)),
+                }
+            }
+        }
+    }
+
+    if stdin == input.is_some() {
+        return Err("expected exactly one of --input and --stdin".to_string());
+    }
+
+    let format: EnrichFormat = match format.as_deref() {
+        None | Some("csv") => EnrichFormat::Csv(CsvOptions {
+            ip_column: ip_column.ok_or("missing --ip-column")?,
+            header,
+        }),
+        Some("ndjson") => EnrichFormat::Ndjson(NdjsonOptions {
+            ip_field: ip_field.ok_or("missing --ip-field")?,
+            field: field.unwrap_or_else(|| "match".to_string()),
+        }),
+        Some(format) => return Err(format!("unknown format '{}'", format)),
+    };
+
+    Ok(EnrichOptions {
+        trie: trie.ok_or("missing --trie")?,
+        input,
+        output,
+        format,
+    })
+}
+
+fn parse_validate(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut path: Option<String> = None;
+    let mut options = ValidateOptions::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--header" => options.header = true,
+            "--no-header" => options.header = false,
+            "--column" => match args.next().map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) if n > 0 => options.column = n - 1,
+                _ => return Err("invalid --column".to_string()),
+            },
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    Ok(Command::Validate {
+        path: path.ok_or("missing FILE")?,
+        options,
+    })
+}
+
+fn parse_lookup(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut trie: Option<String> = None;
+    let mut ips: Vec<String> = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trie" => trie = Some(args.next().ok_or("missing value for '--trie'")?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+            _ => ips.push(arg),
+        }
+    }
+
+    Ok(Command::Lookup {
+        trie: trie.ok_or("missing --trie")?,
+        ips,
+    })
+}
+
+/// Read the options of the command from the config file, as the arguments they
+/// would be given as on the command line.
+fn config_args(path: &str, command: &str) -> Result<Vec<String>, String> {
+    let text: String = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let config: Config = config::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+
+    for section in config.sections() {
+        if !CONFIG_SECTIONS.contains(&section) {
+            return Err(format!("{}: unknown section '{}'", path, section));
+        }
+    }
+
+    let mut args: Vec<String> = Vec::new();
+    for (key, value) in config.section(command).into_iter().flatten() {
+        let flag: String = format!("--{}", key.replace('_', "-"));
+        match value {
+            ConfigValue::Bool(true) => args.push(flag),
+            ConfigValue::Bool(false) => {}
+            value => args.extend([flag, value.to_string()]),
+        }
+    }
+    Ok(args)
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.peekable();
+    let config: Option<String> = match args.next_if(|arg| arg == "--config") {
+        Some(_) => Some(args.next().ok_or("missing value for '--config'")?),
+        None => None,
+    };
+    let command: String = args.next().unwrap_or_else(|| "bench".to_string());
+
+    // Options of the config come first, so the command line overrides them.
+    let defaults: Vec<String> = match &config {
+        Some(path) => config_args(path, &command)?,
+        None => Vec::new(),
+    };
+    let args = defaults.into_iter().chain(args);
+    match command.as_str() {
+        "bench" => parse_bench(args).map(Command::Bench),
+        "lookup" => parse_lookup(args),
+        "enrich" => parse_enrich(args).map(Command::Enrich),
+        "validate" => parse_validate(args),
+        "diff" => match args.collect::<Vec<String>>().as_slice() {
+            [old, new] => Ok(Command::Diff {
+                old: old.clone(),
+                new: new.clone(),
+            }),
+            _ => Err("expected OLD and NEW tries".to_string()),
+        },
+        command => Err(format!("unknown command '{}'", command)),
+    }
+}
+
+fn run_bench(options: BenchOptions) {
+    let n_cidr_blocks: usize = options.n_cidr_blocks;
     println!("Generating {} CIDR blocks", n_cidr_blocks);
     let cidr_blocks: Vec<(u32, u32)> = generate_cidr_blocks(n_cidr_blocks);
 
-    let mut t: Trie<u32> = Trie::empty();
+    let mut builder: TrieBuilder<u32> = TrieBuilder::with_capacity(n_cidr_blocks);
     println!("Inserting CIDR blocks to Trie");
     let mut thread_rng: ThreadRng = rand::rng();
     for (net, prefix) in cidr_blocks.into_iter() {
-        t.insert_net_and_prefix(net, prefix, thread_rng.random());
+        builder.insert_net_and_prefix(net, prefix, thread_rng.random());
     }
+    let t: Arc<FrozenTrie<u32>> = builder.build();
+    let trie: Trie<u32> = Arc::unwrap_or_clone(t).thaw();
 
-    let n_ips: usize = 50_000_000;
+    let n_ips: usize = options.n_ips;
     println!("Generating {} ips for lookup", n_ips);
     let ips: Vec<u32> = generate_ips(n_ips);
 
-    println!("Starting timer, performing {} lookups...", n_ips);
-
-    let start = Instant::now();
-    let n_hits: usize = ips
-        .par_iter()
-        .map(|ip| t.get(*ip).len())
-        .collect::<Vec<usize>>()
-        .into_iter()
-        .sum();
-    let elapsed = start.elapsed();
-
-    println!(
-        "\nSTATS:\nElapsed {:?} ms for {:?} ip lookups, {:?} ns per lookup",
-        elapsed.as_millis() as f64,
-        n_ips,
-        elapsed.as_nanos() as f64 / n_ips as f64,
+    println!("\nSTATS:");
+    for kind in options.backends {
+        let backend: Arc<dyn LpmBackend<u32> + Send + Sync> = kind.build(trie.clone());
+        println!("{}", bench(backend.as_ref(), &ips));
+    }
+
+    if let Some(ip) = ips.get(23) {
+        println!("Example hit: ip={}, values:{:?}", ip, trie.get(*ip));
+    }
+}
+
+fn load_trie(path: &str) -> Result<Trie<String>, String> {
+    let file: File = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    Trie::decode_from_reader(&mut BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn run_lookup(trie: &str, ips: Vec<String>) -> Result<(), String> {
+    let trie: Trie<String> = load_trie(trie)?;
+    let ips: Box<dyn Iterator<Item = io::Result<String>>> = if ips.is_empty() {
+        Box::new(io::stdin().lock().lines())
+    } else {
+        Box::new(ips.into_iter().map(Ok))
+    };
+
+    let mut stdout = io::stdout().lock();
+    for ip in ips {
+        let ip: String = ip.map_err(|e| format!("stdin: {}", e))?;
+        if ip.trim().is_empty() {
+            continue;
+        }
+        match parse_ip(&ip) {
+            Some(addr) => writeln!(stdout, "{}", match_record(&trie, addr))
+                .map_err(|e| format!("stdout: {}", e))?,
+            None => eprintln!("invalid ip '{}'", ip),
+        }
+    }
+    Ok(())
+}
+
+fn run_enrich(options: EnrichOptions) -> Result<(), String> {
+    let trie: Trie<String> = load_trie(&options.trie)?;
+    let source: &str = options.input.as_deref().unwrap_or("stdin");
+    let reader: Box<dyn BufRead> = match &options.input {
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).map_err(|e| format!("{}: {}", path, e))?,
+        )),
+        None => Box::new(io::stdin().lock()),
+    };
+    let mut writer: BufWriter<Box<dyn Write>> = BufWriter::new(match &options.output {
+        Some(path) => Box::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => Box::new(io::stdout().lock()),
+    });
+
+    let summary: EnrichSummary = match &options.format {
+        EnrichFormat::Csv(csv) => enrich_csv(&trie, reader, &mut writer, csv),
+        EnrichFormat::Ndjson(ndjson) => enrich_ndjson(&trie, reader, &mut writer, ndjson),
+    }
+    .map_err(|e| format!("{}: {}", source, e))?;
+
+    eprintln!(
+        "Enriched {} rows, {} matched, {} without a valid ip",
+        summary.rows, summary.matched, summary.invalid
+    );
+    Ok(())
+}
+
+fn run_diff(old: &str, new: &str) -> Result<(), String> {
+    let report = diff::report(&load_trie(old)?, &load_trie(new)?);
+    println!("{}", report.to_json());
+    Ok(())
+}
+
+fn run_validate(path: &str, options: &ValidateOptions) -> Result<(), String> {
+    let file: File = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let report: ValidationReport =
+        validate(BufReader::new(file), options).map_err(|e| format!("{}: {}", path, e))?;
+
+    for finding in report.findings.iter() {
+        println!("{}", finding);
+    }
+    let errors: usize = report.findings.iter().filter(|f| f.is_error()).count();
+    eprintln!(
+        "Validated {} blocks, {} errors, {} overlaps",
+        report.blocks,
+        errors,
+        report.findings.len() - errors
     );
 
-    println!("Got {} lookup hits", n_hits);
-    println!("Example hit: ip={}, values:{:?}", ips[23], t.get(ips[23]));
+    match report.is_ok() {
+        true => Ok(()),
+        false => Err(format!("{}: invalid prefix list", path)),
+    }
+}
+
+fn main() {
+    let command: Command = parse_args(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    });
+
+    let result: Result<(), String> = match command {
+        Command::Bench(options) => {
+            run_bench(options);
+            Ok(())
+        }
+        Command::Lookup { trie, ips } => run_lookup(&trie, ips),
+        Command::Enrich(options) => run_enrich(options),
+        Command::Diff { old, new } => run_diff(&old, &new),
+        Command::Validate { path, options } => run_validate(&path, &options),
+    };
 
-    println!("Writing trie to file 'trie.bin'");
-    // t.write_to_file("trie.bin");
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> impl Iterator<Item = String> {
+        s.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let path: &str = "./test-config.toml";
+        let config =
+            "[enrich]\ntrie = \"geo.bin\"\ninput = \"events.csv\"\nip_column = 2\nheader = true\n";
+        fs::write(path, config).unwrap();
+
+        let Ok(Command::Enrich(options)) = parse_args(args(&format!("--config {} enrich", path)))
+        else {
+            panic!("expected the enrich command");
+        };
+        assert_eq!(Some("events.csv"), options.input.as_deref());
+        assert!(matches!(
+            options.format,
+            EnrichFormat::Csv(CsvOptions { header: true, .. })
+        ));
+
+        let command = parse_args(args(&format!(
+            "--config {} enrich --stdin --no-header",
+            path
+        )));
+        let Ok(Command::Enrich(options)) = command else {
+            panic!("expected the enrich command");
+        };
+        assert_eq!(None, options.input);
+        assert!(matches!(
+            options.format,
+            EnrichFormat::Csv(CsvOptions { header: false, .. })
+        ));
+
+        let command = parse_args(args(
+            "enrich --trie geo.bin --stdin --input a.csv --ip-column 1",
+        ));
+        let Ok(Command::Enrich(options)) = command else {
+            panic!("expected the enrich command");
+        };
+        assert_eq!(Some("a.csv"), options.input.as_deref());
+        fs::remove_file(path).unwrap();
+    }
 }
@@ -0,0 +1,172 @@
+use crate::concurrent::ConcurrentTrie;
+use crate::radix_trie::Trie;
+
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A trie shared between readers and writers. Readers clone the inner `Arc` to get a
+/// snapshot, writers mutate through `Arc::make_mut` while holding the write lock.
+pub type SharedTrie<V> = Arc<RwLock<Arc<Trie<V>>>>;
+
+type Pass<V> = Box<dyn Fn(&mut Trie<V>) + Send + Sync>;
+
+/// Periodically runs maintenance passes (compaction, aggregation, expiry pruning, ...)
+/// on a snapshot of a shared trie and atomically swaps the result in.
+pub struct Maintainer<V> {
+    table: ConcurrentTrie<V>,
+    interval: Duration,
+    passes: Vec<Pass<V>>,
+}
+
+impl<V: Clone + 'static> Maintainer<V> {
+    /// Create a new maintainer for the shared trie without any passes.
+    pub fn new(table: impl Into<ConcurrentTrie<V>>, interval: Duration) -> Self {
+        Maintainer {
+            table: table.into(),
+            interval,
+            passes: Vec::new(),
+        }
+    }
+
+    /// Add a custom maintenance pass, e.g. pruning expired values.
+    pub fn with_pass(mut self, pass: impl Fn(&mut Trie<V>) + Send + Sync + 'static) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Add a pass compacting the trie.
+    pub fn with_compaction(self) -> Self {
+        self.with_pass(Trie::compact)
+    }
+
+    /// Run every pass once on a snapshot of the shared trie and swap the result in.
+    ///
+    /// If the shared trie was replaced or mutated while the passes were running the
+    /// result is discarded, so that no concurrent write is lost, and `false` is returned.
+    pub fn run_once(&self) -> bool {
+        let snapshot: Arc<Trie<V>> = self.table.snapshot();
+
+        let mut maintained: Trie<V> = (*snapshot).clone();
+        for pass in self.passes.iter() {
+            pass(&mut maintained);
+        }
+
+        self.table.compare_and_swap(&snapshot, maintained)
+    }
+}
+
+impl<V: PartialEq + Clone + 'static> Maintainer<V> {
+    /// Add a pass aggregating identical sibling prefixes.
+    pub fn with_aggregation(self) -> Self {
+        self.with_pass(Trie::aggregate)
+    }
+}
+
+impl<V: Clone + Send + Sync + 'static> Maintainer<V> {
+    /// Run the maintainer on a background thread until the returned handle is stopped.
+    pub fn spawn(self) -> MaintainerHandle {
+        let interval: Duration = self.interval;
+        spawn_periodic(interval, move || {
+            self.run_once();
+        })
+    }
+}
+
+/// Run the closure every interval on a background thread until the returned handle is
+/// stopped.
+pub(crate) fn spawn_periodic(
+    interval: Duration,
+    mut run: impl FnMut() + Send + 'static,
+) -> MaintainerHandle {
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            run();
+        }
+    });
+
+    MaintainerHandle { stop, thread }
+}
+
+/// Handle to a maintainer running on a background thread.
+pub struct MaintainerHandle {
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl MaintainerHandle {
+    /// Stop the background maintainer and wait for it to finish its current run.
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        self.thread.join().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    fn shared() -> SharedTrie<u32> {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/25", 1);
+        t.insert_cidr("10.0.0.128/25", 1);
+        Arc::new(RwLock::new(Arc::new(t)))
+    }
+
+    #[test]
+    fn run_once_swaps_maintained_trie() {
+        let table = shared();
+        let before: Arc<Trie<u32>> = Arc::clone(&table.read().unwrap());
+
+        let maintainer = Maintainer::new(Arc::clone(&table), Duration::from_secs(60))
+            .with_compaction()
+            .with_aggregation();
+        assert!(maintainer.run_once());
+
+        let mut expected: Trie<u32> = Trie::empty();
+        expected.insert_cidr("10.0.0.0/24", 1);
+        assert_eq!(&expected, table.read().unwrap().as_ref());
+
+        // Readers holding the old snapshot are unaffected by the swap.
+        assert_eq!(vec![&1], before.get(Ipv4Addr::new(10, 0, 0, 200)));
+    }
+
+    #[test]
+    fn run_once_keeps_concurrent_writes() {
+        let table = shared();
+        let writer = Arc::clone(&table);
+        let maintainer =
+            Maintainer::new(Arc::clone(&table), Duration::from_secs(60)).with_pass(move |_| {
+                let mut t = writer.write().unwrap();
+                Arc::make_mut(&mut t).insert_cidr("20.0.0.0/8", 2);
+            });
+
+        assert!(!maintainer.run_once());
+        assert!(
+            table
+                .read()
+                .unwrap()
+                .contains_ip(Ipv4Addr::new(20, 1, 2, 3))
+        );
+    }
+
+    #[test]
+    fn spawn_and_stop() {
+        let table = shared();
+        let handle = Maintainer::new(Arc::clone(&table), Duration::from_millis(1))
+            .with_aggregation()
+            .spawn();
+
+        let mut expected: Trie<u32> = Trie::empty();
+        expected.insert_cidr("10.0.0.0/24", 1);
+        while **table.read().unwrap() != expected {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        handle.stop();
+    }
+}
@@ -0,0 +1,424 @@
+//! Ingestion of MaxMind GeoIP2 and GeoLite2 databases into a trie.
+//!
+//! Every ipv4 network of the database is converted into an owned record, so the
+//! resulting trie is persisted with [`Trie::write_to_file`] and queried offline
+//! without the database:
+//!
+//! ```ignore
+//! let t: Trie<CityRecord> = Trie::from_mmdb("GeoLite2-City.mmdb")?;
+//! t.write_to_file("geo.bin");
+//! ```
+//!
+//! A City database can also be read as [`CountryRecord`]s, since it holds every
+//! field of a Country database, and into a [`GeoTrie`] with [`GeoTrie::from_mmdb`].
+//!
+//! [`GeoTrie`]: crate::geo::GeoTrie
+//! [`GeoTrie::from_mmdb`]: crate::geo::GeoTrie::from_mmdb
+
+use crate::radix_trie::{CidrBlock, Trie};
+
+use bincode::{Decode, Encode};
+use ipnetwork::IpNetwork;
+use maxminddb::{MaxMindDBError, Reader, geoip2};
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+/// An owned record converted from the record of a network in a MaxMind database.
+pub trait MmdbRecord: Sized {
+    /// The record as decoded from the database, borrowing its strings.
+    type Raw<'de>: Deserialize<'de>;
+
+    fn from_raw(raw: Self::Raw<'_>) -> Self;
+}
+
+/// Get the English name out of the names of a record.
+fn english(names: Option<BTreeMap<&str, &str>>) -> Option<String> {
+    names?.get("en").map(|name| name.to_string())
+}
+
+/// Country a network is assigned to, from a GeoIP2 Country or City database.
+#[derive(Clone, Debug, Decode, Default, Encode, Eq, Hash, PartialEq)]
+pub struct CountryRecord {
+    /// GeoNames identifier of the country.
+    pub geoname_id: Option<u32>,
+    /// ISO 3166-1 alpha-2 code of the country.
+    pub iso_code: Option<String>,
+    pub name: Option<String>,
+    /// Two letter code of the continent, e.g. `EU`.
+    pub continent_code: Option<String>,
+    /// ISO 3166-1 alpha-2 code of the country the network is registered in, which
+    /// may differ from the country it is located in.
+    pub registered_country_iso_code: Option<String>,
+    pub is_in_european_union: bool,
+}
+
+impl CountryRecord {
+    fn new(
+        country: Option<geoip2::country::Country<'_>>,
+        continent: Option<geoip2::country::Continent<'_>>,
+        registered_country: Option<geoip2::country::Country<'_>>,
+    ) -> Self {
+        let mut record = CountryRecord {
+            continent_code: continent.and_then(|c| c.code).map(str::to_string),
+            registered_country_iso_code: registered_country
+                .and_then(|c| c.iso_code)
+                .map(str::to_string),
+            ..CountryRecord::default()
+        };
+        if let Some(country) = country {
+            record.geoname_id = country.geoname_id;
+            record.iso_code = country.iso_code.map(str::to_string);
+            record.name = english(country.names);
+            record.is_in_european_union = country.is_in_european_union.unwrap_or(false);
+        }
+        record
+    }
+}
+
+impl MmdbRecord for CountryRecord {
+    type Raw<'de> = geoip2::Country<'de>;
+
+    fn from_raw(raw: geoip2::Country<'_>) -> Self {
+        CountryRecord::new(raw.country, raw.continent, raw.registered_country)
+    }
+}
+
+/// Location a network is assigned to, from a GeoIP2 City database.
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq)]
+pub struct CityRecord {
+    /// GeoNames identifier of the city.
+    pub geoname_id: Option<u32>,
+    pub name: Option<String>,
+    /// ISO 3166-2 codes of the subdivisions, from the largest to the smallest.
+    pub subdivisions: Vec<String>,
+    pub postal_code: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Radius in kilometers around the coordinates the network is likely within.
+    pub accuracy_radius: Option<u16>,
+    /// Time zone of the location, e.g. `Europe/Stockholm`.
+    pub time_zone: Option<String>,
+    pub country: CountryRecord,
+}
+
+impl MmdbRecord for CityRecord {
+    type Raw<'de> = geoip2::City<'de>;
+
+    fn from_raw(raw: geoip2::City<'_>) -> Self {
+        let mut record = CityRecord {
+            subdivisions: raw
+                .subdivisions
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|s| s.iso_code.map(str::to_string))
+                .collect(),
+            postal_code: raw.postal.and_then(|p| p.code).map(str::to_string),
+            country: CountryRecord::new(raw.country, raw.continent, raw.registered_country),
+            ..CityRecord::default()
+        };
+        if let Some(city) = raw.city {
+            record.geoname_id = city.geoname_id;
+            record.name = english(city.names);
+        }
+        if let Some(location) = raw.location {
+            record.latitude = location.latitude;
+            record.longitude = location.longitude;
+            record.accuracy_radius = location.accuracy_radius;
+            record.time_zone = location.time_zone.map(str::to_string);
+        }
+        record
+    }
+}
+
+/// Autonomous system a network is announced by, from a GeoLite2 ASN database.
+#[derive(Clone, Debug, Decode, Default, Encode, Eq, Hash, PartialEq)]
+pub struct AsnRecord {
+    /// Number of the autonomous system, 0 if the database has none.
+    pub number: u32,
+    pub organization: Option<String>,
+}
+
+impl MmdbRecord for AsnRecord {
+    type Raw<'de> = geoip2::Asn<'de>;
+
+    fn from_raw(raw: geoip2::Asn<'_>) -> Self {
+        AsnRecord {
+            number: raw.autonomous_system_number.unwrap_or(0),
+            organization: raw.autonomous_system_organization.map(str::to_string),
+        }
+    }
+}
+
+/// Get an iterator over every ipv4 network of the database and its record, in
+/// order. The ipv4 networks of an ipv6 database, i.e. those under `::/96`, are
+/// given as ipv4 blocks, and every other ipv6 network is left out.
+pub fn networks<'de, V, S>(
+    reader: &'de Reader<S>,
+) -> Result<impl Iterator<Item = Result<(CidrBlock, V), MaxMindDBError>> + 'de, MaxMindDBError>
+where
+    V: MmdbRecord + 'de,
+    S: AsRef<[u8]>,
+{
+    let all: IpNetwork = IpNetwork::new(Ipv4Addr::UNSPECIFIED.into(), 0).unwrap();
+    let within = reader.within::<V::Raw<'de>>(all)?;
+
+    Ok(within.filter_map(|item| match item {
+        Ok(item) => match item.ip_net {
+            IpNetwork::V4(net) => Some(Ok((
+                CidrBlock {
+                    net: u32::from(net.network()),
+                    prefix: net.prefix() as u32,
+                },
+                V::from_raw(item.info),
+            ))),
+            IpNetwork::V6(_) => None,
+        },
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+/// Build a trie of every ipv4 network of the opened database, see [`networks`].
+pub fn from_reader<V, S>(reader: &Reader<S>) -> Result<Trie<V>, MaxMindDBError>
+where
+    V: MmdbRecord,
+    S: AsRef<[u8]>,
+{
+    let mut trie: Trie<V> = Trie::empty();
+    for network in networks(reader)? {
+        let (cidr, record) = network?;
+        trie.try_insert(&cidr, record).unwrap();
+    }
+    Ok(trie)
+}
+
+impl<V: MmdbRecord> Trie<V> {
+    /// Build a trie of every ipv4 network of the MaxMind database at the path, see
+    /// [`networks`].
+    pub fn from_mmdb<P: AsRef<Path>>(path: P) -> Result<Self, MaxMindDBError> {
+        from_reader(&Reader::open_readfile(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::{GeoRecord, GeoTrie};
+
+    use std::str::FromStr;
+
+    /// A value of the MaxMind DB data section.
+    enum Data {
+        Str(&'static str),
+        U16(u16),
+        U32(u32),
+        U64(u64),
+        F64(f64),
+        Map(Vec<(&'static str, Data)>),
+        Array(Vec<Data>),
+    }
+
+    fn write_data(data: &Data, out: &mut Vec<u8>) {
+        match data {
+            Data::Str(s) if s.len() >= 29 => {
+                out.extend([2 << 5 | 29, s.len() as u8 - 29]);
+                out.extend(s.as_bytes());
+            }
+            Data::Str(s) => {
+                out.push(2 << 5 | s.len() as u8);
+                out.extend(s.as_bytes());
+            }
+            Data::U16(n) => {
+                out.push(5 << 5 | 2);
+                out.extend(n.to_be_bytes());
+            }
+            Data::U32(n) => {
+                out.push(6 << 5 | 4);
+                out.extend(n.to_be_bytes());
+            }
+            Data::U64(n) => {
+                out.extend([8, 9 - 7]);
+                out.extend(n.to_be_bytes());
+            }
+            Data::F64(x) => {
+                out.push(3 << 5 | 8);
+                out.extend(x.to_be_bytes());
+            }
+            Data::Map(entries) => {
+                out.push(7 << 5 | entries.len() as u8);
+                for (key, value) in entries {
+                    write_data(&Data::Str(key), out);
+                    write_data(value, out);
+                }
+            }
+            Data::Array(items) => {
+                out.extend([items.len() as u8, 11 - 7]);
+                for item in items {
+                    write_data(item, out);
+                }
+            }
+        }
+    }
+
+    /// Build an ipv4 database with 24 bit records of the networks and their data.
+    fn database(networks: Vec<(&str, Data)>) -> Vec<u8> {
+        // A child is 0 for none, as the root is never a child, the number of a node,
+        // or the number of a network with the top bit set.
+        let mut nodes: Vec<[u32; 2]> = vec![[0, 0]];
+        let mut data: Vec<u8> = Vec::new();
+        let mut offsets: Vec<usize> = Vec::new();
+        for (i, (cidr, value)) in networks.iter().enumerate() {
+            let cidr: CidrBlock = CidrBlock::from_str(cidr).unwrap();
+            offsets.push(data.len());
+            write_data(value, &mut data);
+
+            let mut node: usize = 0;
+            for depth in 0..cidr.prefix {
+                let bit: usize = (cidr.net >> (31 - depth) & 1) as usize;
+                if depth + 1 == cidr.prefix {
+                    nodes[node][bit] = 1 << 31 | i as u32;
+                } else {
+                    if nodes[node][bit] == 0 {
+                        nodes.push([0, 0]);
+                        nodes[node][bit] = nodes.len() as u32 - 1;
+                    }
+                    node = nodes[node][bit] as usize;
+                }
+            }
+        }
+
+        let count: usize = nodes.len();
+        let mut bytes: Vec<u8> = Vec::new();
+        for child in nodes.into_iter().flatten() {
+            let record: usize = match child {
+                0 => count,
+                c if c >> 31 == 1 => count + 16 + offsets[(c & !(1 << 31)) as usize],
+                c => c as usize,
+            };
+            bytes.extend(&(record as u32).to_be_bytes()[1..]);
+        }
+        bytes.extend([0; 16]);
+        bytes.extend(data);
+
+        bytes.extend(b"\xab\xcd\xefMaxMind.com");
+        let metadata = Data::Map(vec![
+            ("binary_format_major_version", Data::U16(2)),
+            ("binary_format_minor_version", Data::U16(0)),
+            ("build_epoch", Data::U64(1_700_000_000)),
+            ("database_type", Data::Str("Test")),
+            ("description", Data::Map(vec![])),
+            ("ip_version", Data::U16(4)),
+            ("languages", Data::Array(vec![Data::Str("en")])),
+            ("node_count", Data::U32(count as u32)),
+            ("record_size", Data::U16(24)),
+        ]);
+        write_data(&metadata, &mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn records_of_database() {
+        let bytes: Vec<u8> = database(vec![
+            (
+                "1.0.0.0/24",
+                Data::Map(vec![
+                    ("autonomous_system_number", Data::U32(13335)),
+                    ("autonomous_system_organization", Data::Str("CLOUDFLARENET")),
+                ]),
+            ),
+            (
+                "81.2.69.0/24",
+                Data::Map(vec![
+                    ("autonomous_system_number", Data::U32(20712)),
+                    (
+                        "city",
+                        Data::Map(vec![
+                            ("geoname_id", Data::U32(2643743)),
+                            ("names", Data::Map(vec![("en", Data::Str("London"))])),
+                        ]),
+                    ),
+                    ("continent", Data::Map(vec![("code", Data::Str("EU"))])),
+                    (
+                        "country",
+                        Data::Map(vec![
+                            ("geoname_id", Data::U32(2635167)),
+                            ("iso_code", Data::Str("GB")),
+                        ]),
+                    ),
+                    (
+                        "location",
+                        Data::Map(vec![
+                            ("latitude", Data::F64(51.5142)),
+                            ("longitude", Data::F64(-0.0931)),
+                            ("time_zone", Data::Str("Europe/London")),
+                        ]),
+                    ),
+                ]),
+            ),
+            ("81.2.70.0/26", Data::Map(vec![])),
+        ]);
+        let reader: Reader<Vec<u8>> = Reader::from_source(bytes).unwrap();
+
+        let asns: Trie<AsnRecord> = from_reader(&reader).unwrap();
+        assert_eq!(
+            vec![
+                (
+                    CidrBlock::from_str("1.0.0.0/24").unwrap(),
+                    vec![AsnRecord {
+                        number: 13335,
+                        organization: Some("CLOUDFLARENET".to_string())
+                    }]
+                ),
+                (
+                    CidrBlock::from_str("81.2.69.0/24").unwrap(),
+                    vec![AsnRecord {
+                        number: 20712,
+                        organization: None
+                    }]
+                ),
+                (
+                    CidrBlock::from_str("81.2.70.0/26").unwrap(),
+                    vec![AsnRecord::default()]
+                ),
+            ],
+            asns.iter()
+                .map(|(cidr, records)| (cidr, records.to_vec()))
+                .collect::<Vec<_>>()
+        );
+
+        let cities: Trie<CityRecord> = from_reader(&reader).unwrap();
+        let london: &CityRecord = cities.get(Ipv4Addr::new(81, 2, 69, 1))[0];
+        assert_eq!(Some(2643743), london.geoname_id);
+        assert_eq!(Some("London"), london.name.as_deref());
+        assert_eq!(Some(51.5142), london.latitude);
+        assert_eq!(Some("Europe/London"), london.time_zone.as_deref());
+        assert_eq!(Some("GB"), london.country.iso_code.as_deref());
+        assert_eq!(Some("EU"), london.country.continent_code.as_deref());
+
+        let countries: Trie<CountryRecord> = from_reader(&reader).unwrap();
+        assert_eq!(
+            Some(2635167),
+            countries.get(Ipv4Addr::new(81, 2, 69, 200))[0].geoname_id
+        );
+        assert_eq!(
+            vec![&CountryRecord::default()],
+            countries.get(Ipv4Addr::new(81, 2, 70, 1))
+        );
+        assert!(countries.get(Ipv4Addr::new(81, 2, 70, 200)).is_empty());
+        assert_eq!(3, countries.iter().count());
+
+        let geo: GeoTrie = GeoTrie::from_mmdb_reader(&reader).unwrap();
+        let record = |geoname_id: u32| GeoRecord {
+            geoname_id,
+            is_latest: true,
+        };
+        assert_eq!(
+            Some(&record(2643743)),
+            geo.lookup_latest(Ipv4Addr::new(81, 2, 69, 1))
+        );
+        assert!(geo.lookup(Ipv4Addr::new(81, 2, 70, 1)).is_empty());
+        assert_eq!(1, geo.trie().iter().count());
+    }
+}
@@ -0,0 +1,403 @@
+use crate::radix_trie::{IntoIpKey, Trie, TrieNode};
+
+use std::error::Error;
+use std::fmt;
+
+/// Largest stride allowed for a single level, bounding a node to 2^16 entries.
+pub const MAX_STRIDE: u8 = 16;
+
+/// Average stride the auto-tuner aims for when picking the number of levels.
+const TARGET_STRIDE: u32 = 8;
+
+const NO_CHILD: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    child: u32,
+    start: u32,
+    len: u32,
+}
+
+/// Read-only multibit trie consuming several bits of the ip per level.
+///
+/// Prefixes that do not end on a level boundary are expanded into every entry
+/// they cover, so a lookup visits at most one node per level and returns the
+/// same values, in the same order, as the binary trie it was built from.
+#[derive(Clone, Debug)]
+pub struct MultibitTrie<V> {
+    strides: Vec<u8>,
+    root_values: Vec<u32>,
+    entries: Vec<Entry>,
+    slots: Vec<u32>,
+    values: Vec<V>,
+}
+
+/// Memory layout of a multibit trie.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MultibitStats {
+    /// Number of bits consumed at each level, starting at the root.
+    pub strides: Vec<u8>,
+    /// Number of multibit nodes across all levels.
+    pub nodes: usize,
+    /// Number of entries across all nodes.
+    pub entries: usize,
+    /// Number of distinct values stored.
+    pub values: usize,
+    /// Number of value references after prefix expansion.
+    pub expanded_values: usize,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum StrideError {
+    /// A stride was zero or larger than [`MAX_STRIDE`].
+    InvalidStride(u8),
+    /// The strides do not reach the longest prefix in the trie.
+    TooShort { covered: u32, required: u32 },
+    /// The strides cover more than the 32 bits of an ip.
+    TooLong(u32),
+}
+
+impl fmt::Display for StrideError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StrideError::InvalidStride(s) => {
+                write!(f, "stride {} is not in the range [1, {}]", s, MAX_STRIDE)
+            }
+            StrideError::TooShort { covered, required } => write!(
+                f,
+                "strides cover {} bits but the trie has prefixes of length {}",
+                covered, required
+            ),
+            StrideError::TooLong(covered) => write!(f, "strides cover {} bits", covered),
+        }
+    }
+}
+
+impl Error for StrideError {}
+
+impl<V: Clone> MultibitTrie<V> {
+    /// Build a multibit trie with strides chosen from the prefix length
+    /// distribution of the provided trie.
+    pub fn from_trie(trie: &Trie<V>) -> Self {
+        let strides: Vec<u8> = tune_strides(trie);
+        Self::with_strides(trie, &strides).unwrap()
+    }
+
+    /// Build a multibit trie with the provided per-level strides.
+    pub fn with_strides(trie: &Trie<V>, strides: &[u8]) -> Result<Self, StrideError> {
+        if let Some(s) = strides.iter().find(|s| **s == 0 || **s > MAX_STRIDE) {
+            return Err(StrideError::InvalidStride(*s));
+        }
+
+        let covered: u32 = strides.iter().map(|s| *s as u32).sum();
+        if covered > 32 {
+            return Err(StrideError::TooLong(covered));
+        }
+
+        let required: u32 = trie.max_depth();
+        if covered < required {
+            return Err(StrideError::TooShort { covered, required });
+        }
+
+        let mut builder = Builder {
+            strides,
+            lists: Vec::new(),
+            children: Vec::new(),
+            values: Vec::new(),
+        };
+
+        let root_values: Vec<u32> = builder.push_values(trie.root().values());
+        if !strides.is_empty() {
+            builder.build_node(trie.root(), 0);
+        }
+
+        let mut slots: Vec<u32> = Vec::new();
+        let entries: Vec<Entry> = builder
+            .lists
+            .into_iter()
+            .zip(builder.children)
+            .map(|(list, child)| {
+                let start = slots.len() as u32;
+                slots.extend(list.iter());
+                Entry {
+                    child,
+                    start,
+                    len: list.len() as u32,
+                }
+            })
+            .collect();
+
+        Ok(MultibitTrie {
+            strides: strides.to_vec(),
+            root_values,
+            entries,
+            slots,
+            values: builder.values,
+        })
+    }
+}
+
+impl<V> MultibitTrie<V> {
+    /// Get the number of bits consumed at each level.
+    pub fn strides(&self) -> &[u8] {
+        &self.strides
+    }
+
+    /// Get the memory layout of the trie.
+    pub fn stats(&self) -> MultibitStats {
+        let root_entries: usize = self.strides.first().map_or(0, |s| 1 << s);
+        let nodes: usize = self.entries.iter().filter(|e| e.child != NO_CHILD).count();
+        MultibitStats {
+            strides: self.strides.clone(),
+            nodes: nodes + usize::from(root_entries > 0),
+            entries: self.entries.len(),
+            values: self.values.len(),
+            expanded_values: self.root_values.len() + self.slots.len(),
+        }
+    }
+
+    /// Get the values associated with the provided ip address.
+    pub fn get<K: IntoIpKey>(&self, ip: K) -> Vec<&V> {
+        let mut buffer: Vec<&V> = Vec::with_capacity(32);
+        let Some(ip) = ip.into_ip_key() else {
+            return buffer;
+        };
+        buffer.extend(self.root_values.iter().map(|i| &self.values[*i as usize]));
+
+        let mut base: usize = 0;
+        let mut consumed: u32 = 0;
+        for stride in self.strides.iter() {
+            let stride = *stride as u32;
+            let index = ((ip << consumed) >> (32 - stride)) as usize;
+            let entry: &Entry = &self.entries[base + index];
+
+            let slots = &self.slots[entry.start as usize..(entry.start + entry.len) as usize];
+            buffer.extend(slots.iter().map(|i| &self.values[*i as usize]));
+
+            if entry.child == NO_CHILD {
+                break;
+            }
+
+            base = entry.child as usize;
+            consumed += stride;
+        }
+
+        buffer
+    }
+
+    /// Get whether or not the trie contains the provided ip address.
+    pub fn contains_ip<K: IntoIpKey>(&self, ip: K) -> bool {
+        !self.get(ip).is_empty()
+    }
+}
+
+struct Builder<'a, V> {
+    strides: &'a [u8],
+    lists: Vec<Vec<u32>>,
+    children: Vec<u32>,
+    values: Vec<V>,
+}
+
+impl<V: Clone> Builder<'_, V> {
+    fn push_values(&mut self, values: &[V]) -> Vec<u32> {
+        let start = self.values.len() as u32;
+        self.values.extend_from_slice(values);
+        (start..self.values.len() as u32).collect()
+    }
+
+    /// Allocate the multibit node for the binary node starting the provided
+    /// level and return the index of its first entry.
+    fn build_node(&mut self, node: &TrieNode<V>, level: usize) -> u32 {
+        let stride: u32 = self.strides[level] as u32;
+        let base: usize = self.lists.len();
+        self.lists.resize(base + (1 << stride), Vec::new());
+        self.children.resize(base + (1 << stride), NO_CHILD);
+
+        self.expand(node, level, base, stride, 0, 0);
+        base as u32
+    }
+
+    fn expand(
+        &mut self,
+        node: &TrieNode<V>,
+        level: usize,
+        base: usize,
+        stride: u32,
+        depth: u32,
+        bits: usize,
+    ) {
+        if depth > 0 && !node.values().is_empty() {
+            let indices: Vec<u32> = self.push_values(node.values());
+            let span: usize = 1 << (stride - depth);
+            for list in self.lists[base + bits * span..base + (bits + 1) * span].iter_mut() {
+                list.extend(indices.iter());
+            }
+        }
+
+        if depth == stride {
+            if node.left().is_some() || node.right().is_some() {
+                let child: u32 = self.build_node(node, level + 1);
+                self.children[base + bits] = child;
+            }
+            return;
+        }
+
+        if let Some(l) = node.left() {
+            self.expand(l, level, base, stride, depth + 1, bits << 1);
+        }
+
+        if let Some(r) = node.right() {
+            self.expand(r, level, base, stride, depth + 1, (bits << 1) | 1);
+        }
+    }
+}
+
+/// Count the binary nodes at each depth that have at least one child, since
+/// only those start a new multibit node when placed on a level boundary.
+fn internal_nodes_per_depth<V>(node: &TrieNode<V>, depth: usize, counts: &mut [u64; 33]) {
+    if node.left().is_none() && node.right().is_none() {
+        return;
+    }
+
+    counts[depth] += 1;
+    for child in [node.left(), node.right()].into_iter().flatten() {
+        internal_nodes_per_depth(child, depth + 1, counts);
+    }
+}
+
+/// Choose strides minimizing the number of allocated entries, using at most one
+/// level per [`TARGET_STRIDE`] bits of the longest prefix.
+pub fn tune_strides<V>(trie: &Trie<V>) -> Vec<u8> {
+    let depth: usize = trie.max_depth() as usize;
+    if depth == 0 {
+        return Vec::new();
+    }
+
+    let mut counts: [u64; 33] = [0; 33];
+    internal_nodes_per_depth(trie.root(), 0, &mut counts);
+
+    let max_stride: usize = MAX_STRIDE as usize;
+    let levels: usize = (depth as u32)
+        .div_ceil(TARGET_STRIDE)
+        .max(depth.div_ceil(max_stride) as u32) as usize;
+
+    // cost[r][j] is the cheapest way of covering depths [0, j) with r levels,
+    // and start[r][j] the depth at which the last of those levels starts.
+    let mut cost: Vec<Vec<u64>> = vec![vec![u64::MAX; depth + 1]; levels + 1];
+    let mut start: Vec<Vec<usize>> = vec![vec![0; depth + 1]; levels + 1];
+    cost[0][0] = 0;
+
+    for r in 1..=levels {
+        for j in 1..=depth {
+            for m in j.saturating_sub(max_stride)..j {
+                if cost[r - 1][m] == u64::MAX {
+                    continue;
+                }
+
+                let c: u64 = cost[r - 1][m].saturating_add(counts[m].max(1) << (j - m));
+                if c < cost[r][j] {
+                    cost[r][j] = c;
+                    start[r][j] = m;
+                }
+            }
+        }
+    }
+
+    let levels: usize = (1..=levels).min_by_key(|r| cost[*r][depth]).unwrap();
+    let mut strides: Vec<u8> = Vec::with_capacity(levels);
+    let mut j: usize = depth;
+    for r in (1..=levels).rev() {
+        let m: usize = start[r][j];
+        strides.push((j - m) as u8);
+        j = m;
+    }
+
+    strides.reverse();
+    strides
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use std::net::Ipv4Addr;
+
+    fn random_trie(n: usize) -> Trie<u32> {
+        let mut rng = rand::rng();
+        let mut t: Trie<u32> = Trie::empty();
+        for i in 0..n {
+            t.insert_net_and_prefix(
+                rng.random::<u32>(),
+                rng.random_range(1u32..=32u32),
+                i as u32,
+            );
+        }
+        t
+    }
+
+    #[test]
+    fn lookups_match_binary_trie() {
+        let t = random_trie(2_000);
+        let mb = MultibitTrie::from_trie(&t);
+        assert_eq!(32, mb.strides().iter().map(|s| *s as u32).sum::<u32>());
+
+        let mut rng = rand::rng();
+        for _ in 0..20_000 {
+            let ip: u32 = rng.random();
+            assert_eq!(t.get(ip), mb.get(ip));
+        }
+    }
+
+    #[test]
+    fn explicit_strides() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.20.0.0/14", 2);
+        t.insert_cidr("10.20.30.0/24", 3);
+
+        let mb = MultibitTrie::with_strides(&t, &[16, 8]).unwrap();
+        assert_eq!(
+            vec![&1, &2, &3],
+            mb.get(u32::from_be_bytes([10, 20, 30, 40]))
+        );
+        assert_eq!(vec![&1, &2], mb.get(u32::from_be_bytes([10, 23, 0, 1])));
+        assert_eq!(vec![&1], mb.get(u32::from_be_bytes([10, 24, 0, 1])));
+        assert!(!mb.contains_ip(u32::from_be_bytes([11, 0, 0, 1])));
+        assert_eq!(vec![&1, &2, &3], mb.get(Ipv4Addr::new(10, 20, 30, 40)));
+
+        assert_eq!(
+            Err(StrideError::TooShort {
+                covered: 16,
+                required: 24
+            }),
+            MultibitTrie::with_strides(&t, &[8, 8]).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn tuned_strides_for_bgp_like_data() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let mut t: Trie<u32> = Trie::empty();
+        for i in 0..20_000 {
+            let prefix: u32 = if i % 10 == 0 { 16 } else { 24 };
+            t.insert_net_and_prefix(rng.random::<u32>(), prefix, i);
+        }
+
+        // The tuned strides cover every prefix length and allocate no more entries
+        // than the byte aligned strides, or a wide first level alone.
+        let strides = tune_strides(&t);
+        assert_eq!(24, strides.iter().map(|&s| s as u32).sum::<u32>());
+        let tuned: MultibitStats = MultibitTrie::from_trie(&t).stats();
+        assert_eq!(strides, tuned.strides);
+        for fixed in [&[8, 8, 8][..], &[16, 8]] {
+            let entries: usize = MultibitTrie::with_strides(&t, fixed)
+                .unwrap()
+                .stats()
+                .entries;
+            assert!(tuned.entries <= entries, "{:?}", fixed);
+        }
+    }
+}
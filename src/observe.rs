@@ -0,0 +1,206 @@
+//! Trie notifying subscribers of every mutation, so that dependent caches can
+//! invalidate exactly the affected prefixes.
+
+use crate::radix_trie::{CidrBlock, Trie, TrieError};
+
+use std::slice;
+
+/// A mutation of an observed trie.
+#[derive(Debug, Eq, PartialEq)]
+pub enum TrieEvent<'a, V> {
+    /// The value was inserted at the cidr block.
+    Inserted { cidr: CidrBlock, value: &'a V },
+    /// The values were removed from the cidr block.
+    Removed { cidr: CidrBlock, values: &'a [V] },
+    /// The values of the cidr block were replaced by a single new value.
+    Replaced {
+        cidr: CidrBlock,
+        old: &'a [V],
+        new: &'a V,
+    },
+}
+
+impl<V> TrieEvent<'_, V> {
+    /// Get the cidr block the event happened at.
+    pub fn cidr(&self) -> &CidrBlock {
+        match self {
+            TrieEvent::Inserted { cidr, .. }
+            | TrieEvent::Removed { cidr, .. }
+            | TrieEvent::Replaced { cidr, .. } => cidr,
+        }
+    }
+}
+
+/// Identifier of a subscription, used to unsubscribe.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SubscriptionId(u64);
+
+type Callback<V> = Box<dyn FnMut(&TrieEvent<'_, V>) + Send>;
+
+/// A trie invoking the callbacks of its subscribers on every mutation, in order of
+/// subscription, after the mutation was applied.
+pub struct ObservableTrie<V> {
+    trie: Trie<V>,
+    subscribers: Vec<(SubscriptionId, Callback<V>)>,
+    next_id: u64,
+}
+
+impl<V> ObservableTrie<V> {
+    /// Wrap the trie without any subscribers.
+    pub fn new(trie: Trie<V>) -> Self {
+        ObservableTrie {
+            trie,
+            subscribers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn trie(&self) -> &Trie<V> {
+        &self.trie
+    }
+
+    /// Unwrap the trie, dropping every subscriber.
+    pub fn into_inner(self) -> Trie<V> {
+        self.trie
+    }
+
+    /// Subscribe the callback to every following mutation.
+    pub fn subscribe(
+        &mut self,
+        callback: impl FnMut(&TrieEvent<'_, V>) + Send + 'static,
+    ) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscribers.push((id, Box::new(callback)));
+        id
+    }
+
+    /// Remove the subscription, returning whether or not it existed.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let before: usize = self.subscribers.len();
+        self.subscribers.retain(|(s, _)| *s != id);
+        self.subscribers.len() != before
+    }
+
+    fn notify(subscribers: &mut [(SubscriptionId, Callback<V>)], event: TrieEvent<'_, V>) {
+        for (_, callback) in subscribers.iter_mut() {
+            callback(&event);
+        }
+    }
+
+    /// Insert the value at the cidr block.
+    pub fn insert(&mut self, cidr: &CidrBlock, value: V) -> Result<(), TrieError> {
+        self.trie.try_insert(cidr, value)?;
+        let value: &V = self.trie.get_exact(cidr).last().unwrap();
+        Self::notify(
+            &mut self.subscribers,
+            TrieEvent::Inserted { cidr: *cidr, value },
+        );
+        Ok(())
+    }
+
+    /// Remove and return every value stored at exactly the cidr block. Subscribers are
+    /// only notified if the block held any values.
+    pub fn remove(&mut self, cidr: &CidrBlock) -> Vec<V> {
+        let values: Vec<V> = self.trie.remove(cidr);
+        if !values.is_empty() {
+            Self::notify(
+                &mut self.subscribers,
+                TrieEvent::Removed {
+                    cidr: *cidr,
+                    values: &values,
+                },
+            );
+        }
+        values
+    }
+
+    /// Replace every value stored at exactly the cidr block by the single value,
+    /// returning the replaced values.
+    pub fn replace(&mut self, cidr: &CidrBlock, value: V) -> Result<Vec<V>, TrieError> {
+        let old: Vec<V> = self
+            .trie
+            .update_exact(cidr, |values| std::mem::replace(values, vec![value]))?;
+        let new: &V = &self.trie.get_exact(cidr)[0];
+        Self::notify(
+            &mut self.subscribers,
+            TrieEvent::Replaced {
+                cidr: *cidr,
+                old: &old,
+                new,
+            },
+        );
+        Ok(old)
+    }
+}
+
+impl<V: PartialEq> ObservableTrie<V> {
+    /// Remove the most recently inserted equal value from exactly the cidr block,
+    /// returning whether or not such a value was found.
+    pub fn remove_value(&mut self, cidr: &CidrBlock, value: &V) -> bool {
+        let Some(i) = self.trie.get_exact(cidr).iter().rposition(|v| v == value) else {
+            return false;
+        };
+
+        let removed: V = self
+            .trie
+            .update_exact(cidr, |values| values.remove(i))
+            .unwrap();
+        Self::notify(
+            &mut self.subscribers,
+            TrieEvent::Removed {
+                cidr: *cidr,
+                values: slice::from_ref(&removed),
+            },
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn subscribers_see_mutations() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let events: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let mut t: ObservableTrie<u32> = ObservableTrie::new(Trie::empty());
+
+        let seen = Arc::clone(&events);
+        let id = t.subscribe(move |event| {
+            let event: String = match event {
+                TrieEvent::Inserted { cidr, value } => format!("+{} {}", cidr, value),
+                TrieEvent::Removed { cidr, values } => format!("-{} {:?}", cidr, values),
+                TrieEvent::Replaced { cidr, old, new } => format!("={} {:?} {}", cidr, old, new),
+            };
+            seen.lock().unwrap().push(event);
+        });
+
+        t.insert(&cidr("10.0.0.0/8"), 1).unwrap();
+        t.insert(&cidr("10.0.0.0/8"), 2).unwrap();
+        assert!(t.remove_value(&cidr("10.0.0.0/8"), &1));
+        assert!(!t.remove_value(&cidr("10.0.0.0/8"), &1));
+        assert_eq!(vec![2], t.replace(&cidr("10.0.0.0/8"), 3).unwrap());
+        assert!(t.remove(&cidr("10.1.0.0/16")).is_empty());
+        assert_eq!(vec![3], t.remove(&cidr("10.0.0.0/8")));
+
+        assert!(t.unsubscribe(id));
+        assert!(!t.unsubscribe(id));
+        t.insert(&cidr("10.0.0.0/8"), 4).unwrap();
+
+        assert_eq!(
+            vec![
+                "+10.0.0.0/8 1",
+                "+10.0.0.0/8 2",
+                "-10.0.0.0/8 [1]",
+                "=10.0.0.0/8 [2] 3",
+                "-10.0.0.0/8 [3]",
+            ],
+            *events.lock().unwrap()
+        );
+    }
+}
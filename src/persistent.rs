@@ -0,0 +1,277 @@
+//! Persistent trie, where every mutation returns a new trie sharing all unchanged
+//! nodes with the previous one. Old versions stay valid and cheap to keep around,
+//! e.g. as snapshots for lock-free readers or for time-travel debugging.
+
+use crate::radix_trie::{CidrBlock, IntoIpKey, Trie, TrieError};
+
+use std::sync::Arc;
+
+struct Node<V> {
+    l: Option<Arc<Node<V>>>,
+    r: Option<Arc<Node<V>>>,
+    v: Option<Arc<[V]>>,
+}
+
+impl<V> Node<V> {
+    fn empty() -> Self {
+        Node {
+            l: None,
+            r: None,
+            v: None,
+        }
+    }
+
+    fn values(&self) -> &[V] {
+        self.v.as_deref().unwrap_or_default()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.v.is_none() && self.l.is_none() && self.r.is_none()
+    }
+
+    fn child(&self, ip: u32, depth: u32) -> Option<&Arc<Node<V>>> {
+        if ((1u32 << (31 - depth)) & ip) == 0 {
+            self.l.as_ref()
+        } else {
+            self.r.as_ref()
+        }
+    }
+}
+
+// Cloning a node only clones the pointers to its children and values.
+impl<V> Clone for Node<V> {
+    fn clone(&self) -> Self {
+        Node {
+            l: self.l.clone(),
+            r: self.r.clone(),
+            v: self.v.clone(),
+        }
+    }
+}
+
+/// A persistent trie of values, see the module documentation.
+pub struct PersistentTrie<V> {
+    root: Arc<Node<V>>,
+}
+
+impl<V> Clone for PersistentTrie<V> {
+    fn clone(&self) -> Self {
+        PersistentTrie {
+            root: Arc::clone(&self.root),
+        }
+    }
+}
+
+impl<V> PersistentTrie<V> {
+    /// Create a new empty trie.
+    pub fn empty() -> Self {
+        PersistentTrie {
+            root: Arc::new(Node::empty()),
+        }
+    }
+
+    /// Get the values associated with the provided ip address.
+    pub fn get<K: IntoIpKey>(&self, ip: K) -> Vec<&V> {
+        let mut buffer: Vec<&V> = Vec::new();
+        let Some(ip) = ip.into_ip_key() else {
+            return buffer;
+        };
+
+        let mut node: &Node<V> = &self.root;
+        for depth in 0..=32 {
+            buffer.extend(node.values());
+            match (depth < 32).then(|| node.child(ip, depth)).flatten() {
+                Some(n) => node = n,
+                None => break,
+            }
+        }
+        buffer
+    }
+
+    /// Get the values stored at exactly the cidr block.
+    pub fn get_exact(&self, cidr: &CidrBlock) -> &[V] {
+        let mut node: &Node<V> = &self.root;
+        for depth in 0..cidr.prefix.min(32) {
+            match node.child(cidr.net, depth) {
+                Some(n) => node = n,
+                None => return &[],
+            }
+        }
+        node.values()
+    }
+
+    /// Get whether or not both tries are the same version, i.e. share their root.
+    pub fn ptr_eq(&self, other: &PersistentTrie<V>) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+}
+
+impl<V: Clone> PersistentTrie<V> {
+    /// Get a new trie with the value inserted at the cidr block. Only the nodes on
+    /// the path to the block are copied, every other node is shared.
+    pub fn insert(&self, cidr: &CidrBlock, value: V) -> Result<Self, TrieError> {
+        if cidr.prefix > 32 {
+            return Err(TrieError::InvalidPrefix(cidr.prefix));
+        }
+
+        Ok(PersistentTrie {
+            root: insert(Some(&self.root), cidr, 0, value),
+        })
+    }
+
+    /// Get a new trie without any of the values stored at exactly the cidr block.
+    /// The new trie is the same version as this one if the block held no values.
+    pub fn remove(&self, cidr: &CidrBlock) -> Self {
+        match remove(&self.root, cidr, 0) {
+            Some(root) => PersistentTrie {
+                root: root.unwrap_or_else(|| Arc::new(Node::empty())),
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// Build a persistent trie holding the same values at the same cidr blocks.
+    pub fn from_trie(trie: &Trie<V>) -> Self {
+        trie.iter()
+            .flat_map(|(cidr, values)| values.iter().map(move |v| (cidr, v)))
+            .fold(PersistentTrie::empty(), |t, (cidr, v)| {
+                t.insert(&cidr, v.clone()).unwrap()
+            })
+    }
+
+    /// Build a mutable trie holding the same values at the same cidr blocks.
+    pub fn to_trie(&self) -> Trie<V> {
+        let mut trie: Trie<V> = Trie::empty();
+        collect(&self.root, 0, 0, &mut trie);
+        trie
+    }
+}
+
+impl<V> Default for PersistentTrie<V> {
+    fn default() -> Self {
+        PersistentTrie::empty()
+    }
+}
+
+fn insert<V: Clone>(
+    node: Option<&Arc<Node<V>>>,
+    cidr: &CidrBlock,
+    depth: u32,
+    value: V,
+) -> Arc<Node<V>> {
+    let mut copy: Node<V> = node.map_or_else(Node::empty, |n| Node::clone(n));
+
+    if depth == cidr.prefix {
+        let mut values: Vec<V> = copy.values().to_vec();
+        values.push(value);
+        copy.v = Some(values.into());
+    } else {
+        let child: Option<&Arc<Node<V>>> = node.and_then(|n| n.child(cidr.net, depth));
+        let child: Arc<Node<V>> = insert(child, cidr, depth + 1, value);
+        if ((1u32 << (31 - depth)) & cidr.net) == 0 {
+            copy.l = Some(child);
+        } else {
+            copy.r = Some(child);
+        }
+    }
+
+    Arc::new(copy)
+}
+
+/// Get the copy of the node without the values of the block, `None` if the block
+/// holds no values and `Some(None)` if the copy would be empty.
+fn remove<V>(node: &Arc<Node<V>>, cidr: &CidrBlock, depth: u32) -> Option<Option<Arc<Node<V>>>> {
+    let mut copy: Node<V> = Node::clone(node);
+
+    if depth == cidr.prefix.min(32) {
+        copy.v.take()?;
+    } else {
+        let child: Option<Arc<Node<V>>> = remove(node.child(cidr.net, depth)?, cidr, depth + 1)?;
+        if ((1u32 << (31 - depth)) & cidr.net) == 0 {
+            copy.l = child;
+        } else {
+            copy.r = child;
+        }
+    }
+
+    Some((!copy.is_empty()).then(|| Arc::new(copy)))
+}
+
+fn collect<V: Clone>(node: &Node<V>, net: u32, depth: u32, trie: &mut Trie<V>) {
+    let cidr = CidrBlock { net, prefix: depth };
+    for value in node.values() {
+        trie.try_insert(&cidr, value.clone()).unwrap();
+    }
+
+    if let Some(n) = &node.l {
+        collect(n, net, depth + 1, trie);
+    }
+
+    if let Some(n) = &node.r {
+        collect(n, net | (1u32 << (31 - depth)), depth + 1, trie);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    fn cidr(s: &str) -> CidrBlock {
+        CidrBlock::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn versions_share_unchanged_nodes() {
+        let v0: PersistentTrie<u32> = PersistentTrie::empty();
+        let v1 = v0.insert(&cidr("10.0.0.0/8"), 1).unwrap();
+        let v2 = v1.insert(&cidr("192.168.0.0/16"), 2).unwrap();
+        let v3 = v2.insert(&cidr("10.1.0.0/16"), 3).unwrap();
+
+        let ip = Ipv4Addr::new(10, 1, 2, 3);
+        assert!(v0.get(ip).is_empty());
+        assert_eq!(vec![&1], v1.get(ip));
+        assert_eq!(vec![&1], v2.get(ip));
+        assert_eq!(vec![&1, &3], v3.get(ip));
+
+        // The 192.168.0.0/16 branch is untouched by the insert of 10.1.0.0/16.
+        assert!(Arc::ptr_eq(
+            v2.root.r.as_ref().unwrap(),
+            v3.root.r.as_ref().unwrap()
+        ));
+
+        let v4 = v3.remove(&cidr("10.0.0.0/8"));
+        assert_eq!(vec![&3], v4.get(ip));
+        assert_eq!(vec![&1, &3], v3.get(ip));
+        assert!(v4.remove(&cidr("10.0.0.0/8")).ptr_eq(&v4));
+        assert!(v4.remove(&cidr("10.1.2.0/24")).ptr_eq(&v4));
+
+        let v5 = v4
+            .remove(&cidr("10.1.0.0/16"))
+            .remove(&cidr("192.168.0.0/16"));
+        assert!(v5.root.is_empty());
+        assert_eq!(
+            Err(TrieError::InvalidPrefix(33)),
+            v5.insert(&CidrBlock { net: 0, prefix: 33 }, 0).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn convert_from_and_to_trie() {
+        let mut trie: Trie<u32> = Trie::empty();
+        trie.insert_cidr("0.0.0.0/0", 0);
+        trie.insert_cidr("10.0.0.0/8", 1);
+        trie.insert_cidr("10.0.0.0/8", 2);
+        trie.insert_cidr("10.1.2.3/32", 3);
+
+        let persistent = PersistentTrie::from_trie(&trie);
+        assert_eq!(&[1, 2], persistent.get_exact(&cidr("10.0.0.0/8")));
+        assert_eq!(
+            trie.get(Ipv4Addr::new(10, 1, 2, 3)),
+            persistent.get(Ipv4Addr::new(10, 1, 2, 3))
+        );
+        assert!(trie.content_eq(&persistent.to_trie()));
+    }
+}
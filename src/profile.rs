@@ -0,0 +1,192 @@
+//! Sampled profiling of lookup traffic, to tune strides and caches with real traffic
+//! rather than synthetic benchmarks.
+
+use crate::radix_trie::{CidrBlock, IntoIpKey, Trie};
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A single sampled lookup.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sample {
+    pub ip: u32,
+    /// Most specific block the ip matched, if any.
+    pub matched: Option<CidrBlock>,
+    /// Depth the lookup descended to, see [`Trie::walk_depth`].
+    pub depth: u32,
+    pub latency: Duration,
+}
+
+/// Summary of the sampled lookups.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileReport {
+    /// Number of lookups, sampled or not.
+    pub lookups: u64,
+    /// Number of samples held in the ring buffer.
+    pub samples: usize,
+    /// Number of samples that matched no block.
+    pub unmatched: usize,
+    pub average_depth: f64,
+    pub average_latency: Duration,
+    /// Number of samples per covering block of the sampled ips, most sampled first.
+    pub hot: Vec<(CidrBlock, usize)>,
+}
+
+/// A trie recording one in every `every` lookups into a ring buffer of the most
+/// recent `capacity` samples. Lookups that are not sampled only bump a counter.
+pub struct ProfiledTrie<V> {
+    trie: Trie<V>,
+    every: u64,
+    capacity: usize,
+    lookups: AtomicU64,
+    samples: Mutex<VecDeque<Sample>>,
+}
+
+impl<V> ProfiledTrie<V> {
+    /// Wrap the trie, sampling one in every `every` lookups, clamped to at least one.
+    pub fn new(trie: Trie<V>, every: u64, capacity: usize) -> Self {
+        ProfiledTrie {
+            trie,
+            every: every.max(1),
+            capacity,
+            lookups: AtomicU64::new(0),
+            samples: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn trie(&self) -> &Trie<V> {
+        &self.trie
+    }
+
+    /// Unwrap the trie, dropping every sample.
+    pub fn into_inner(self) -> Trie<V> {
+        self.trie
+    }
+
+    /// Get the values associated with the provided ip address.
+    pub fn get<K: IntoIpKey>(&self, ip: K) -> Vec<&V> {
+        let Some(ip) = ip.into_ip_key() else {
+            return Vec::new();
+        };
+
+        if !self
+            .lookups
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.every)
+        {
+            return self.trie.get(ip);
+        }
+
+        let start = Instant::now();
+        let values: Vec<&V> = self.trie.get(ip);
+        let latency: Duration = start.elapsed();
+
+        let sample = Sample {
+            ip,
+            matched: self.trie.get_blocks(ip).last().map(|(cidr, _)| *cidr),
+            depth: self.trie.walk_depth(ip),
+            latency,
+        };
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        if self.capacity > 0 {
+            samples.push_back(sample);
+        }
+
+        values
+    }
+
+    /// Get the samples held in the ring buffer, from the oldest to the most recent.
+    pub fn samples(&self) -> Vec<Sample> {
+        self.samples.lock().unwrap().iter().copied().collect()
+    }
+
+    /// Summarize the samples held in the ring buffer, counting hot subtrees by the
+    /// covering block of `hot_prefix` bits of the sampled ips.
+    pub fn profile_report(&self, hot_prefix: u32) -> ProfileReport {
+        let samples = self.samples.lock().unwrap();
+        let n: usize = samples.len();
+
+        let mut hot: HashMap<CidrBlock, usize> = HashMap::new();
+        for sample in samples.iter() {
+            let cidr = CidrBlock {
+                net: sample.ip,
+                prefix: 32,
+            };
+            *hot.entry(cidr.anonymized(hot_prefix)).or_default() += 1;
+        }
+        let mut hot: Vec<(CidrBlock, usize)> = hot.into_iter().collect();
+        hot.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let depth: u64 = samples.iter().map(|s| s.depth as u64).sum();
+        let latency: Duration = samples.iter().map(|s| s.latency).sum();
+        ProfileReport {
+            lookups: self.lookups.load(Ordering::Relaxed),
+            samples: n,
+            unmatched: samples.iter().filter(|s| s.matched.is_none()).count(),
+            average_depth: if n == 0 { 0.0 } else { depth as f64 / n as f64 },
+            average_latency: latency / n.max(1) as u32,
+            hot,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    #[test]
+    fn samples_and_report() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut trie: Trie<u32> = Trie::empty();
+        trie.insert_cidr("10.0.0.0/8", 1);
+        trie.insert_cidr("10.1.0.0/16", 2);
+
+        let profiled: ProfiledTrie<u32> = ProfiledTrie::new(trie, 2, 3);
+        for ip in [
+            Ipv4Addr::new(10, 1, 0, 1),
+            Ipv4Addr::new(10, 1, 0, 2),
+            Ipv4Addr::new(10, 1, 0, 3),
+            Ipv4Addr::new(10, 1, 0, 4),
+            Ipv4Addr::new(192, 168, 0, 1),
+            Ipv4Addr::new(192, 168, 0, 2),
+            Ipv4Addr::new(10, 2, 0, 1),
+            Ipv4Addr::new(10, 2, 0, 2),
+            Ipv4Addr::new(10, 1, 9, 9),
+        ] {
+            assert_eq!(profiled.trie().get(ip), profiled.get(ip));
+        }
+
+        // Every other lookup is sampled, the oldest sample was evicted.
+        let samples: Vec<Sample> = profiled.samples();
+        assert_eq!(
+            vec![
+                u32::from(Ipv4Addr::new(192, 168, 0, 1)),
+                u32::from(Ipv4Addr::new(10, 2, 0, 1)),
+                u32::from(Ipv4Addr::new(10, 1, 9, 9)),
+            ],
+            samples.iter().map(|s| s.ip).collect::<Vec<u32>>()
+        );
+        assert_eq!(
+            vec![None, Some(cidr("10.0.0.0/8")), Some(cidr("10.1.0.0/16"))],
+            samples.iter().map(|s| s.matched).collect::<Vec<_>>()
+        );
+
+        let report: ProfileReport = profiled.profile_report(8);
+        assert_eq!(9, report.lookups);
+        assert_eq!(3, report.samples);
+        assert_eq!(1, report.unmatched);
+        assert_eq!((0.0 + 14.0 + 16.0) / 3.0, report.average_depth);
+        assert_eq!(
+            vec![(cidr("10.0.0.0/8"), 2), (cidr("192.0.0.0/8"), 1)],
+            report.hot
+        );
+    }
+}
@@ -1,12 +1,22 @@
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
 use bincode::{Decode, Encode, config};
+use rayon::ThreadPoolBuilder;
+use rayon::prelude::*;
+
+use crate::util::{self, FNV_OFFSET_BASIS, fnv1a};
 
 use std::error::Error;
-use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter};
-use std::net::Ipv4Addr;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::Index;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 
-#[derive(Debug, Decode, Encode, Eq, PartialEq)]
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
 pub struct TrieNode<V> {
     l: Option<Box<TrieNode<V>>>,
     r: Option<Box<TrieNode<V>>>,
@@ -36,6 +46,48 @@ impl<V> TrieNode<V> {
         }
     }
 
+    /// Get the child node whose next bit is 0, if any.
+    pub fn left(&self) -> Option<&TrieNode<V>> {
+        self.l.as_deref()
+    }
+
+    /// Get the child node whose next bit is 1, if any.
+    pub fn right(&self) -> Option<&TrieNode<V>> {
+        self.r.as_deref()
+    }
+
+    /// Get the values stored directly at this node.
+    pub fn values(&self) -> &[V] {
+        self.v.as_deref().unwrap_or(&[])
+    }
+
+    /// Get the height of the subtree rooted at this node, i.e. the number of edges
+    /// on the longest path down to a leaf.
+    pub fn height(&self) -> u32 {
+        let l: u32 = self.left().map_or(0, |n| 1 + n.height());
+        let r: u32 = self.right().map_or(0, |n| 1 + n.height());
+        l.max(r)
+    }
+
+    /// Get the length of the longest chain of nodes in the subtree that have a single
+    /// child and no values, together with the length of the chain ending at this node.
+    fn chains(&self) -> (u32, u32) {
+        let (mut longest, mut ending): (u32, u32) = (0, 0);
+        for n in [self.left(), self.right()].into_iter().flatten() {
+            let (l, e) = n.chains();
+            longest = longest.max(l);
+            ending = ending.max(e);
+        }
+
+        let single_child: bool = self.l.is_some() != self.r.is_some();
+        let ending: u32 = if single_child && !self.has_values() {
+            ending + 1
+        } else {
+            0
+        };
+        (longest.max(ending), ending)
+    }
+
     fn insert(&mut self, ip: u32, mask: u32, value: V) {
         if mask == 0 {
             if let Some(v) = &mut self.v {
@@ -79,14 +131,277 @@ impl<V> TrieNode<V> {
             n.get(ip << 1, mask << 1, buffer);
         }
     }
+
+    /// Get the cidr block and values of every node on the path of ip holding values.
+    fn blocks<'a>(&'a self, ip: u32, depth: u32, buffer: &mut Vec<(CidrBlock, &'a [V])>) {
+        if self.has_values() {
+            let net: u32 = ip & prefix_mask(depth);
+            buffer.push((CidrBlock { net, prefix: depth }, self.values()));
+        }
+
+        if depth == 32 {
+            return;
+        }
+
+        if let Some(n) = if ((1u32 << (31 - depth)) & ip) == 0 {
+            &self.l
+        } else {
+            &self.r
+        } {
+            n.blocks(ip, depth + 1, buffer);
+        }
+    }
+
+    /// Get the node reached by following the first `depth` bits of ip, if any.
+    fn find(&self, ip: u32, depth: u32) -> Option<&TrieNode<V>> {
+        (0..depth).try_fold(self, |node, i| {
+            if ((1u32 << (31 - i)) & ip) == 0 {
+                node.left()
+            } else {
+                node.right()
+            }
+        })
+    }
+
+    /// Get the values of the deepest node on the path of ip that holds any values.
+    fn longest<'a>(&'a self, ip: u32, mask: u32, longest: &mut &'a [V]) {
+        if self.has_values() {
+            *longest = self.values();
+        }
+
+        if mask == 0 {
+            return;
+        }
+
+        if let Some(n) = if ((1u32 << 31) & ip) == 0 {
+            &self.l
+        } else {
+            &self.r
+        } {
+            n.longest(ip << 1, mask << 1, longest);
+        }
+    }
+
+    fn has_values(&self) -> bool {
+        self.v.as_ref().is_some_and(|v| !v.is_empty())
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.l.is_none() && self.r.is_none()
+    }
+
+    /// Drop empty value lists and prune children that hold no values, returning
+    /// whether this node can itself be pruned.
+    fn compact(&mut self) -> bool {
+        if !self.has_values() {
+            self.v = None;
+        }
+
+        if self.l.as_mut().is_some_and(|n| n.compact()) {
+            self.l = None;
+        }
+
+        if self.r.as_mut().is_some_and(|n| n.compact()) {
+            self.r = None;
+        }
+
+        self.v.is_none() && self.is_leaf()
+    }
+
+    /// Build a new subtree with the values returned by `f`, pruning branches left
+    /// without any values.
+    fn filter_map<U, F>(&self, net: u32, depth: u32, f: &mut F) -> Option<TrieNode<U>>
+    where
+        F: FnMut(&CidrBlock, &V) -> Option<U>,
+    {
+        let cidr = CidrBlock { net, prefix: depth };
+        let values: Vec<U> = self.values().iter().filter_map(|v| f(&cidr, v)).collect();
+
+        let node = TrieNode {
+            l: self
+                .left()
+                .and_then(|n| n.filter_map(net, depth + 1, f))
+                .map(Box::new),
+            r: self
+                .right()
+                .and_then(|n| n.filter_map(net | (1u32 << (31 - depth)), depth + 1, f))
+                .map(Box::new),
+            v: Some(values).filter(|v| !v.is_empty()),
+        };
+
+        if node.v.is_none() && node.is_leaf() {
+            None
+        } else {
+            Some(node)
+        }
+    }
+
+    /// Get the node reached by following the first `depth` bits of ip mutably, if any.
+    fn find_mut(&mut self, ip: u32, depth: u32) -> Option<&mut TrieNode<V>> {
+        let mut node: &mut TrieNode<V> = self;
+        for i in 0..depth {
+            node = if ((1u32 << (31 - i)) & ip) == 0 {
+                node.l.as_deref_mut()?
+            } else {
+                node.r.as_deref_mut()?
+            };
+        }
+        Some(node)
+    }
+
+    /// Apply `f` to the node reached by following the first `depth` bits of ip, if
+    /// any, pruning the nodes on the path that are left without values or children.
+    fn update<R, F>(&mut self, ip: u32, depth: u32, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut TrieNode<V>) -> R,
+    {
+        if depth == 0 {
+            let result: R = f(self);
+            if !self.has_values() {
+                self.v = None;
+            }
+            return Some(result);
+        }
+
+        let next_node: &mut Option<Box<TrieNode<V>>> = if ((1u32 << 31) & ip) == 0 {
+            &mut self.l
+        } else {
+            &mut self.r
+        };
+
+        let n = next_node.as_mut()?;
+        let result: Option<R> = n.update(ip << 1, depth - 1, f);
+        if n.v.is_none() && n.is_leaf() {
+            *next_node = None;
+        }
+        result
+    }
+
+    /// Drop the values rejected by `f`, returning the number of values dropped.
+    fn retain<F>(&mut self, net: u32, depth: u32, f: &mut F) -> usize
+    where
+        F: FnMut(&CidrBlock, &V) -> bool,
+    {
+        let mut dropped: usize = 0;
+        if let Some(values) = &mut self.v {
+            let cidr = CidrBlock { net, prefix: depth };
+            let before: usize = values.len();
+            values.retain(|v| f(&cidr, v));
+            dropped += before - values.len();
+        }
+
+        if let Some(n) = &mut self.l {
+            dropped += n.retain(net, depth + 1, f);
+        }
+
+        if let Some(n) = &mut self.r {
+            dropped += n.retain(net | (1u32 << (31 - depth)), depth + 1, f);
+        }
+
+        dropped
+    }
+
+    /// Move all values and children of the other node into this node, appending
+    /// values after the ones already stored here.
+    fn merge(&mut self, other: TrieNode<V>) {
+        if let Some(values) = other.v {
+            match &mut self.v {
+                Some(v) => v.extend(values),
+                None => self.v = Some(values),
+            }
+        }
+
+        for (own, theirs) in [(&mut self.l, other.l), (&mut self.r, other.r)] {
+            match (own, theirs) {
+                (Some(n), Some(o)) => n.merge(*o),
+                (own @ None, Some(o)) => *own = Some(o),
+                _ => {}
+            }
+        }
+    }
+
+    /// Merge the subtree into the node reached by following the first `depth` bits of ip.
+    fn graft(&mut self, ip: u32, depth: u32, subtree: TrieNode<V>) {
+        if depth == 0 {
+            self.merge(subtree);
+            return;
+        }
+
+        let next_node: &mut Option<Box<TrieNode<V>>> = if ((1u32 << 31) & ip) == 0 {
+            &mut self.l
+        } else {
+            &mut self.r
+        };
+
+        next_node
+            .get_or_insert_with(|| Box::new(TrieNode::empty()))
+            .graft(ip << 1, depth - 1, subtree);
+    }
+}
+
+impl<V: PartialEq> TrieNode<V> {
+    /// Merge sibling leaves holding identical values into this node.
+    fn aggregate(&mut self) {
+        if let Some(n) = &mut self.l {
+            n.aggregate();
+        }
+
+        if let Some(n) = &mut self.r {
+            n.aggregate();
+        }
+
+        let mergeable = match (&self.l, &self.r) {
+            (Some(l), Some(r)) => l.is_leaf() && r.is_leaf() && l.has_values() && l.v == r.v,
+            _ => false,
+        };
+
+        if mergeable {
+            let values = self.l.take().unwrap().v.unwrap();
+            self.r = None;
+            match &mut self.v {
+                Some(v) => v.extend(values),
+                None => self.v = Some(values),
+            }
+        }
+    }
+
+    /// Move the values of every node `depth` levels below this node into it and drop
+    /// those nodes, keeping only one of equal values.
+    fn truncate(&mut self, depth: u32) {
+        if depth > 0 {
+            for n in [&mut self.l, &mut self.r].into_iter().flatten() {
+                n.truncate(depth - 1);
+            }
+            return;
+        }
+
+        let mut values: Vec<V> = self.v.take().unwrap_or_default();
+        for n in [self.l.take(), self.r.take()].into_iter().flatten() {
+            n.drain_unique(&mut values);
+        }
+        self.v = Some(values).filter(|v| !v.is_empty());
+    }
+
+    /// Append the values of this subtree in preorder that are not yet in `values`.
+    fn drain_unique(self, values: &mut Vec<V>) {
+        for value in self.v.into_iter().flatten() {
+            if !values.contains(&value) {
+                values.push(value);
+            }
+        }
+
+        for n in [self.l, self.r].into_iter().flatten() {
+            n.drain_unique(values);
+        }
+    }
 }
 
-#[derive(Debug, Decode, Encode, Eq, PartialEq)]
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
 pub struct Trie<V> {
     root: TrieNode<V>,
 }
 
-impl<V: Decode<()> + Encode> Trie<V> {
+impl<V> Trie<V> {
     /// Create a new empty trie.
     pub fn empty() -> Self {
         Trie {
@@ -107,193 +422,2683 @@ impl<V: Decode<()> + Encode> Trie<V> {
     /// Insert a new cidr block with corresponding value to the trie.
     pub fn insert_cidr(&mut self, cidr: &str, value: V) {
         let cidr_block = CidrBlock::from_str(cidr).unwrap();
-        let mask: u32 = 0xffffffffu32 << (32 - cidr_block.prefix);
+        let mask: u32 = prefix_mask(cidr_block.prefix);
         self.root.insert(cidr_block.net, mask, value);
     }
 
     /// Insert a new cidr block by its net and prefix values.
-    pub fn insert_net_and_prefix(&mut self, net: u32, prefix: u32, value: V) {
-        let mask: u32 = 0xffffffffu32 << (32 - prefix);
+    ///
+    /// Panics if the net is not an IPv4 address, see [`IntoIpKey`].
+    pub fn insert_net_and_prefix<K: IntoIpKey>(&mut self, net: K, prefix: u32, value: V) {
+        let net: u32 = net.into_ip_key().expect("net is not an IPv4 address");
+        let mask: u32 = prefix_mask(prefix);
         self.root.insert(net, mask, value);
     }
 
+    /// Insert a new cidr block with corresponding value to the trie, failing
+    /// instead of panicking if the block is invalid.
+    pub fn try_insert(&mut self, cidr: &CidrBlock, value: V) -> Result<(), TrieError> {
+        if cidr.prefix > 32 {
+            return Err(TrieError::InvalidPrefix(cidr.prefix));
+        }
+
+        self.root.insert(cidr.net, prefix_mask(cidr.prefix), value);
+        Ok(())
+    }
+
+    /// Insert every cidr block and value pair, continuing past invalid blocks and
+    /// reporting them by their position in the input.
+    pub fn insert_many<I>(&mut self, items: I) -> InsertSummary
+    where
+        I: IntoIterator<Item = (CidrBlock, V)>,
+    {
+        let mut summary = InsertSummary::default();
+        for (i, (cidr, value)) in items.into_iter().enumerate() {
+            match self.try_insert(&cidr, value) {
+                Ok(()) => summary.inserted += 1,
+                Err(e) => summary.errors.push((i, e)),
+            }
+        }
+        summary
+    }
+
+    /// Like [`Trie::insert_many`], but sorts the blocks by network and prefix first
+    /// so that consecutive inserts walk mostly the same nodes. Values for the same
+    /// block keep their relative input order.
+    pub fn insert_many_sorted<I>(&mut self, items: I) -> InsertSummary
+    where
+        I: IntoIterator<Item = (CidrBlock, V)>,
+    {
+        let mut items: Vec<(usize, CidrBlock, V)> = items
+            .into_iter()
+            .enumerate()
+            .map(|(i, (cidr, value))| (i, cidr, value))
+            .collect();
+        items
+            .sort_by_key(|(_, cidr, _)| (cidr.net & prefix_mask(cidr.prefix.min(32)), cidr.prefix));
+
+        let mut summary = InsertSummary::default();
+        for (i, cidr, value) in items.into_iter() {
+            match self.try_insert(&cidr, value) {
+                Ok(()) => summary.inserted += 1,
+                Err(e) => summary.errors.push((i, e)),
+            }
+        }
+        summary.errors.sort_by_key(|(i, _)| *i);
+        summary
+    }
+
     /// Get the values associated with the provided ip address.
-    pub fn get(&self, ip: u32) -> Vec<&V> {
+    pub fn get<K: IntoIpKey>(&self, ip: K) -> Vec<&V> {
         let mut buffer: Vec<&V> = Vec::with_capacity(32);
-        self.root.get(ip, 0xffffffffu32, &mut buffer);
+        if let Some(ip) = ip.into_ip_key() {
+            self.root.get(ip, 0xffffffffu32, &mut buffer);
+        }
         buffer
     }
 
-    /// Get whether or not the trie contains the provided ip address.
-    pub fn contains_ip(&self, ip: u32) -> bool {
-        let mut buffer: Vec<&V> = Vec::with_capacity(32);
-        self.root.get(ip, 0xffffffffu32, &mut buffer);
-        buffer.len() != 0
+    /// Get every cidr block containing the provided ip address together with its
+    /// values, from the least to the most specific block.
+    pub fn get_blocks<K: IntoIpKey>(&self, ip: K) -> Vec<(CidrBlock, &[V])> {
+        let mut buffer: Vec<(CidrBlock, &[V])> = Vec::new();
+        if let Some(ip) = ip.into_ip_key() {
+            self.root.blocks(ip, 0, &mut buffer);
+        }
+        buffer
     }
 
-    /// Initialize a Trie instance that was saved to a binary file.
-    pub fn read_from_file(path: &str) -> Self {
-        let config: config::Configuration = config::standard();
-        let file: File = match OpenOptions::new().read(true).write(false).open(path) {
-            Ok(f) => f,
-            Err(_) => {
-                println!("{} did not exist, creating an empty Trie...", path);
-                return Trie::empty();
+    /// Get the block closest to the provided ip address, together with its distance
+    /// in bits: 32 minus the number of leading bits the ip address shares with the
+    /// network of the block, or 0 if the block contains the ip address. This is the
+    /// bit length of their xor, ignoring the host bits of the block, so the closest
+    /// blocks are the ones sharing the most leading bits with the ip address, e.g. a
+    /// sibling /24 of the /24 of the ip is at distance 9.
+    ///
+    /// Among blocks at the same distance the least specific one is returned, the
+    /// first by network if several are equally specific, except that the most
+    /// specific block containing the ip address is returned if there is any.
+    pub fn nearest_match<K: IntoIpKey>(&self, ip: K) -> Option<(CidrBlock, u8)> {
+        let ip: u32 = ip.into_ip_key()?;
+        if let Some((cidr, _)) = self.get_blocks(ip).last() {
+            return Some((*cidr, 0));
+        }
+
+        let mut path: Vec<&TrieNode<V>> = vec![&self.root];
+        while let Some(node) = path.last().filter(|_| path.len() <= 32) {
+            let next = if ((1u32 << (32 - path.len())) & ip) == 0 {
+                node.left()
+            } else {
+                node.right()
+            };
+            match next {
+                Some(n) => path.push(n),
+                None => break,
             }
-        };
+        }
 
-        let mut reader: BufReader<File> = BufReader::new(file);
-        bincode::decode_from_std_read(&mut reader, config).unwrap()
-    }
+        // The blocks in the branch off the path at some depth share exactly that many
+        // leading bits with the ip, so the deepest branch holding any block wins.
+        for (depth, node) in (0..path.len() as u32).zip(path).rev() {
+            if depth == 32 {
+                continue;
+            }
+            let bit: u32 = (ip >> (31 - depth)) & 1;
+            let Some(branch) = (if bit == 0 { node.right() } else { node.left() }) else {
+                continue;
+            };
 
-    /// Write the state of the Trie to binary file.
-    pub fn write_to_file(&self, path: &str) {
-        let config: config::Configuration = config::standard();
-        let file: File = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(path)
-            .unwrap();
+            let net: u32 = (ip & prefix_mask(depth)) | ((bit ^ 1) << (31 - depth));
+            if let Some(cidr) = shallowest_block(branch, net, depth + 1, 32) {
+                return Some((cidr, (32 - depth) as u8));
+            }
+        }
 
-        let mut writer: BufWriter<File> = BufWriter::new(file);
-        bincode::encode_into_std_write(&self, &mut writer, config).unwrap();
+        None
     }
-}
-
-pub struct CidrBlock {
-    pub net: u32,
-    pub prefix: u32,
-}
-
-impl FromStr for CidrBlock {
-    type Err = Box<dyn Error>;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<&str> = s.splitn(2, "/").collect();
 
-        let net: Ipv4Addr = parts[0].parse()?;
-        let prefix: u32 = parts[1].parse()?;
+    /// Get every cidr block overlapping the provided cidr block together with its
+    /// values, i.e. the blocks containing it from the least to the most specific,
+    /// followed by the block itself and the blocks inside it ordered by network.
+    pub fn overlapping(&self, cidr: &CidrBlock) -> Vec<(CidrBlock, &[V])> {
+        let prefix: u32 = cidr.prefix.min(32);
+        let net: u32 = cidr.net & prefix_mask(prefix);
 
-        Ok(CidrBlock {
-            net: net.into(),
-            prefix,
-        })
+        let mut buffer: Vec<(CidrBlock, &[V])> = self.get_blocks(net);
+        buffer.retain(|(c, _)| c.prefix < prefix);
+        if let Some(node) = self.root.find(net, prefix) {
+            buffer.extend(Iter {
+                stack: vec![(node, net, prefix)],
+            });
+        }
+        buffer
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Get the values stored at exactly the cidr block, not including the values of
+    /// less or more specific blocks.
+    pub fn get_exact(&self, cidr: &CidrBlock) -> &[V] {
+        self.root
+            .find(cidr.net, cidr.prefix.min(32))
+            .map_or(&[], TrieNode::values)
+    }
 
-    #[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
-    struct TestDummyStruct {
-        pub a: u32,
-        pub b: bool,
-        pub c: String,
+    /// Get the depth of the deepest node of the trie.
+    pub fn max_depth(&self) -> u32 {
+        self.root.height()
     }
 
-    #[test]
-    fn trie_with_custom_struct() {
-        let mut t: Trie<TestDummyStruct> = Trie::empty();
-        let tds = TestDummyStruct {
-            a: 23,
-            b: true,
-            c: "Hello Radix Trie!".to_string(),
-        };
-        t.insert_net_and_prefix(Ipv4Addr::new(183, 40, 20, 0).into(), 8, tds.clone());
-        assert_eq!(vec![&tds], t.get(Ipv4Addr::new(183, 41, 24, 249).into()));
-        assert_eq!(23, t.get(Ipv4Addr::new(183, 41, 24, 249).into())[0].a);
+    /// Get the depth of the node of the cidr block, or `None` if the trie has no
+    /// node for it. Nodes are never path compressed, so this is the prefix length.
+    pub fn depth_of(&self, cidr: &CidrBlock) -> Option<u32> {
+        let depth: u32 = cidr.prefix;
+        (depth <= 32 && self.root.find(cidr.net, depth).is_some()).then_some(depth)
     }
 
-    #[test]
-    fn trie_with_many_custom_struct() {
-        let mut t: Trie<TestDummyStruct> = Trie::empty();
-        let tds1 = TestDummyStruct {
-            a: 1,
-            b: true,
-            c: "Hello Radix Trie!".to_string(),
-        };
-        let tds2 = TestDummyStruct {
-            a: 69,
-            b: false,
-            c: "Goodbye...".to_string(),
-        };
-        let tds3 = TestDummyStruct {
-            a: 420,
-            b: true,
-            c: "Don't you dare go hollow.".to_string(),
+    /// Get the depth of the deepest node on the path of the provided ip address, i.e.
+    /// the number of nodes a lookup of the address descends through.
+    pub fn walk_depth<K: IntoIpKey>(&self, ip: K) -> u32 {
+        let Some(ip) = ip.into_ip_key() else {
+            return 0;
         };
 
-        t.insert_net_and_prefix(Ipv4Addr::new(183, 210, 129, 0).into(), 2, tds1.clone());
-        t.insert_net_and_prefix(Ipv4Addr::new(183, 40, 31, 0).into(), 8, tds2.clone());
-        t.insert_net_and_prefix(Ipv4Addr::new(10, 123, 42, 0).into(), 24, tds3.clone());
+        let mut node: &TrieNode<V> = &self.root;
+        let mut depth: u32 = 0;
+        while depth < 32 {
+            let next: Option<&TrieNode<V>> = if ((1u32 << (31 - depth)) & ip) == 0 {
+                node.left()
+            } else {
+                node.right()
+            };
+            match next {
+                Some(n) => node = n,
+                None => break,
+            }
+            depth += 1;
+        }
+        depth
+    }
 
-        let g = t.get(Ipv4Addr::new(183, 123, 59, 3).into());
-        assert_eq!(vec![&tds1, &tds2], g);
+    /// Estimate the memory held by the trie in bytes, counting its nodes and the inline
+    /// size of its values, but neither memory the values own nor unused capacity.
+    pub fn memory_usage(&self) -> usize {
+        let root = NodeRef {
+            node: &self.root,
+            cidr: CidrBlock::ALL,
+        };
+        let values: usize = self.iter().map(|(_, values)| values.len()).sum();
+        size_of::<Trie<V>>()
+            + (root.subtree_nodes() - 1) * size_of::<TrieNode<V>>()
+            + values * size_of::<V>()
+    }
 
-        let gg = t.get(Ipv4Addr::new(10, 123, 42, 250).into());
-        assert_eq!(vec![&tds3], gg);
-        assert_eq!(
-            Vec::<&TestDummyStruct>::new(),
-            t.get(Ipv4Addr::new(20, 159, 30, 42).into())
-        );
+    /// Get the length of the longest chain of nodes that have a single child and no
+    /// values. Long chains make lookups walk many nodes without finding anything,
+    /// and are what a path compressed backend would collapse.
+    pub fn longest_chain(&self) -> u32 {
+        self.root.chains().0
     }
 
-    #[test]
-    fn insert_from_net_and_prefix() {
-        let mut t: Trie<u32> = Trie::empty();
-        t.insert_net_and_prefix(Ipv4Addr::new(183, 40, 20, 0).into(), 8, 49);
-        t.insert_net_and_prefix(Ipv4Addr::new(183, 40, 21, 3).into(), 16, 150);
-        t.insert_net_and_prefix(Ipv4Addr::new(20, 30, 40, 0).into(), 31, 420);
+    /// Get the node at exactly the network and prefix length, if the trie has one,
+    /// for structural introspection.
+    pub fn node_at<K: IntoIpKey>(&self, net: K, prefix: u32) -> Option<NodeRef<'_, V>> {
+        let net: u32 = net.into_ip_key()?;
+        if prefix > 32 {
+            return None;
+        }
 
-        assert_eq!(false, t.contains_ip(Ipv4Addr::new(182, 41, 21, 3).into()));
-        assert_eq!(
-            vec![&49, &150],
-            t.get(Ipv4Addr::new(183, 40, 25, 59).into())
-        );
-        assert_eq!(vec![&420], t.get(Ipv4Addr::new(20, 30, 40, 1).into()));
+        let cidr = CidrBlock {
+            net: net & prefix_mask(prefix),
+            prefix,
+        };
+        self.root
+            .find(net, prefix)
+            .map(|node| NodeRef { node, cidr })
     }
 
-    #[test]
-    fn cidr_block_from_str_ok() {
-        let cb = CidrBlock::from_str("127.0.1.40/30").unwrap();
-        assert_eq!(u32::from(Ipv4Addr::new(127, 0, 1, 40)), cb.net);
-        assert_eq!(30, cb.prefix);
+    /// Get the values stored at exactly the cidr block mutably.
+    pub fn get_exact_mut(&mut self, cidr: &CidrBlock) -> &mut [V] {
+        match self.root.find_mut(cidr.net, cidr.prefix.min(32)) {
+            Some(node) => node.v.as_deref_mut().unwrap_or_default(),
+            None => &mut [],
+        }
     }
 
-    #[test]
+    /// Apply `f` to the values stored at exactly the cidr block, creating the block if
+    /// it does not exist yet and pruning it if `f` leaves it without values.
+    pub fn update_exact<R, F>(&mut self, cidr: &CidrBlock, f: F) -> Result<R, TrieError>
+    where
+        F: FnOnce(&mut Vec<V>) -> R,
+    {
+        if cidr.prefix > 32 {
+            return Err(TrieError::InvalidPrefix(cidr.prefix));
+        }
+
+        let node = TrieNode {
+            l: None,
+            r: None,
+            v: Some(Vec::new()),
+        };
+        self.root.graft(cidr.net, cidr.prefix, node);
+        Ok(self
+            .root
+            .update(cidr.net, cidr.prefix, |node| {
+                f(node.v.get_or_insert_with(Vec::new))
+            })
+            .unwrap())
+    }
+
+    /// Remove and return every value stored at exactly the cidr block, pruning the
+    /// branch if it no longer leads to any values.
+    pub fn remove(&mut self, cidr: &CidrBlock) -> Vec<V> {
+        self.root
+            .update(cidr.net, cidr.prefix.min(32), |node| {
+                node.v.take().unwrap_or_default()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get the values of the most specific cidr block containing the provided ip
+    /// address, or an empty slice if no block contains it.
+    pub fn get_longest<K: IntoIpKey>(&self, ip: K) -> &[V] {
+        let mut longest: &[V] = &[];
+        if let Some(ip) = ip.into_ip_key() {
+            self.root.longest(ip, 0xffffffffu32, &mut longest);
+        }
+        longest
+    }
+
+    /// Get the values associated with the provided ip address after zeroing all but
+    /// its first `keep_bits` bits, so that the full address is never matched.
+    pub fn lookup_anonymized<K: IntoIpKey>(&self, ip: K, keep_bits: u32) -> Vec<&V> {
+        match ip.into_ip_key() {
+            Some(ip) => self.get(ip & prefix_mask(keep_bits.min(32))),
+            None => Vec::new(),
+        }
+    }
+
+    /// Insert the value at the cidr block, rolled up into its covering block of
+    /// `keep_bits` if it is more specific, see [`CidrBlock::anonymized`].
+    pub fn insert_anonymized(
+        &mut self,
+        cidr: &CidrBlock,
+        value: V,
+        keep_bits: u32,
+    ) -> Result<(), TrieError> {
+        if cidr.prefix > 32 {
+            return Err(TrieError::InvalidPrefix(cidr.prefix));
+        }
+        self.try_insert(&cidr.anonymized(keep_bits), value)
+    }
+
+    /// Get whether or not the trie contains the provided ip address.
+    pub fn contains_ip<K: IntoIpKey>(&self, ip: K) -> bool {
+        !self.get(ip).is_empty()
+    }
+
+    /// Call `f` with every ip and the values associated with it, reusing a single
+    /// buffer for all lookups instead of allocating a result per ip.
+    pub fn for_each_match<I, F>(&self, ips: I, mut f: F)
+    where
+        I: IntoIterator<Item = u32>,
+        F: FnMut(u32, &[&V]),
+    {
+        let mut buffer: Vec<&V> = Vec::with_capacity(32);
+        for ip in ips.into_iter() {
+            buffer.clear();
+            self.root.get(ip, 0xffffffffu32, &mut buffer);
+            f(ip, &buffer);
+        }
+    }
+
+    /// Iterate over every cidr block holding values together with its values,
+    /// ordered by network and then by prefix length.
+    pub fn iter(&self) -> Iter<'_, V> {
+        Iter {
+            stack: vec![(&self.root, 0, 0)],
+        }
+    }
+
+    /// Create a new trie holding the values returned by `f` for every block and
+    /// value, dropping values for which `f` returns `None`. The original trie is
+    /// left untouched.
+    pub fn filter_map<U, F>(&self, mut f: F) -> Trie<U>
+    where
+        F: FnMut(&CidrBlock, &V) -> Option<U>,
+    {
+        Trie {
+            root: self
+                .root
+                .filter_map(0, 0, &mut f)
+                .unwrap_or_else(TrieNode::empty),
+        }
+    }
+
+    /// Drop every value rejected by the predicate in place, pruning the branches left
+    /// without any values, and return the number of values dropped.
+    pub fn retain<F>(&mut self, mut pred: F) -> usize
+    where
+        F: FnMut(&CidrBlock, &V) -> bool,
+    {
+        let dropped: usize = self.root.retain(0, 0, &mut pred);
+        self.compact();
+        dropped
+    }
+
+    /// Remove empty value lists and prune branches that no longer lead to any
+    /// values, without changing the result of any lookup.
+    pub fn compact(&mut self) {
+        self.root.compact();
+    }
+
+    /// Get whether or not every ip address matching any value in the other trie also
+    /// matches a value in this trie, regardless of the values and of how the address
+    /// space is split into blocks in either trie.
+    pub fn covers<U>(&self, other: &Trie<U>) -> bool {
+        coverage_blocks(
+            Some(&self.root),
+            Some(&other.root),
+            CidrBlock::ALL,
+            |ours, theirs| theirs && !ours,
+        )
+        .is_empty()
+    }
+
+    /// Get whether or not every ip address matching any value in this trie also matches
+    /// a value in the other trie, see [`Trie::covers`].
+    pub fn is_covered_by<U>(&self, other: &Trie<U>) -> bool {
+        other.covers(self)
+    }
+
+    /// Get the minimal set of cidr blocks holding every ip address that matches a value
+    /// in exactly one of the tries, i.e. the change in coverage between the two.
+    pub fn coverage_xor<U>(&self, other: &Trie<U>) -> Vec<CidrBlock> {
+        coverage_blocks(
+            Some(&self.root),
+            Some(&other.root),
+            CidrBlock::ALL,
+            |ours, theirs| ours != theirs,
+        )
+    }
+
+    /// Get the minimal set of cidr blocks holding every ip address that does not match
+    /// any value in the trie, e.g. to derive deny rules from an allowlist.
+    pub fn coverage_complement(&self) -> Vec<CidrBlock> {
+        coverage_blocks(
+            Some(&self.root),
+            None::<&TrieNode<V>>,
+            CidrBlock::ALL,
+            |ours, _| !ours,
+        )
+    }
+
+    /// Get whether or not every ip address in the cidr block matches a value in the
+    /// trie, either of an enclosing block or of blocks inside it that together span it.
+    pub fn covers_cidr(&self, cidr: &CidrBlock) -> bool {
+        // The blocks are minimal, so a fully covered block is collected as a whole.
+        let covered: Vec<CidrBlock> = self.coverage_within(cidr);
+        covered.len() == 1 && covered[0].prefix == cidr.prefix.min(32)
+    }
+
+    /// Get the narrowest cidr block containing all the provided ip addresses, `None`
+    /// if there are no addresses. See [`util::common_supernet`] for the u32 parts.
+    pub fn common_supernet(ips: &[u32]) -> Option<CidrBlock> {
+        let (net, prefix) = util::common_supernet(ips)?;
+        Some(CidrBlock { net, prefix })
+    }
+
+    /// Get the minimal set of cidr blocks inside the cidr block holding every ip
+    /// address that matches a value in the trie.
+    fn coverage_within(&self, cidr: &CidrBlock) -> Vec<CidrBlock> {
+        let prefix: u32 = cidr.prefix.min(32);
+        let cidr = CidrBlock {
+            net: cidr.net & prefix_mask(prefix),
+            prefix,
+        };
+
+        let mut node: &TrieNode<V> = &self.root;
+        for depth in 0..prefix {
+            if node.has_values() {
+                return vec![cidr];
+            }
+
+            let next: Option<&TrieNode<V>> = if ((1u32 << (31 - depth)) & cidr.net) == 0 {
+                node.left()
+            } else {
+                node.right()
+            };
+            match next {
+                Some(n) => node = n,
+                None => return Vec::new(),
+            }
+        }
+
+        coverage_blocks(Some(node), None::<&TrieNode<V>>, cidr, |ours, _| ours)
+    }
+}
+
+/// Number of leading bits used to partition blocks for parallel insertion.
+const PARTITION_BITS: u32 = 8;
+
+impl<V: Send> Trie<V> {
+    /// Insert every cidr block and value pair from a parallel iterator.
+    ///
+    /// Blocks are partitioned by their leading bits and each partition is built
+    /// into its own subtrie in parallel, before being grafted onto the root. The
+    /// resulting trie is identical to inserting the items sequentially in order.
+    pub fn par_extend<I>(&mut self, items: I) -> InsertSummary
+    where
+        I: IntoParallelIterator<Item = (CidrBlock, V)>,
+    {
+        let items: Vec<(CidrBlock, V)> = items.into_par_iter().collect();
+
+        let mut summary = InsertSummary::default();
+        let mut partitions: Vec<Vec<(u32, u32, V)>> =
+            (0..1 << PARTITION_BITS).map(|_| Vec::new()).collect();
+
+        for (i, (cidr, value)) in items.into_iter().enumerate() {
+            if cidr.prefix > 32 {
+                summary
+                    .errors
+                    .push((i, TrieError::InvalidPrefix(cidr.prefix)));
+                continue;
+            }
+
+            summary.inserted += 1;
+            let mask: u32 = prefix_mask(cidr.prefix);
+            if cidr.prefix < PARTITION_BITS {
+                self.root.insert(cidr.net, mask, value);
+            } else {
+                let partition: usize = (cidr.net >> (32 - PARTITION_BITS)) as usize;
+                partitions[partition].push((
+                    cidr.net << PARTITION_BITS,
+                    mask << PARTITION_BITS,
+                    value,
+                ));
+            }
+        }
+
+        let subtries: Vec<(u32, TrieNode<V>)> = partitions
+            .into_par_iter()
+            .enumerate()
+            .filter(|(_, items)| !items.is_empty())
+            .map(|(partition, items)| {
+                let mut node: TrieNode<V> = TrieNode::empty();
+                for (ip, mask, value) in items.into_iter() {
+                    node.insert(ip, mask, value);
+                }
+                ((partition as u32) << (32 - PARTITION_BITS), node)
+            })
+            .collect();
+
+        for (ip, node) in subtries.into_iter() {
+            self.root.graft(ip, PARTITION_BITS, node);
+        }
+
+        summary
+    }
+}
+
+impl<V: Clone> Trie<V> {
+    /// Create a new trie holding clones of the values for which the predicate
+    /// returns true. The original trie is left untouched.
+    pub fn clone_filtered<F>(&self, mut pred: F) -> Trie<V>
+    where
+        F: FnMut(&CidrBlock, &V) -> bool,
+    {
+        self.filter_map(|cidr, v| pred(cidr, v).then(|| v.clone()))
+    }
+
+    /// Get owned clones of the values associated with the provided ip address.
+    pub fn get_cloned<K: IntoIpKey>(&self, ip: K) -> Vec<V> {
+        self.get(ip).into_iter().cloned().collect()
+    }
+
+    /// Create a new trie holding clones of the values restricted to the coverage of
+    /// the filter trie. Blocks only partially covered by the filter are split into the
+    /// covered pieces, each of which holds all values of the block.
+    pub fn intersect_coverage<U>(&self, filter: &Trie<U>) -> Trie<V> {
+        let mut trie: Trie<V> = Trie::empty();
+        for (cidr, values) in self.iter() {
+            for piece in filter.coverage_within(&cidr) {
+                for v in values {
+                    trie.try_insert(&piece, v.clone()).unwrap();
+                }
+            }
+        }
+        trie
+    }
+}
+
+impl<V> Trie<Arc<V>> {
+    /// Get shared handles to the values associated with the provided ip address,
+    /// only bumping reference counts rather than cloning the values themselves.
+    pub fn get_owned<K: IntoIpKey>(&self, ip: K) -> Vec<Arc<V>> {
+        self.get(ip).into_iter().map(Arc::clone).collect()
+    }
+}
+
+impl<V: Sync> Trie<V> {
+    /// Get the values associated with each of the provided ip addresses, looking
+    /// them up in parallel. The results are in the same order as the ips.
+    pub fn lookup_many_par(&self, ips: &[u32]) -> Vec<Vec<&V>> {
+        ips.par_iter().map(|ip| self.get(*ip)).collect()
+    }
+}
+
+impl<V: Encode> Trie<V> {
+    /// Encode the trie into the writer, returning the number of bytes written.
+    pub fn encode_to_writer<W: Write>(&self, writer: &mut W) -> Result<usize, EncodeError> {
+        bincode::encode_into_std_write(self, writer, config::standard())
+    }
+
+    /// Encode only the cidr blocks accepted by the filter into the writer, returning
+    /// the number of bytes written. The output decodes as a regular trie holding
+    /// just those blocks, so a large table can be persisted in slices.
+    pub fn encode_to_writer_filtered<W, F>(
+        &self,
+        writer: &mut W,
+        filter: F,
+    ) -> Result<usize, EncodeError>
+    where
+        W: Write,
+        F: Fn(&CidrBlock) -> bool,
+    {
+        let root = FilteredNode {
+            node: &self.root,
+            net: 0,
+            depth: 0,
+            filter: &filter,
+        };
+        bincode::encode_into_std_write(root, writer, config::standard())
+    }
+
+    /// Compute a stable hash over the cidr blocks and values of the trie, independent
+    /// of insertion order, so that replicas can cheaply check that they hold the
+    /// same contents. Values are hashed by their bincode encoding.
+    pub fn fingerprint(&self) -> u64 {
+        let config: config::Configuration = config::standard();
+        let mut hash: u64 = FNV_OFFSET_BASIS;
+        let mut buffer: Vec<u8> = Vec::new();
+
+        for (cidr, values) in self.iter() {
+            let mut value_hashes: Vec<u64> = values
+                .iter()
+                .map(|v| {
+                    buffer.clear();
+                    bincode::encode_into_std_write(v, &mut buffer, config).unwrap();
+                    fnv1a(FNV_OFFSET_BASIS, &buffer)
+                })
+                .collect();
+            value_hashes.sort_unstable();
+
+            hash = fnv1a(hash, &cidr.net.to_be_bytes());
+            hash = fnv1a(hash, &[cidr.prefix as u8]);
+            hash = fnv1a(hash, &(value_hashes.len() as u64).to_be_bytes());
+            for h in value_hashes.iter() {
+                hash = fnv1a(hash, &h.to_be_bytes());
+            }
+        }
+
+        hash
+    }
+}
+
+impl<V: Decode<()>> Trie<V> {
+    /// Decode a trie written by [`Trie::encode_to_writer`] from the reader.
+    pub fn decode_from_reader<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        bincode::decode_from_std_read(reader, config::standard())
+    }
+}
+
+impl<V: Decode<()> + Encode> Trie<V> {
+    /// Initialize a Trie instance that was saved to a binary file.
+    pub fn read_from_file(path: &str) -> Self {
+        let config: config::Configuration = config::standard();
+        let file: File = match OpenOptions::new().read(true).write(false).open(path) {
+            Ok(f) => f,
+            Err(_) => {
+                println!("{} did not exist, creating an empty Trie...", path);
+                return Trie::empty();
+            }
+        };
+
+        let mut reader: BufReader<File> = BufReader::new(file);
+        bincode::decode_from_std_read(&mut reader, config).unwrap()
+    }
+
+    /// Write the state of the Trie to binary file.
+    pub fn write_to_file(&self, path: &str) {
+        let config: config::Configuration = config::standard();
+        let file: File = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+
+        let mut writer: BufWriter<File> = BufWriter::new(file);
+        bincode::encode_into_std_write(self, &mut writer, config).unwrap();
+    }
+
+    /// Write only the cidr blocks accepted by the filter to binary file, see
+    /// [`Trie::encode_to_writer_filtered`].
+    pub fn write_to_file_filtered<F: Fn(&CidrBlock) -> bool>(&self, path: &str, filter: F) {
+        let file: File = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+
+        let mut writer: BufWriter<File> = BufWriter::new(file);
+        self.encode_to_writer_filtered(&mut writer, filter).unwrap();
+    }
+}
+
+/// Version of the layout of sharded trie directories and indexed trie files.
+const SHARD_FORMAT_VERSION: u32 = 1;
+
+/// Largest number of shards a trie can be split into.
+const MAX_SHARDS: u32 = 1 << 16;
+
+/// Get the network of the shard in a trie split by its leading `bits`.
+fn shard_net(bits: u32, shard: u32) -> u32 {
+    shard.checked_shl(32 - bits).unwrap_or(0)
+}
+
+/// Leading bytes of a trie file written by [`Trie::write_to_file_indexed`].
+const INDEXED_MAGIC: [u8; 4] = *b"MMRT";
+
+/// Magic, layout version, number of leading bits, trie of the blocks shorter than
+/// those bits and encoded length of every subtree, from the first to the last.
+type IndexHeader<T> = ([u8; 4], u32, u32, T, Vec<u64>);
+
+fn shard_path(dir: &Path, shard: u32) -> PathBuf {
+    dir.join(format!("shard-{:05}.bin", shard))
+}
+
+impl<V: Encode> Trie<V> {
+    /// Split the trie by the leading bits of its blocks into `shards` files in the
+    /// directory, which is created if needed, so it can be loaded in parallel with
+    /// [`Trie::read_sharded`]. The number of shards must be a power of two of at
+    /// most 65536.
+    ///
+    /// Blocks shorter than the leading bits are written to a manifest next to the
+    /// shards, together with the number of shards.
+    pub fn write_sharded(&self, dir: &str, shards: u32) -> Result<(), ShardError> {
+        if !shards.is_power_of_two() || shards > MAX_SHARDS {
+            return Err(ShardError::InvalidShardCount(shards));
+        }
+
+        let dir: &Path = Path::new(dir);
+        fs::create_dir_all(dir)?;
+        let bits: u32 = shards.trailing_zeros();
+        let config: config::Configuration = config::standard();
+
+        let top = ShallowNode {
+            node: &self.root,
+            levels: bits,
+        };
+        let mut writer: BufWriter<File> = BufWriter::new(File::create(dir.join("manifest.bin"))?);
+        bincode::encode_into_std_write((SHARD_FORMAT_VERSION, bits, top), &mut writer, config)?;
+        writer.flush()?;
+
+        let empty: TrieNode<V> = TrieNode::empty();
+        for shard in 0..shards {
+            let node: &TrieNode<V> = self
+                .root
+                .find(shard_net(bits, shard), bits)
+                .unwrap_or(&empty);
+            let mut writer: BufWriter<File> = BufWriter::new(File::create(shard_path(dir, shard))?);
+            bincode::encode_into_std_write(node, &mut writer, config)?;
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the trie to a single file, split by the leading bits of its blocks into
+    /// `shards` subtrees like [`Trie::write_sharded`], with an index of where each
+    /// subtree starts, so it can be loaded with [`Trie::read_from_file_parallel`].
+    pub fn write_to_file_indexed(&self, path: &str, shards: u32) -> Result<(), ShardError> {
+        if !shards.is_power_of_two() || shards > MAX_SHARDS {
+            return Err(ShardError::InvalidShardCount(shards));
+        }
+
+        let bits: u32 = shards.trailing_zeros();
+        let config: config::Configuration = config::standard();
+        let empty: TrieNode<V> = TrieNode::empty();
+        let nodes: Vec<&TrieNode<V>> = (0..shards)
+            .map(|shard| {
+                self.root
+                    .find(shard_net(bits, shard), bits)
+                    .unwrap_or(&empty)
+            })
+            .collect();
+
+        // Size the subtrees first, so the index can precede them.
+        let lengths: Vec<u64> = nodes
+            .iter()
+            .map(|node| bincode::encode_into_std_write(node, &mut io::sink(), config))
+            .map(|length| length.map(|n| n as u64))
+            .collect::<Result<_, EncodeError>>()?;
+        let top = ShallowNode {
+            node: &self.root,
+            levels: bits,
+        };
+        let header: IndexHeader<ShallowNode<V>> =
+            (INDEXED_MAGIC, SHARD_FORMAT_VERSION, bits, top, lengths);
+
+        let mut writer: BufWriter<File> = BufWriter::new(File::create(path)?);
+        bincode::encode_into_std_write(header, &mut writer, config)?;
+        for node in nodes {
+            bincode::encode_into_std_write(node, &mut writer, config)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<V: Decode<()> + Send> Trie<V> {
+    /// Read a trie written by [`Trie::write_sharded`] from the directory, decoding
+    /// the shards in parallel on the rayon thread pool before grafting them together.
+    pub fn read_sharded(dir: &str) -> Result<Self, ShardError> {
+        let dir: &Path = Path::new(dir);
+        let config: config::Configuration = config::standard();
+
+        let mut reader: BufReader<File> = BufReader::new(File::open(dir.join("manifest.bin"))?);
+        let (version, bits, mut trie): (u32, u32, Trie<V>) =
+            bincode::decode_from_std_read(&mut reader, config)?;
+        if version != SHARD_FORMAT_VERSION {
+            return Err(ShardError::UnsupportedVersion(version));
+        }
+        if bits > MAX_SHARDS.trailing_zeros() {
+            return Err(ShardError::InvalidShardCount(1 << bits.min(31)));
+        }
+
+        let shards: Vec<TrieNode<V>> = (0..1u32 << bits)
+            .into_par_iter()
+            .map(|shard| {
+                let file: File = File::open(shard_path(dir, shard))?;
+                Ok(bincode::decode_from_std_read(
+                    &mut BufReader::new(file),
+                    config,
+                )?)
+            })
+            .collect::<Result<_, ShardError>>()?;
+
+        trie.graft_shards(bits, shards);
+        Ok(trie)
+    }
+
+    /// Read a trie written by [`Trie::write_to_file_indexed`], decoding the indexed
+    /// subtrees concurrently on a pool of `threads` threads, or as many threads as
+    /// rayon defaults to if zero.
+    pub fn read_from_file_parallel(path: &str, threads: usize) -> Result<Self, ShardError> {
+        let config: config::Configuration = config::standard();
+        let mut reader: BufReader<File> = BufReader::new(File::open(path)?);
+        let (magic, version, bits, mut trie, lengths): IndexHeader<Trie<V>> =
+            bincode::decode_from_std_read(&mut reader, config)?;
+        if magic != INDEXED_MAGIC {
+            return Err(ShardError::NotIndexed);
+        }
+        if version != SHARD_FORMAT_VERSION {
+            return Err(ShardError::UnsupportedVersion(version));
+        }
+        if bits > MAX_SHARDS.trailing_zeros() || lengths.len() != 1 << bits {
+            return Err(ShardError::InvalidShardCount(lengths.len() as u32));
+        }
+
+        let mut offsets: Vec<u64> = Vec::with_capacity(lengths.len());
+        let mut offset: u64 = reader.stream_position()?;
+        for length in lengths {
+            offsets.push(offset);
+            offset += length;
+        }
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(io::Error::other)?;
+        let shards: Vec<TrieNode<V>> = pool.install(|| {
+            offsets
+                .into_par_iter()
+                .map(|offset| {
+                    let mut file: File = File::open(path)?;
+                    file.seek(SeekFrom::Start(offset))?;
+                    Ok(bincode::decode_from_std_read(
+                        &mut BufReader::new(file),
+                        config,
+                    )?)
+                })
+                .collect::<Result<_, ShardError>>()
+        })?;
+
+        trie.graft_shards(bits, shards);
+        Ok(trie)
+    }
+
+    /// Graft the subtrees of a trie split by its leading `bits` onto this trie,
+    /// skipping empty ones.
+    fn graft_shards(&mut self, bits: u32, shards: Vec<TrieNode<V>>) {
+        for (shard, node) in (0..).zip(shards) {
+            if node.has_values() || !node.is_leaf() {
+                self.root.graft(shard_net(bits, shard), bits, node);
+            }
+        }
+    }
+}
+
+/// Encodes a node in the same layout as the derived [`Encode`] of [`TrieNode`],
+/// leaving out every node `levels` or more levels below it.
+struct ShallowNode<'a, V> {
+    node: &'a TrieNode<V>,
+    levels: u32,
+}
+
+impl<V: Encode> Encode for ShallowNode<'_, V> {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        for child in [self.node.left(), self.node.right()] {
+            child
+                .filter(|_| self.levels > 1)
+                .map(|node| ShallowNode {
+                    node,
+                    levels: self.levels - 1,
+                })
+                .encode(encoder)?;
+        }
+        (self.levels > 0 && self.node.has_values())
+            .then(|| self.node.values())
+            .encode(encoder)
+    }
+}
+
+/// Encodes a node in the same layout as the derived [`Encode`] of [`TrieNode`],
+/// leaving out values of rejected blocks and branches without any accepted values.
+struct FilteredNode<'a, V, F> {
+    node: &'a TrieNode<V>,
+    net: u32,
+    depth: u32,
+    filter: &'a F,
+}
+
+impl<'a, V, F: Fn(&CidrBlock) -> bool> FilteredNode<'a, V, F> {
+    fn child(&self, node: Option<&'a TrieNode<V>>, bit: u32) -> Option<Self> {
+        let child = FilteredNode {
+            node: node?,
+            net: self.net | (bit << (31 - self.depth)),
+            depth: self.depth + 1,
+            filter: self.filter,
+        };
+        child.keeps_any().then_some(child)
+    }
+
+    fn keeps_values(&self) -> bool {
+        self.node.has_values()
+            && (self.filter)(&CidrBlock {
+                net: self.net,
+                prefix: self.depth,
+            })
+    }
+
+    fn keeps_any(&self) -> bool {
+        self.keeps_values()
+            || self.child(self.node.left(), 0).is_some()
+            || self.child(self.node.right(), 1).is_some()
+    }
+}
+
+impl<V: Encode, F: Fn(&CidrBlock) -> bool> Encode for FilteredNode<'_, V, F> {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.child(self.node.left(), 0).encode(encoder)?;
+        self.child(self.node.right(), 1).encode(encoder)?;
+        self.keeps_values()
+            .then(|| self.node.values())
+            .encode(encoder)
+    }
+}
+
+impl<V: PartialEq> Trie<V> {
+    /// Collapse pairs of sibling prefixes holding identical values into their
+    /// common parent prefix, e.g. two /25s into one /24. Every ip matches the
+    /// same values before and after aggregation.
+    pub fn aggregate(&mut self) {
+        self.root.aggregate();
+    }
+
+    /// Roll every block more specific than `prefix_len` up into its covering block of
+    /// that length, keeping only one of equal values per block. Exports anonymized to
+    /// e.g. /24 no longer hold data about single hosts.
+    pub fn anonymize(&mut self, prefix_len: u32) {
+        if prefix_len < 32 {
+            self.root.truncate(prefix_len);
+        }
+    }
+
+    /// Remove the most recently inserted value equal to the provided one from exactly
+    /// the cidr block, returning whether or not such a value was found.
+    pub fn remove_value(&mut self, cidr: &CidrBlock, value: &V) -> bool {
+        self.root
+            .update(cidr.net, cidr.prefix.min(32), |node| {
+                let values: &mut Vec<V> = node.v.as_mut()?;
+                let i: usize = values.iter().rposition(|v| v == value)?;
+                values.remove(i);
+                Some(())
+            })
+            .flatten()
+            .is_some()
+    }
+
+    /// Get whether or not both tries hold the same values at the same cidr blocks,
+    /// regardless of value order within a block and of empty nodes in either trie.
+    pub fn content_eq(&self, other: &Trie<V>) -> bool {
+        let mut these = self.iter();
+        let mut those = other.iter();
+        loop {
+            match (these.next(), those.next()) {
+                (None, None) => return true,
+                (Some((a, va)), Some((b, vb))) if a == b && is_permutation(va, vb) => {}
+                _ => return false,
+            }
+        }
+    }
+
+    /// Get whether or not every ip address matches the same values in both tries,
+    /// regardless of value order and of which blocks the values are stored at.
+    /// Unlike [`Trie::content_eq`] this holds across [`Trie::aggregate`].
+    pub fn lookup_eq(&self, other: &Trie<V>) -> bool {
+        lookup_eq(
+            Some(&self.root),
+            Some(&other.root),
+            &mut Vec::new(),
+            &mut Vec::new(),
+        )
+    }
+}
+
+fn is_permutation<V: PartialEq>(a: &[V], b: &[V]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut used: Vec<bool> = vec![false; b.len()];
+    a.iter()
+        .all(|x| match (0..b.len()).find(|i| !used[*i] && b[*i] == *x) {
+            Some(i) => {
+                used[i] = true;
+                true
+            }
+            None => false,
+        })
+}
+
+/// Get the minimal set of cidr blocks whose addresses satisfy `pred` in address order,
+/// see [`coverage`].
+fn coverage_blocks<A, B, P>(
+    a: Option<&TrieNode<A>>,
+    b: Option<&TrieNode<B>>,
+    cidr: CidrBlock,
+    pred: P,
+) -> Vec<CidrBlock>
+where
+    P: Fn(bool, bool) -> bool + Copy,
+{
+    let mut out: Vec<CidrBlock> = Vec::new();
+    if coverage(a, b, cidr, (false, false), pred, &mut out) {
+        out.push(cidr);
+    }
+    // The blocks are disjoint, so ordering them by network orders them by address.
+    out.sort_unstable_by_key(|cidr| cidr.net);
+    out
+}
+
+/// Walk two subtrees in lockstep and collect the minimal set of blocks whose addresses
+/// satisfy `pred`, given whether or not each address matches a value in the first and
+/// in the second subtree. `covered` holds whether the ancestors of the subtrees matched.
+///
+/// Returns `true` without collecting anything if the whole block satisfies `pred`, so
+/// that the caller can collect the parent block instead of both halves.
+fn coverage<A, B, P>(
+    a: Option<&TrieNode<A>>,
+    b: Option<&TrieNode<B>>,
+    cidr: CidrBlock,
+    covered: (bool, bool),
+    pred: P,
+    out: &mut Vec<CidrBlock>,
+) -> bool
+where
+    P: Fn(bool, bool) -> bool + Copy,
+{
+    let covered_a: bool = covered.0 || a.is_some_and(TrieNode::has_values);
+    let covered_b: bool = covered.1 || b.is_some_and(TrieNode::has_values);
+
+    // Below a covered node or a leaf every address is covered the same way.
+    let settled_a: bool = covered_a || a.is_none_or(TrieNode::is_leaf);
+    let settled_b: bool = covered_b || b.is_none_or(TrieNode::is_leaf);
+    if settled_a && settled_b {
+        return pred(covered_a, covered_b);
+    }
+
+    let halves = [
+        (
+            a.and_then(TrieNode::left),
+            b.and_then(TrieNode::left),
+            cidr.net,
+        ),
+        (
+            a.and_then(TrieNode::right),
+            b.and_then(TrieNode::right),
+            cidr.net | (1u32 << (31 - cidr.prefix)),
+        ),
+    ];
+
+    let full: Vec<(bool, CidrBlock)> = halves
+        .into_iter()
+        .map(|(a, b, net)| {
+            let half = CidrBlock {
+                net,
+                prefix: cidr.prefix + 1,
+            };
+            let full: bool = coverage(a, b, half, (covered_a, covered_b), pred, out);
+            (full, half)
+        })
+        .collect();
+
+    if full.iter().all(|(full, _)| *full) {
+        return true;
+    }
+
+    out.extend(
+        full.into_iter()
+            .filter(|(full, _)| *full)
+            .map(|(_, half)| half),
+    );
+    false
+}
+
+fn lookup_eq<'a, V: PartialEq>(
+    a: Option<&'a TrieNode<V>>,
+    b: Option<&'a TrieNode<V>>,
+    matched_a: &mut Vec<&'a V>,
+    matched_b: &mut Vec<&'a V>,
+) -> bool {
+    let (len_a, len_b) = (matched_a.len(), matched_b.len());
+    matched_a.extend(a.map_or(&[][..], |n| n.values()));
+    matched_b.extend(b.map_or(&[][..], |n| n.values()));
+
+    let is_leaf = |n: Option<&TrieNode<V>>| n.is_none_or(|n| n.is_leaf());
+    let eq: bool = if is_leaf(a) && is_leaf(b) {
+        is_permutation(matched_a, matched_b)
+    } else {
+        lookup_eq(
+            a.and_then(|n| n.left()),
+            b.and_then(|n| n.left()),
+            matched_a,
+            matched_b,
+        ) && lookup_eq(
+            a.and_then(|n| n.right()),
+            b.and_then(|n| n.right()),
+            matched_a,
+            matched_b,
+        )
+    };
+
+    matched_a.truncate(len_a);
+    matched_b.truncate(len_b);
+    eq
+}
+
+/// Read-only view of a node of a trie together with its cidr block, see [`Trie::node_at`].
+#[derive(Clone, Copy, Debug)]
+pub struct NodeRef<'a, V> {
+    node: &'a TrieNode<V>,
+    cidr: CidrBlock,
+}
+
+impl<'a, V> NodeRef<'a, V> {
+    pub fn cidr(&self) -> CidrBlock {
+        self.cidr
+    }
+
+    /// Get the values stored at the node itself.
+    pub fn values(&self) -> &'a [V] {
+        self.node.values()
+    }
+
+    pub fn has_left(&self) -> bool {
+        self.node.l.is_some()
+    }
+
+    pub fn has_right(&self) -> bool {
+        self.node.r.is_some()
+    }
+
+    /// Get the child node covering the lower half of the block.
+    pub fn left(&self) -> Option<NodeRef<'a, V>> {
+        self.node.left().map(|node| NodeRef {
+            node,
+            cidr: CidrBlock {
+                net: self.cidr.net,
+                prefix: self.cidr.prefix + 1,
+            },
+        })
+    }
+
+    /// Get the child node covering the upper half of the block.
+    pub fn right(&self) -> Option<NodeRef<'a, V>> {
+        // A /32 has no children, so the bit of the upper half is only computed for
+        // nodes that have one.
+        self.node.right().map(|node| NodeRef {
+            node,
+            cidr: CidrBlock {
+                net: self.cidr.net | (1u32 << (31 - self.cidr.prefix)),
+                prefix: self.cidr.prefix + 1,
+            },
+        })
+    }
+
+    /// Get the number of values stored at the node and all of its descendants.
+    pub fn subtree_values(&self) -> usize {
+        self.values().len()
+            + self.left().map_or(0, |n| n.subtree_values())
+            + self.right().map_or(0, |n| n.subtree_values())
+    }
+
+    /// Get the number of nodes in the subtree rooted at the node, including itself.
+    pub fn subtree_nodes(&self) -> usize {
+        1 + self.left().map_or(0, |n| n.subtree_nodes())
+            + self.right().map_or(0, |n| n.subtree_nodes())
+    }
+
+    /// Get the height of the subtree rooted at the node, see [`TrieNode::height`].
+    pub fn height(&self) -> u32 {
+        self.node.height()
+    }
+}
+
+/// Iterator over the cidr blocks and values of a trie, see [`Trie::iter`].
+pub struct Iter<'a, V> {
+    stack: Vec<(&'a TrieNode<V>, u32, u32)>,
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (CidrBlock, &'a [V]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, net, depth)) = self.stack.pop() {
+            if let Some(r) = node.right() {
+                self.stack
+                    .push((r, net | (1u32 << (31 - depth)), depth + 1));
+            }
+
+            if let Some(l) = node.left() {
+                self.stack.push((l, net, depth + 1));
+            }
+
+            if node.has_values() {
+                let cidr = CidrBlock { net, prefix: depth };
+                return Some((cidr, node.values()));
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a, V> IntoIterator for &'a Trie<V> {
+    type Item = (CidrBlock, &'a [V]);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
+
+/// Index the trie by ip address, see [`Trie::get_longest`].
+impl<V, K: IntoIpKey> Index<K> for Trie<V> {
+    type Output = [V];
+
+    fn index(&self, ip: K) -> &[V] {
+        self.get_longest(ip)
+    }
+}
+
+/// Types that can be used as ip addresses when inserting into or looking up the trie.
+///
+/// IPv6 addresses are only accepted if they are IPv4-mapped (`::ffff:a.b.c.d`), any
+/// other IPv6 address never matches and cannot be inserted.
+///
+/// Since 0.2.0 the lookup and insert methods of the trie are generic over this trait,
+/// so an argument whose type was left to inference, e.g. `Ipv4Addr::new(..).into()`,
+/// no longer compiles. Pass the address itself or name the type with `u32::from(..)`.
+pub trait IntoIpKey {
+    /// Convert into the numeric IPv4 address, if the address is an IPv4 address.
+    fn into_ip_key(self) -> Option<u32>;
+}
+
+impl IntoIpKey for u32 {
+    fn into_ip_key(self) -> Option<u32> {
+        Some(self)
+    }
+}
+
+impl IntoIpKey for Ipv4Addr {
+    fn into_ip_key(self) -> Option<u32> {
+        Some(self.into())
+    }
+}
+
+impl IntoIpKey for [u8; 4] {
+    fn into_ip_key(self) -> Option<u32> {
+        Some(u32::from_be_bytes(self))
+    }
+}
+
+impl IntoIpKey for Ipv6Addr {
+    fn into_ip_key(self) -> Option<u32> {
+        self.to_ipv4_mapped().map(u32::from)
+    }
+}
+
+impl IntoIpKey for IpAddr {
+    fn into_ip_key(self) -> Option<u32> {
+        match self {
+            IpAddr::V4(ip) => ip.into_ip_key(),
+            IpAddr::V6(ip) => ip.into_ip_key(),
+        }
+    }
+}
+
+/// Get the least specific block in the subtree of the node at the network and depth,
+/// the first by network among equally specific ones, if it is at most `limit` deep.
+fn shallowest_block<V>(node: &TrieNode<V>, net: u32, depth: u32, limit: u32) -> Option<CidrBlock> {
+    let mut level: Vec<(&TrieNode<V>, u32)> = vec![(node, net)];
+    for prefix in depth..=limit {
+        if let Some((_, net)) = level.iter().find(|(node, _)| node.has_values()) {
+            return Some(CidrBlock { net: *net, prefix });
+        }
+        if prefix == 32 {
+            break;
+        }
+
+        level = level
+            .into_iter()
+            .flat_map(|(node, net)| {
+                let right: u32 = net | (1 << (31 - prefix));
+                [(node.left(), net), (node.right(), right)]
+            })
+            .filter_map(|(node, net)| Some((node?, net)))
+            .collect();
+    }
+    None
+}
+
+/// Get the network mask for a prefix length, all ones for lengths of 32 and above.
+fn prefix_mask(prefix: u32) -> u32 {
+    u32::MAX
+        .checked_shl(32u32.saturating_sub(prefix))
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum TrieError {
+    /// The prefix length is larger than 32.
+    InvalidPrefix(u32),
+    /// Inserting would grow the trie beyond its memory limit, in bytes.
+    MemoryLimit(usize),
+    /// The trie is frozen and can no longer be written to.
+    Frozen,
+}
+
+impl fmt::Display for TrieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrieError::InvalidPrefix(p) => write!(f, "invalid prefix length {}", p),
+            TrieError::MemoryLimit(n) => write!(f, "memory limit of {} bytes exceeded", n),
+            TrieError::Frozen => write!(f, "cannot write to a frozen trie"),
+        }
+    }
+}
+
+impl Error for TrieError {}
+
+/// Reasons writing or reading a sharded trie can fail.
+#[derive(Debug)]
+pub enum ShardError {
+    /// The number of shards is not a power of two of at most 65536.
+    InvalidShardCount(u32),
+    /// The directory or file was written in a layout this version cannot read.
+    UnsupportedVersion(u32),
+    /// The file was not written by [`Trie::write_to_file_indexed`].
+    NotIndexed,
+    Io(io::Error),
+    Encode(EncodeError),
+    Decode(DecodeError),
+}
+
+impl fmt::Display for ShardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShardError::InvalidShardCount(n) => write!(f, "invalid number of shards {}", n),
+            ShardError::UnsupportedVersion(v) => {
+                write!(f, "unsupported shard layout version {}", v)
+            }
+            ShardError::NotIndexed => write!(f, "not an indexed trie file"),
+            ShardError::Io(e) => write!(f, "{}", e),
+            ShardError::Encode(e) => write!(f, "{}", e),
+            ShardError::Decode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ShardError {}
+
+impl From<io::Error> for ShardError {
+    fn from(e: io::Error) -> Self {
+        ShardError::Io(e)
+    }
+}
+
+impl From<EncodeError> for ShardError {
+    fn from(e: EncodeError) -> Self {
+        ShardError::Encode(e)
+    }
+}
+
+impl From<DecodeError> for ShardError {
+    fn from(e: DecodeError) -> Self {
+        ShardError::Decode(e)
+    }
+}
+
+/// Outcome of a bulk insertion.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct InsertSummary {
+    /// Number of values that were inserted.
+    pub inserted: usize,
+    /// Position in the input and reason of every item that was not inserted.
+    pub errors: Vec<(usize, TrieError)>,
+}
+
+impl InsertSummary {
+    /// Get whether or not every item was inserted.
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Decode, Encode, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CidrBlock {
+    pub net: u32,
+    pub prefix: u32,
+}
+
+impl CidrBlock {
+    /// The block holding every ipv4 address, `0.0.0.0/0`.
+    pub const ALL: CidrBlock = CidrBlock { net: 0, prefix: 0 };
+
+    /// Create a new cidr block, validating that the prefix length is in the range [0, 32].
+    pub fn new(net: Ipv4Addr, prefix: u32) -> Result<Self, CidrError> {
+        if prefix > 32 {
+            return Err(CidrError::InvalidPrefix(prefix));
+        }
+
+        Ok(CidrBlock {
+            net: net.into(),
+            prefix,
+        })
+    }
+
+    /// Like [`CidrBlock::new`], but also rejects nets with any host bits set,
+    /// e.g. 10.0.0.1/24.
+    pub fn new_strict(net: Ipv4Addr, prefix: u32) -> Result<Self, CidrError> {
+        let cidr = CidrBlock::new(net, prefix)?;
+        if cidr.net & !prefix_mask(prefix) != 0 {
+            return Err(CidrError::HostBitsSet(cidr));
+        }
+
+        Ok(cidr)
+    }
+
+    /// Get the first address of the block, i.e. the net with its host bits cleared.
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.net & prefix_mask(self.prefix))
+    }
+
+    /// Get the last address of the block, i.e. the net with its host bits set.
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.net | !prefix_mask(self.prefix))
+    }
+
+    /// Get the smallest list of cidr blocks covering exactly the addresses in the
+    /// inclusive range [first, last], ordered by network. Empty if first > last.
+    pub fn covering_range(first: u32, last: u32) -> Vec<CidrBlock> {
+        let mut blocks: Vec<CidrBlock> = Vec::new();
+        let mut next: u64 = first as u64;
+        let end: u64 = last as u64 + 1;
+
+        while next < end {
+            let aligned: u32 = if next == 0 {
+                32
+            } else {
+                next.trailing_zeros().min(32)
+            };
+            let fits: u32 = 63 - (end - next).leading_zeros();
+            let host_bits: u32 = aligned.min(fits);
+            blocks.push(CidrBlock {
+                net: next as u32,
+                prefix: 32 - host_bits,
+            });
+            next += 1u64 << host_bits;
+        }
+
+        blocks
+    }
+
+    /// Get the covering block of at most `keep_bits` bits, i.e. the block itself if it
+    /// is no more specific than that.
+    pub fn anonymized(&self, keep_bits: u32) -> CidrBlock {
+        let prefix: u32 = self.prefix.min(keep_bits).min(32);
+        CidrBlock {
+            net: self.net & prefix_mask(prefix),
+            prefix,
+        }
+    }
+
+    /// Get the number of addresses in the block, 1 for prefixes above 32.
+    pub fn len(&self) -> u64 {
+        1u64 << (32 - self.prefix.min(32))
+    }
+
+    /// Always false, a cidr block contains at least one address.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Iterate over every address in the block, from the network to the broadcast address.
+    pub fn addresses(&self) -> CidrAddresses {
+        let network: u64 = u32::from(self.network()) as u64;
+        CidrAddresses {
+            next: network,
+            end: network + self.len(),
+        }
+    }
+
+    /// Iterate over the usable host addresses in the block, which excludes the
+    /// network and broadcast addresses unless the block is a /31 or /32.
+    pub fn hosts(&self) -> CidrAddresses {
+        let mut addresses: CidrAddresses = self.addresses();
+        if self.prefix < 31 {
+            addresses.next += 1;
+            addresses.end -= 1;
+        }
+        addresses
+    }
+
+    /// Get whether or not the block contains the provided ip address.
+    pub fn contains<K: IntoIpKey>(&self, ip: K) -> bool {
+        let mask: u32 = prefix_mask(self.prefix);
+        ip.into_ip_key()
+            .is_some_and(|ip| ip & mask == self.net & mask)
+    }
+}
+
+/// Iterator over a range of addresses in a cidr block.
+#[derive(Clone, Debug)]
+pub struct CidrAddresses {
+    next: u64,
+    end: u64,
+}
+
+impl Iterator for CidrAddresses {
+    type Item = Ipv4Addr;
+
+    fn next(&mut self) -> Option<Ipv4Addr> {
+        if self.next >= self.end {
+            return None;
+        }
+
+        self.next += 1;
+        Some(Ipv4Addr::from((self.next - 1) as u32))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining: usize = usize::try_from(self.end - self.next).unwrap_or(usize::MAX);
+        (remaining, Some(remaining))
+    }
+}
+
+impl DoubleEndedIterator for CidrAddresses {
+    fn next_back(&mut self) -> Option<Ipv4Addr> {
+        if self.next >= self.end {
+            return None;
+        }
+
+        self.end -= 1;
+        Some(Ipv4Addr::from(self.end as u32))
+    }
+}
+
+impl std::iter::FusedIterator for CidrAddresses {}
+
+impl fmt::Display for CidrBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", Ipv4Addr::from(self.net), self.prefix)
+    }
+}
+
+impl TryFrom<(Ipv4Addr, u8)> for CidrBlock {
+    type Error = CidrError;
+    fn try_from((net, prefix): (Ipv4Addr, u8)) -> Result<Self, Self::Error> {
+        CidrBlock::new(net, prefix as u32)
+    }
+}
+
+impl FromStr for CidrBlock {
+    type Err = CidrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || CidrError::Malformed(s.to_string());
+        let (net, prefix) = s.split_once("/").ok_or_else(malformed)?;
+
+        let net: Ipv4Addr = net.parse().map_err(|_| malformed())?;
+        let prefix: u32 = prefix.parse().map_err(|_| malformed())?;
+
+        CidrBlock::new(net, prefix)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum CidrError {
+    /// The string is not of the form `a.b.c.d/len`.
+    Malformed(String),
+    /// The prefix length is larger than 32.
+    InvalidPrefix(u32),
+    /// The net has bits set beyond the prefix length.
+    HostBitsSet(CidrBlock),
+}
+
+impl fmt::Display for CidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CidrError::Malformed(s) => write!(f, "malformed cidr block '{}'", s),
+            CidrError::InvalidPrefix(p) => write!(f, "invalid prefix length {}", p),
+            CidrError::HostBitsSet(cidr) => write!(f, "cidr block {} has host bits set", cidr),
+        }
+    }
+}
+
+impl Error for CidrError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+    struct TestDummyStruct {
+        pub a: u32,
+        pub b: bool,
+        pub c: String,
+    }
+
+    #[test]
+    fn trie_with_custom_struct() {
+        let mut t: Trie<TestDummyStruct> = Trie::empty();
+        let tds = TestDummyStruct {
+            a: 23,
+            b: true,
+            c: "Hello Radix Trie!".to_string(),
+        };
+        t.insert_net_and_prefix(u32::from(Ipv4Addr::new(183, 40, 20, 0)), 8, tds.clone());
+        assert_eq!(
+            vec![&tds],
+            t.get(u32::from(Ipv4Addr::new(183, 41, 24, 249)))
+        );
+        assert_eq!(23, t.get(u32::from(Ipv4Addr::new(183, 41, 24, 249)))[0].a);
+    }
+
+    #[test]
+    fn trie_with_many_custom_struct() {
+        let mut t: Trie<TestDummyStruct> = Trie::empty();
+        let tds1 = TestDummyStruct {
+            a: 1,
+            b: true,
+            c: "Hello Radix Trie!".to_string(),
+        };
+        let tds2 = TestDummyStruct {
+            a: 69,
+            b: false,
+            c: "Goodbye...".to_string(),
+        };
+        let tds3 = TestDummyStruct {
+            a: 420,
+            b: true,
+            c: "Don't you dare go hollow.".to_string(),
+        };
+
+        t.insert_net_and_prefix(u32::from(Ipv4Addr::new(183, 210, 129, 0)), 2, tds1.clone());
+        t.insert_net_and_prefix(u32::from(Ipv4Addr::new(183, 40, 31, 0)), 8, tds2.clone());
+        t.insert_net_and_prefix(u32::from(Ipv4Addr::new(10, 123, 42, 0)), 24, tds3.clone());
+
+        let g = t.get(u32::from(Ipv4Addr::new(183, 123, 59, 3)));
+        assert_eq!(vec![&tds1, &tds2], g);
+
+        let gg = t.get(u32::from(Ipv4Addr::new(10, 123, 42, 250)));
+        assert_eq!(vec![&tds3], gg);
+        assert_eq!(
+            Vec::<&TestDummyStruct>::new(),
+            t.get(u32::from(Ipv4Addr::new(20, 159, 30, 42)))
+        );
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn insert_from_net_and_prefix() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_net_and_prefix(u32::from(Ipv4Addr::new(183, 40, 20, 0)), 8, 49);
+        t.insert_net_and_prefix(u32::from(Ipv4Addr::new(183, 40, 21, 3)), 16, 150);
+        t.insert_net_and_prefix(u32::from(Ipv4Addr::new(20, 30, 40, 0)), 31, 420);
+
+        assert_eq!(
+            false,
+            t.contains_ip(u32::from(Ipv4Addr::new(182, 41, 21, 3)))
+        );
+        assert_eq!(
+            vec![&49, &150],
+            t.get(u32::from(Ipv4Addr::new(183, 40, 25, 59)))
+        );
+        assert_eq!(vec![&420], t.get(u32::from(Ipv4Addr::new(20, 30, 40, 1))));
+    }
+
+    #[test]
+    fn cidr_block_from_str_ok() {
+        let cb = CidrBlock::from_str("127.0.1.40/30").unwrap();
+        assert_eq!(u32::from(Ipv4Addr::new(127, 0, 1, 40)), cb.net);
+        assert_eq!(30, cb.prefix);
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn write_and_load_trie_ok() {
         let mut t = Trie::empty();
         t.insert_cidr("50.178.3.0/16", 3);
         t.insert_cidr("214.0.0.0/24", 128);
         t.write_to_file("./test-trie.bin");
 
-        assert_eq!(true, t.contains_ip(Ipv4Addr::new(50, 178, 3, 6).into()));
-        assert_eq!(vec![&128], t.get(Ipv4Addr::new(214, 0, 0, 39).into()));
+        assert_eq!(true, t.contains_ip(u32::from(Ipv4Addr::new(50, 178, 3, 6))));
+        assert_eq!(vec![&128], t.get(u32::from(Ipv4Addr::new(214, 0, 0, 39))));
+
+        let mut tt = Trie::read_from_file("./test-trie.bin");
+        assert_eq!(t, tt);
+
+        tt.insert_cidr("33.12.14.0/24", 420);
+        assert_eq!(
+            false,
+            t.contains_ip(u32::from(Ipv4Addr::new(33, 12, 14, 15)))
+        );
+        assert_eq!(
+            true,
+            tt.contains_ip(u32::from(Ipv4Addr::new(33, 12, 14, 15)))
+        );
+    }
+
+    #[derive(Copy, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+    struct TestMaxMindData {
+        pub geoname_id: u32,
+        pub is_latest: bool,
+    }
+
+    #[test]
+    fn maxmind_data() {
+        let mmd = TestMaxMindData {
+            geoname_id: 2655045,
+            is_latest: true,
+        };
+
+        let mut t: Trie<TestMaxMindData> = Trie::empty();
+        t.insert_net_and_prefix(u32::from(Ipv4Addr::new(183, 210, 129, 0)), 2, mmd);
+        assert_eq!(vec![&mmd], t.get(u32::from(Ipv4Addr::new(184, 23, 0, 15))));
+    }
+
+    #[test]
+    fn compact_prunes_empty_branches() {
+        let empty = TrieNode::new(None, None, Some(Vec::new()));
+        let left = TrieNode::new(Some(Box::new(empty)), None, None);
+        let mut t: Trie<u32> = Trie::new(TrieNode::new(Some(Box::new(left)), None, None));
+        t.insert_cidr("200.0.0.0/8", 7);
+
+        t.compact();
+
+        let mut expected: Trie<u32> = Trie::empty();
+        expected.insert_cidr("200.0.0.0/8", 7);
+        assert_eq!(expected, t);
+    }
+
+    #[test]
+    fn aggregate_merges_identical_siblings() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/25", 1);
+        t.insert_cidr("10.0.0.128/25", 1);
+        t.insert_cidr("10.0.1.0/24", 1);
+        t.insert_cidr("10.0.2.0/24", 2);
+
+        t.aggregate();
+
+        let mut expected: Trie<u32> = Trie::empty();
+        expected.insert_cidr("10.0.0.0/23", 1);
+        expected.insert_cidr("10.0.2.0/24", 2);
+        assert_eq!(expected, t);
+        assert_eq!(vec![&1], t.get(Ipv4Addr::new(10, 0, 1, 200)));
+    }
+
+    #[test]
+    fn insert_many_reports_invalid_blocks() {
+        let mut t: Trie<u32> = Trie::empty();
+        let summary = t.insert_many(vec![
+            (CidrBlock::from_str("10.0.0.0/8").unwrap(), 1),
+            (CidrBlock { net: 0, prefix: 33 }, 2),
+            (CidrBlock::from_str("0.0.0.0/0").unwrap(), 3),
+        ]);
+
+        assert_eq!(2, summary.inserted);
+        assert_eq!(vec![(1, TrieError::InvalidPrefix(33))], summary.errors);
+        assert_eq!(vec![&3, &1], t.get(Ipv4Addr::new(10, 1, 2, 3)));
+    }
+
+    #[test]
+    fn insert_many_sorted_keeps_value_order() {
+        let items = || {
+            vec![
+                (CidrBlock::from_str("20.0.0.0/8").unwrap(), 1),
+                (CidrBlock::from_str("10.0.0.0/8").unwrap(), 2),
+                (CidrBlock { net: 0, prefix: 40 }, 3),
+                (CidrBlock::from_str("20.0.0.0/8").unwrap(), 4),
+            ]
+        };
+
+        let mut sorted: Trie<u32> = Trie::empty();
+        let summary = sorted.insert_many_sorted(items());
+        assert_eq!(vec![(2, TrieError::InvalidPrefix(40))], summary.errors);
+
+        let mut unsorted: Trie<u32> = Trie::empty();
+        unsorted.insert_many(items());
+        assert_eq!(unsorted, sorted);
+        assert_eq!(vec![&1, &4], sorted.get(Ipv4Addr::new(20, 0, 0, 1)));
+    }
+
+    #[test]
+    fn par_extend_matches_sequential_inserts() {
+        let blocks: Vec<(u32, u32)> = crate::util::generate_cidr_blocks(5_000);
+        let items = || {
+            blocks
+                .iter()
+                .enumerate()
+                .map(|(i, (net, prefix))| {
+                    (
+                        CidrBlock {
+                            net: *net,
+                            prefix: *prefix,
+                        },
+                        i,
+                    )
+                })
+                .collect::<Vec<(CidrBlock, usize)>>()
+        };
+
+        let mut sequential: Trie<usize> = Trie::empty();
+        sequential.insert_cidr("64.0.0.0/2", usize::MAX);
+        sequential.insert_many(items());
+
+        let mut parallel: Trie<usize> = Trie::empty();
+        parallel.insert_cidr("64.0.0.0/2", usize::MAX);
+        let summary = parallel.par_extend(items());
+
+        assert!(summary.is_ok());
+        assert_eq!(blocks.len(), summary.inserted);
+        assert_eq!(sequential, parallel);
+    }
+
+    #[test]
+    fn lookup_many_par_preserves_order() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.1.0.0/16", 2);
+
+        let ips: Vec<u32> = vec![
+            Ipv4Addr::new(10, 1, 2, 3).into(),
+            Ipv4Addr::new(11, 0, 0, 0).into(),
+            Ipv4Addr::new(10, 2, 0, 0).into(),
+        ];
+        assert_eq!(
+            vec![vec![&1, &2], vec![], vec![&1]],
+            t.lookup_many_par(&ips)
+        );
+    }
+
+    #[test]
+    fn for_each_match_visits_every_ip() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.1.0.0/16", 2);
+
+        let ips: Vec<u32> = vec![
+            Ipv4Addr::new(10, 1, 2, 3).into(),
+            Ipv4Addr::new(11, 0, 0, 0).into(),
+            Ipv4Addr::new(10, 2, 0, 0).into(),
+        ];
+
+        let mut lines: Vec<String> = Vec::new();
+        t.for_each_match(ips.iter().copied(), |ip, values| {
+            lines.push(format!("{} {:?}", Ipv4Addr::from(ip), values));
+        });
+        assert_eq!(
+            vec!["10.1.2.3 [1, 2]", "11.0.0.0 []", "10.2.0.0 [1]"],
+            lines
+        );
+    }
+
+    #[test]
+    fn owned_results_outlive_the_trie() {
+        let mut t: Trie<String> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", "ten".to_string());
+        let cloned: Vec<String> = t.get_cloned(Ipv4Addr::new(10, 0, 0, 1));
 
-        let mut tt = Trie::read_from_file("./test-trie.bin");
-        assert_eq!(t, tt);
+        let mut shared: Trie<Arc<String>> = Trie::empty();
+        shared.insert_cidr("10.0.0.0/8", Arc::new("ten".to_string()));
+        let owned: Vec<Arc<String>> = shared.get_owned(Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(2, Arc::strong_count(&owned[0]));
 
-        tt.insert_cidr("33.12.14.0/24", 420);
-        assert_eq!(false, t.contains_ip(Ipv4Addr::new(33, 12, 14, 15).into()));
-        assert_eq!(true, tt.contains_ip(Ipv4Addr::new(33, 12, 14, 15).into()));
+        drop(t);
+        drop(shared);
+        let handle = std::thread::spawn(move || (cloned, owned));
+        let (cloned, owned) = handle.join().unwrap();
+        assert_eq!(vec!["ten".to_string()], cloned);
+        assert_eq!("ten", owned[0].as_str());
     }
 
-    #[derive(Copy, Clone, Debug, Decode, Encode, Eq, PartialEq)]
-    struct TestMaxMindData {
-        pub geoname_id: u32,
-        pub is_latest: bool,
+    #[test]
+    fn ip_address_keys() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_net_and_prefix(Ipv4Addr::new(10, 0, 0, 0), 8, 1);
+        t.insert_net_and_prefix([10, 1, 0, 0], 16, 2);
+
+        let ip = Ipv4Addr::new(10, 1, 2, 3);
+        assert_eq!(vec![&1, &2], t.get(ip));
+        assert_eq!(t.get(ip), t.get(u32::from(ip)));
+        assert_eq!(t.get(ip), t.get(IpAddr::V4(ip)));
+        assert_eq!(t.get(ip), t.get(ip.to_ipv6_mapped()));
+        assert!(!t.contains_ip(IpAddr::V6("2001:db8::1".parse().unwrap())));
     }
 
     #[test]
-    fn maxmind_data() {
-        let mmd = TestMaxMindData {
-            geoname_id: 2655045,
-            is_latest: true,
+    fn index_returns_most_specific_values() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.1.0.0/16", 2);
+        t.insert_cidr("10.1.0.0/16", 3);
+
+        assert_eq!(&[2, 3], &t[Ipv4Addr::new(10, 1, 2, 3)]);
+        assert_eq!(&[1], &t[u32::from(Ipv4Addr::new(10, 2, 0, 0))]);
+        assert!(t[Ipv4Addr::new(11, 0, 0, 0)].is_empty());
+    }
+
+    #[test]
+    fn cidr_block_accessors() {
+        let cb = CidrBlock::try_from((Ipv4Addr::new(10, 20, 30, 40), 22)).unwrap();
+        assert_eq!("10.20.30.40/22", cb.to_string());
+        assert_eq!(Ipv4Addr::new(10, 20, 28, 0), cb.network());
+        assert_eq!(Ipv4Addr::new(10, 20, 31, 255), cb.broadcast());
+        assert!(cb.contains(Ipv4Addr::new(10, 20, 28, 0)));
+        assert!(cb.contains(Ipv4Addr::new(10, 20, 31, 255)));
+        assert!(!cb.contains(Ipv4Addr::new(10, 20, 32, 0)));
+
+        let all = CidrBlock::from_str("0.0.0.0/0").unwrap();
+        assert_eq!(Ipv4Addr::new(255, 255, 255, 255), all.broadcast());
+        assert!(all.contains(u32::MAX));
+
+        // Blocks built from the fields directly may have too long prefixes, which
+        // are treated like a /32.
+        let host = CidrBlock {
+            net: 0x0a000001,
+            prefix: 40,
         };
+        assert_eq!(Ipv4Addr::new(10, 0, 0, 1), host.network());
+        assert_eq!(Ipv4Addr::new(10, 0, 0, 1), host.broadcast());
+        assert!(host.contains(Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(!host.contains(Ipv4Addr::new(10, 0, 0, 2)));
 
-        let mut t: Trie<TestMaxMindData> = Trie::empty();
-        t.insert_net_and_prefix(Ipv4Addr::new(183, 210, 129, 0).into(), 2, mmd);
-        assert_eq!(vec![&mmd], t.get(Ipv4Addr::new(184, 23, 0, 15).into()));
+        let mut blocks = vec![cb, all, CidrBlock::from_str("10.20.30.40/22").unwrap()];
+        blocks.sort();
+        blocks.dedup();
+        assert_eq!(vec![all, cb], blocks);
+    }
+
+    #[test]
+    fn cidr_block_validation() {
+        let net = Ipv4Addr::new(10, 0, 0, 1);
+        assert_eq!(Err(CidrError::InvalidPrefix(33)), CidrBlock::new(net, 33));
+        assert_eq!(
+            Err(CidrError::InvalidPrefix(40)),
+            CidrBlock::try_from((net, 40))
+        );
+        assert_eq!(
+            Err(CidrError::HostBitsSet(CidrBlock::new(net, 24).unwrap())),
+            CidrBlock::new_strict(net, 24)
+        );
+        assert!(CidrBlock::new_strict(net, 32).is_ok());
+        assert!(CidrBlock::new_strict(Ipv4Addr::new(10, 0, 0, 0), 24).is_ok());
+
+        for s in ["10.0.0.0", "10.0.0/8", "10.0.0.0/x", "10.0.0.0/33"] {
+            assert!(CidrBlock::from_str(s).is_err());
+        }
+    }
+
+    #[test]
+    fn cidr_block_addresses() {
+        let cb = CidrBlock::from_str("192.168.1.9/30").unwrap();
+        assert_eq!(4, cb.len());
+        assert_eq!(
+            vec![
+                Ipv4Addr::new(192, 168, 1, 8),
+                Ipv4Addr::new(192, 168, 1, 9),
+                Ipv4Addr::new(192, 168, 1, 10),
+                Ipv4Addr::new(192, 168, 1, 11),
+            ],
+            cb.addresses().collect::<Vec<Ipv4Addr>>()
+        );
+        assert_eq!(
+            vec![
+                Ipv4Addr::new(192, 168, 1, 10),
+                Ipv4Addr::new(192, 168, 1, 9)
+            ],
+            cb.hosts().rev().collect::<Vec<Ipv4Addr>>()
+        );
+
+        let p2p = CidrBlock::from_str("10.0.0.0/31").unwrap();
+        assert_eq!(2, p2p.hosts().count());
+
+        let host = CidrBlock {
+            net: 0x0a000001,
+            prefix: 40,
+        };
+        assert_eq!(1, host.len());
+        assert_eq!(
+            vec![Ipv4Addr::new(10, 0, 0, 1)],
+            host.hosts().collect::<Vec<_>>()
+        );
+
+        let all = CidrBlock::from_str("0.0.0.0/0").unwrap();
+        assert_eq!(1 << 32, all.len());
+        assert_eq!(
+            Some(Ipv4Addr::new(255, 255, 255, 255)),
+            all.addresses().next_back()
+        );
+    }
+
+    #[test]
+    fn iter_visits_blocks_in_order() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.1.0.0/16", 2);
+        t.insert_cidr("0.0.0.0/0", 0);
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("255.255.255.255/32", 3);
+
+        let blocks: Vec<String> = t
+            .iter()
+            .map(|(cidr, values)| format!("{} {:?}", cidr, values))
+            .collect();
+        assert_eq!(
+            vec![
+                "0.0.0.0/0 [0]",
+                "10.0.0.0/8 [1]",
+                "10.1.0.0/16 [2]",
+                "255.255.255.255/32 [3]"
+            ],
+            blocks
+        );
+    }
+
+    #[test]
+    fn content_and_lookup_equality() {
+        let mut a: Trie<u32> = Trie::empty();
+        a.insert_cidr("10.0.0.0/25", 1);
+        a.insert_cidr("10.0.0.128/25", 1);
+        a.insert_cidr("20.0.0.0/8", 2);
+        a.insert_cidr("20.0.0.0/8", 3);
+
+        let mut b: Trie<u32> = Trie::new(TrieNode::new(
+            None,
+            Some(Box::new(TrieNode::new(None, None, Some(Vec::new())))),
+            None,
+        ));
+        b.insert_cidr("20.0.0.0/8", 3);
+        b.insert_cidr("20.0.0.0/8", 2);
+        b.insert_cidr("10.0.0.128/25", 1);
+        b.insert_cidr("10.0.0.0/25", 1);
+
+        assert!(a != b);
+        assert!(a.content_eq(&b));
+        assert!(a.lookup_eq(&b));
+
+        b.aggregate();
+        assert!(!a.content_eq(&b));
+        assert!(a.lookup_eq(&b));
+
+        b.insert_cidr("10.0.0.0/26", 1);
+        assert!(!a.lookup_eq(&b));
+    }
+
+    #[test]
+    fn fingerprint_ignores_insertion_order() {
+        let mut a: Trie<String> = Trie::empty();
+        a.insert_cidr("10.0.0.0/8", "a".to_string());
+        a.insert_cidr("10.0.0.0/8", "b".to_string());
+        a.insert_cidr("20.0.0.0/16", "c".to_string());
+
+        let mut b: Trie<String> = Trie::empty();
+        b.insert_cidr("20.0.0.0/16", "c".to_string());
+        b.insert_cidr("10.0.0.0/8", "b".to_string());
+        b.insert_cidr("10.0.0.0/8", "a".to_string());
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_ne!(Trie::<String>::empty().fingerprint(), a.fingerprint());
+
+        b.insert_cidr("20.0.0.0/17", "c".to_string());
+        assert_ne!(a.fingerprint(), b.fingerprint());
+    }
+
+    #[test]
+    fn filtered_and_transformed_copies() {
+        let mut t: Trie<(String, String)> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", ("SE".to_string(), "Stockholm".to_string()));
+        t.insert_cidr("10.1.0.0/16", ("NO".to_string(), "Oslo".to_string()));
+        t.insert_cidr("20.0.0.0/8", ("DK".to_string(), "Aarhus".to_string()));
+
+        let countries: Trie<String> = t.filter_map(|_, (country, _)| Some(country.clone()));
+        assert_eq!(vec!["SE", "NO"], countries.get(Ipv4Addr::new(10, 1, 0, 1)));
+
+        let short: Trie<(String, String)> = t.clone_filtered(|cidr, _| cidr.prefix <= 8);
+        let mut expected: Trie<(String, String)> = Trie::empty();
+        expected.insert_cidr("10.0.0.0/8", ("SE".to_string(), "Stockholm".to_string()));
+        expected.insert_cidr("20.0.0.0/8", ("DK".to_string(), "Aarhus".to_string()));
+        assert_eq!(expected, short);
+        assert_eq!(3, t.iter().count());
+    }
+
+    #[test]
+    fn filtered_encoding_decodes_to_filtered_trie() {
+        let mut t: Trie<u32> = Trie::empty();
+        for (i, (net, prefix)) in crate::util::generate_cidr_blocks(2_000)
+            .into_iter()
+            .enumerate()
+        {
+            t.insert_net_and_prefix(net, prefix, i as u32);
+        }
+
+        let mut full: Vec<u8> = Vec::new();
+        t.encode_to_writer(&mut full).unwrap();
+        let decoded: Trie<u32> = bincode::decode_from_slice(&full, config::standard())
+            .unwrap()
+            .0;
+        assert_eq!(t, decoded);
+        assert_eq!(t, Trie::decode_from_reader(&mut full.as_slice()).unwrap());
+
+        let first_half = |cidr: &CidrBlock| cidr.prefix >= 1 && cidr.net < (1 << 31);
+        let mut sliced: Vec<u8> = Vec::new();
+        t.encode_to_writer_filtered(&mut sliced, first_half)
+            .unwrap();
+        let decoded: Trie<u32> = bincode::decode_from_slice(&sliced, config::standard())
+            .unwrap()
+            .0;
+        assert_eq!(t.clone_filtered(|cidr, _| first_half(cidr)), decoded);
+        assert!(sliced.len() < full.len());
+    }
+
+    #[test]
+    fn covering_range_blocks() {
+        let cidrs = |first: [u8; 4], last: [u8; 4]| {
+            CidrBlock::covering_range(u32::from_be_bytes(first), u32::from_be_bytes(last))
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(vec!["10.0.0.0/24"], cidrs([10, 0, 0, 0], [10, 0, 0, 255]));
+        assert_eq!(
+            vec!["10.0.0.1/32", "10.0.0.2/31", "10.0.0.4/30", "10.0.0.8/32"],
+            cidrs([10, 0, 0, 1], [10, 0, 0, 8])
+        );
+        assert_eq!(vec!["0.0.0.0/0"], cidrs([0, 0, 0, 0], [255, 255, 255, 255]));
+        assert_eq!(
+            vec!["255.255.255.255/32"],
+            cidrs([255, 255, 255, 255], [255, 255, 255, 255])
+        );
+        assert!(cidrs([10, 0, 0, 1], [10, 0, 0, 0]).is_empty());
+    }
+
+    #[test]
+    fn anonymize_host_blocks() {
+        let mut t: Trie<&str> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", "corp");
+        t.insert_cidr("10.1.2.0/24", "office");
+        t.insert_cidr("10.1.2.3/32", "scanner");
+        t.insert_cidr("10.1.2.4/31", "office");
+        t.insert_cidr("10.1.2.9/32", "scanner");
+        t.insert_cidr("10.1.3.1/32", "scanner");
+        t.anonymize(24);
+
+        let blocks: Vec<(String, Vec<&str>)> = t
+            .iter()
+            .map(|(cidr, values)| (cidr.to_string(), values.to_vec()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("10.0.0.0/8".to_string(), vec!["corp"]),
+                ("10.1.2.0/24".to_string(), vec!["office", "scanner"]),
+                ("10.1.3.0/24".to_string(), vec!["scanner"]),
+            ],
+            blocks
+        );
+
+        let before = t.clone();
+        t.anonymize(32);
+        assert_eq!(before, t);
+    }
+
+    #[test]
+    fn get_blocks_and_exact() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("0.0.0.0/0", 0);
+        t.insert_cidr("10.0.0.0/8", 8);
+        t.insert_cidr("10.1.0.0/16", 16);
+        t.insert_cidr("10.1.0.0/16", 17);
+        t.insert_cidr("10.1.2.3/32", 32);
+
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        assert_eq!(
+            vec![
+                (cidr("0.0.0.0/0"), &[0][..]),
+                (cidr("10.0.0.0/8"), &[8][..]),
+                (cidr("10.1.0.0/16"), &[16, 17][..]),
+                (cidr("10.1.2.3/32"), &[32][..]),
+            ],
+            t.get_blocks(Ipv4Addr::new(10, 1, 2, 3))
+        );
+        assert_eq!(
+            vec![(cidr("0.0.0.0/0"), &[0][..])],
+            t.get_blocks(Ipv4Addr::new(11, 0, 0, 1))
+        );
+
+        assert_eq!(&[16, 17], t.get_exact(&cidr("10.1.0.0/16")));
+        assert_eq!(&[0], t.get_exact(&cidr("0.0.0.0/0")));
+        assert!(t.get_exact(&cidr("10.1.0.0/24")).is_empty());
+        assert!(t.get_exact(&cidr("10.1.2.0/24")).is_empty());
+    }
+
+    #[test]
+    fn retain_values() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.0.0.0/8", 2);
+        t.insert_cidr("10.1.0.0/16", 3);
+        t.insert_cidr("10.1.2.0/24", 4);
+
+        assert_eq!(2, t.retain(|cidr, v| cidr.prefix != 24 && v % 2 == 1));
+
+        let mut expected: Trie<u32> = Trie::empty();
+        expected.insert_cidr("10.0.0.0/8", 1);
+        expected.insert_cidr("10.1.0.0/16", 3);
+        assert_eq!(expected, t);
+    }
+
+    #[test]
+    fn remove_blocks_and_values() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.1.0.0/16", 2);
+        t.insert_cidr("10.1.0.0/16", 3);
+        t.insert_cidr("10.1.0.0/16", 2);
+
+        assert!(t.remove_value(&cidr("10.1.0.0/16"), &2));
+        assert_eq!(&[2, 3], t.get_exact(&cidr("10.1.0.0/16")));
+        assert!(!t.remove_value(&cidr("10.1.0.0/16"), &4));
+        assert!(!t.remove_value(&cidr("10.1.2.0/24"), &2));
+
+        t.get_exact_mut(&cidr("10.1.0.0/16"))[0] = 5;
+        assert!(t.get_exact_mut(&cidr("10.2.0.0/16")).is_empty());
+
+        assert_eq!(vec![5, 3], t.remove(&cidr("10.1.0.0/16")));
+        assert!(t.remove(&cidr("10.1.0.0/16")).is_empty());
+
+        let mut expected: Trie<u32> = Trie::empty();
+        expected.insert_cidr("10.0.0.0/8", 1);
+        assert_eq!(expected, t);
+    }
+
+    #[test]
+    fn update_exact_values() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.0.0.0/8", 3);
+
+        assert_eq!(
+            Ok(()),
+            t.update_exact(&cidr("10.0.0.0/8"), |v| v.insert(1, 2))
+        );
+        assert_eq!(&[1, 2, 3], t.get_exact(&cidr("10.0.0.0/8")));
+
+        assert_eq!(Ok(0), t.update_exact(&cidr("10.1.0.0/16"), |v| v.len()));
+        assert_eq!(Ok(()), t.update_exact(&cidr("10.0.0.0/8"), |v| v.clear()));
+
+        assert_eq!(Trie::empty(), t);
+        assert_eq!(
+            Err(TrieError::InvalidPrefix(33)),
+            t.update_exact(&CidrBlock { net: 0, prefix: 33 }, |v| v.len())
+        );
+    }
+
+    #[test]
+    fn node_at_introspection() {
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.0.0.0/8", 2);
+        t.insert_cidr("10.0.0.0/9", 3);
+        t.insert_cidr("10.128.0.0/9", 4);
+        t.insert_cidr("10.128.0.0/10", 5);
+
+        let node = t.node_at(Ipv4Addr::new(10, 1, 2, 3), 8).unwrap();
+        assert_eq!(CidrBlock::from_str("10.0.0.0/8").unwrap(), node.cidr());
+        assert_eq!(&[1, 2], node.values());
+        assert!(node.has_left() && node.has_right());
+        assert_eq!(5, node.subtree_values());
+        assert_eq!(4, node.subtree_nodes());
+
+        let right = node.right().unwrap();
+        assert_eq!(CidrBlock::from_str("10.128.0.0/9").unwrap(), right.cidr());
+        assert_eq!(&[4], right.values());
+        assert!(right.has_left() && !right.has_right());
+        assert_eq!(2, right.subtree_values());
+
+        let root = t.node_at(0u32, 0).unwrap();
+        assert!(root.values().is_empty());
+        assert_eq!(5, root.subtree_values());
+
+        assert!(t.node_at(Ipv4Addr::new(10, 0, 0, 0), 10).is_none());
+        assert!(t.node_at(Ipv4Addr::new(10, 0, 0, 0), 33).is_none());
+
+        t.insert_cidr("255.255.255.255/32", 6);
+        let host = t.node_at(u32::MAX, 32).unwrap();
+        assert_eq!(&[6], host.values());
+        assert!(host.left().is_none() && host.right().is_none());
+        assert_eq!(1, host.subtree_values());
+        assert_eq!(1, host.subtree_nodes());
+        assert_eq!(6, t.node_at(0u32, 0).unwrap().subtree_values());
+    }
+
+    #[test]
+    fn depth_and_height() {
+        let mut t: Trie<u32> = Trie::empty();
+        assert_eq!(0, t.max_depth());
+        assert_eq!(0, t.longest_chain());
+
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.1.2.0/24", 2);
+        t.insert_cidr("10.128.0.0/9", 3);
+
+        assert_eq!(24, t.max_depth());
+        assert_eq!(
+            Some(8),
+            t.depth_of(&CidrBlock::from_str("10.0.0.0/8").unwrap())
+        );
+        assert_eq!(
+            Some(12),
+            t.depth_of(&CidrBlock::from_str("10.0.0.0/12").unwrap())
+        );
+        assert_eq!(
+            None,
+            t.depth_of(&CidrBlock::from_str("11.0.0.0/8").unwrap())
+        );
+
+        let node = t.node_at(Ipv4Addr::new(10, 0, 0, 0), 8).unwrap();
+        assert_eq!(16, node.height());
+        assert_eq!(0, node.right().unwrap().height());
+        // The root to 10.0.0.0/8 and the /9 down to the /24 are valueless chains.
+        assert_eq!(15, t.longest_chain());
+
+        assert_eq!(24, t.walk_depth(Ipv4Addr::new(10, 1, 2, 9)));
+        assert_eq!(15, t.walk_depth(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(9, t.walk_depth(Ipv4Addr::new(10, 200, 0, 1)));
+        assert_eq!(0, t.walk_depth(Ipv4Addr::new(200, 0, 0, 1)));
+    }
+
+    #[test]
+    fn coverage_superset() {
+        let mut old: Trie<u32> = Trie::empty();
+        old.insert_cidr("10.0.0.0/25", 1);
+        old.insert_cidr("10.0.0.128/25", 2);
+        old.insert_cidr("192.168.1.0/24", 3);
+
+        let mut new: Trie<&str> = Trie::empty();
+        new.insert_cidr("10.0.0.0/24", "allow");
+        new.insert_cidr("192.168.0.0/16", "allow");
+        new.insert_cidr("192.168.1.0/24", "allow");
+
+        assert!(new.covers(&old));
+        assert!(old.is_covered_by(&new));
+        assert!(!old.covers(&new));
+        assert!(old.covers(&old));
+        assert!(old.covers(&Trie::<u32>::empty()));
+        assert!(!Trie::<u32>::empty().covers(&old));
+
+        let mut all: Trie<()> = Trie::empty();
+        all.insert_cidr("0.0.0.0/0", ());
+        assert!(all.covers(&new));
+        assert!(!Trie::<u32>::empty().covers(&all));
+    }
+
+    #[test]
+    fn coverage_xor_blocks() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut old: Trie<u32> = Trie::empty();
+        old.insert_cidr("10.0.0.0/24", 1);
+        old.insert_cidr("192.168.0.0/24", 2);
+
+        let mut new: Trie<u32> = Trie::empty();
+        new.insert_cidr("10.0.0.0/25", 1);
+        new.insert_cidr("10.0.0.128/25", 1);
+        new.insert_cidr("192.168.0.0/25", 2);
+        new.insert_cidr("172.16.0.0/12", 3);
+
+        assert_eq!(
+            vec![cidr("172.16.0.0/12"), cidr("192.168.0.128/25")],
+            old.coverage_xor(&new)
+        );
+        assert_eq!(old.coverage_xor(&new), new.coverage_xor(&old));
+        assert!(old.coverage_xor(&old).is_empty());
+
+        let mut all: Trie<u32> = Trie::empty();
+        all.insert_cidr("0.0.0.0/0", 0);
+        assert_eq!(
+            vec![cidr("0.0.0.0/0")],
+            all.coverage_xor(&Trie::<u32>::empty())
+        );
+    }
+
+    #[test]
+    fn coverage_complement_blocks() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut allow: Trie<()> = Trie::empty();
+        allow.insert_cidr("128.0.0.0/2", ());
+        allow.insert_cidr("192.0.0.0/3", ());
+        allow.insert_cidr("10.0.0.0/8", ());
+        allow.insert_cidr("10.1.0.0/16", ());
+
+        let deny: Vec<CidrBlock> = allow.coverage_complement();
+        assert_eq!(
+            vec![
+                cidr("0.0.0.0/5"),
+                cidr("8.0.0.0/7"),
+                cidr("11.0.0.0/8"),
+                cidr("12.0.0.0/6"),
+                cidr("16.0.0.0/4"),
+                cidr("32.0.0.0/3"),
+                cidr("64.0.0.0/2"),
+                cidr("224.0.0.0/3"),
+            ],
+            deny
+        );
+
+        let mut denied: Trie<()> = Trie::empty();
+        for c in deny.iter() {
+            denied.try_insert(c, ()).unwrap();
+        }
+        assert_eq!(vec![cidr("0.0.0.0/0")], denied.coverage_xor(&allow));
+        assert_eq!(
+            vec![cidr("10.0.0.0/8"), cidr("128.0.0.0/2"), cidr("192.0.0.0/3")],
+            denied.coverage_complement()
+        );
+        assert_eq!(
+            vec![cidr("0.0.0.0/0")],
+            Trie::<()>::empty().coverage_complement()
+        );
+    }
+
+    #[test]
+    fn intersect_with_filter() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut feed: Trie<&str> = Trie::empty();
+        feed.insert_cidr("10.0.0.0/8", "a");
+        feed.insert_cidr("10.0.0.0/8", "b");
+        feed.insert_cidr("10.1.2.0/24", "c");
+        feed.insert_cidr("192.168.0.0/16", "d");
+        feed.insert_cidr("172.16.0.0/12", "e");
+
+        let mut announced: Trie<u32> = Trie::empty();
+        announced.insert_cidr("10.1.0.0/16", 1);
+        announced.insert_cidr("10.2.0.0/16", 2);
+        announced.insert_cidr("192.0.0.0/8", 3);
+
+        let scoped: Trie<&str> = feed.intersect_coverage(&announced);
+        assert_eq!(
+            vec![
+                (cidr("10.1.0.0/16"), &["a", "b"][..]),
+                (cidr("10.1.2.0/24"), &["c"][..]),
+                (cidr("10.2.0.0/16"), &["a", "b"][..]),
+                (cidr("192.168.0.0/16"), &["d"][..]),
+            ],
+            scoped.iter().collect::<Vec<_>>()
+        );
+        assert!(scoped.is_covered_by(&announced));
+        assert!(
+            feed.intersect_coverage(&Trie::<u32>::empty())
+                .iter()
+                .next()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn covers_whole_cidr() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("192.168.0.0/25", 2);
+        t.insert_cidr("192.168.0.128/26", 3);
+        t.insert_cidr("192.168.0.192/26", 4);
+        t.insert_cidr("172.16.0.0/25", 5);
+        t.insert_cidr("172.16.1.0/24", 6);
+
+        assert!(t.covers_cidr(&cidr("10.0.0.0/8")));
+        assert!(t.covers_cidr(&cidr("10.1.2.0/24")));
+        assert!(t.covers_cidr(&cidr("192.168.0.0/24")));
+        assert!(t.covers_cidr(&cidr("192.168.0.64/26")));
+        assert!(!t.covers_cidr(&cidr("172.16.0.0/24")));
+        assert!(!t.covers_cidr(&cidr("172.16.0.0/23")));
+        assert!(!t.covers_cidr(&cidr("0.0.0.0/0")));
+        assert!(!t.covers_cidr(&cidr("11.0.0.0/8")));
+        assert!(t.covers_cidr(&cidr("10.9.9.9/32")));
+    }
+
+    #[test]
+    fn overlapping_blocks() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("0.0.0.0/0", 0);
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.1.0.0/16", 2);
+        t.insert_cidr("10.1.0.0/16", 3);
+        t.insert_cidr("10.1.2.0/24", 4);
+        t.insert_cidr("10.1.128.0/17", 5);
+        t.insert_cidr("10.2.0.0/16", 6);
+
+        assert_eq!(
+            vec![
+                (cidr("0.0.0.0/0"), &[0][..]),
+                (cidr("10.0.0.0/8"), &[1][..]),
+                (cidr("10.1.0.0/16"), &[2, 3][..]),
+                (cidr("10.1.2.0/24"), &[4][..]),
+                (cidr("10.1.128.0/17"), &[5][..]),
+            ],
+            t.overlapping(&cidr("10.1.0.0/16"))
+        );
+        assert_eq!(
+            vec![
+                (cidr("0.0.0.0/0"), &[0][..]),
+                (cidr("10.0.0.0/8"), &[1][..])
+            ],
+            t.overlapping(&cidr("10.3.0.0/16"))
+        );
+        assert_eq!(6, t.overlapping(&cidr("0.0.0.0/0")).len());
+        assert_eq!(
+            vec![(cidr("0.0.0.0/0"), &[0][..])],
+            t.overlapping(&cidr("192.168.0.0/16"))
+        );
+    }
+
+    #[test]
+    fn common_supernet_of_ips() {
+        let ips: Vec<u32> = [
+            Ipv4Addr::new(203, 0, 113, 7),
+            Ipv4Addr::new(203, 0, 113, 200),
+            Ipv4Addr::new(203, 0, 112, 1),
+        ]
+        .map(u32::from)
+        .to_vec();
+        assert_eq!(
+            Some(CidrBlock::from_str("203.0.112.0/23").unwrap()),
+            Trie::<u32>::common_supernet(&ips)
+        );
+        assert_eq!(None, Trie::<u32>::common_supernet(&[]));
+    }
+
+    #[test]
+    fn anonymized_lookup_and_insert() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut t: Trie<u32> = Trie::empty();
+        t.insert_cidr("10.0.0.0/8", 1);
+        t.insert_cidr("10.1.2.128/25", 2);
+        t.insert_anonymized(&cidr("10.1.2.3/32"), 3, 24).unwrap();
+        t.insert_anonymized(&cidr("192.168.0.0/16"), 4, 24).unwrap();
+        assert_eq!(
+            Err(TrieError::InvalidPrefix(33)),
+            t.insert_anonymized(&CidrBlock { net: 0, prefix: 33 }, 5, 24)
+        );
+
+        assert_eq!(&[3], t.get_exact(&cidr("10.1.2.0/24")));
+        assert_eq!(&[4], t.get_exact(&cidr("192.168.0.0/16")));
+
+        let ip = Ipv4Addr::new(10, 1, 2, 200);
+        assert_eq!(vec![&1, &3, &2], t.get(ip));
+        assert_eq!(vec![&1, &3], t.lookup_anonymized(ip, 24));
+        assert_eq!(vec![&1], t.lookup_anonymized(ip, 8));
+        assert_eq!(t.get(ip), t.lookup_anonymized(ip, 40));
+        assert_eq!(cidr("10.1.0.0/16"), cidr("10.1.2.3/32").anonymized(16));
+        assert_eq!(cidr("10.0.0.0/8"), cidr("10.0.0.0/8").anonymized(16));
+    }
+
+    #[test]
+    fn write_and_read_sharded() {
+        let mut t: Trie<u32> = Trie::empty();
+        for (i, (net, prefix)) in crate::util::generate_cidr_blocks(2_000)
+            .into_iter()
+            .enumerate()
+        {
+            t.insert_net_and_prefix(net, prefix, i as u32);
+        }
+        t.insert_cidr("0.0.0.0/0", 1);
+        t.insert_cidr("10.0.0.0/7", 2);
+        t.insert_cidr("10.0.0.0/8", 3);
+
+        for shards in [1, 2, 16, 256] {
+            t.write_sharded("./test-trie-shards", shards).unwrap();
+            let read: Trie<u32> = Trie::read_sharded("./test-trie-shards").unwrap();
+            assert_eq!(
+                t.iter().collect::<Vec<_>>(),
+                read.iter().collect::<Vec<_>>()
+            );
+        }
+
+        assert!(matches!(
+            t.write_sharded("./test-trie-shards", 3),
+            Err(ShardError::InvalidShardCount(3))
+        ));
+        assert!(matches!(
+            Trie::<u32>::read_sharded("./test-trie-shards/missing"),
+            Err(ShardError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn write_and_read_indexed_file() {
+        let mut t: Trie<u32> = Trie::empty();
+        for (i, (net, prefix)) in crate::util::generate_cidr_blocks(2_000)
+            .into_iter()
+            .enumerate()
+        {
+            t.insert_net_and_prefix(net, prefix, i as u32);
+        }
+        t.insert_cidr("0.0.0.0/0", 1);
+        t.insert_cidr("10.0.0.0/7", 2);
+
+        for (shards, threads) in [(1, 1), (16, 4), (256, 0)] {
+            t.write_to_file_indexed("./test-trie-indexed.bin", shards)
+                .unwrap();
+            let read: Trie<u32> =
+                Trie::read_from_file_parallel("./test-trie-indexed.bin", threads).unwrap();
+            assert_eq!(
+                t.iter().collect::<Vec<_>>(),
+                read.iter().collect::<Vec<_>>()
+            );
+        }
+
+        // Files in the regular format are rejected rather than misread.
+        t.write_to_file("./test-trie-indexed.bin");
+        assert!(matches!(
+            Trie::<u32>::read_from_file_parallel("./test-trie-indexed.bin", 1),
+            Err(ShardError::NotIndexed)
+        ));
+    }
+
+    #[test]
+    fn nearest_match_distance() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut t: Trie<u32> = Trie::empty();
+        assert_eq!(None, t.nearest_match(Ipv4Addr::new(10, 0, 0, 5)));
+
+        t.insert_cidr("10.0.1.0/24", 1);
+        t.insert_cidr("192.168.0.0/16", 2);
+        t.insert_cidr("192.168.7.0/24", 3);
+
+        assert_eq!(
+            Some((cidr("10.0.1.0/24"), 9)),
+            t.nearest_match(Ipv4Addr::new(10, 0, 0, 5))
+        );
+        assert_eq!(
+            Some((cidr("10.0.1.0/24"), 10)),
+            t.nearest_match(Ipv4Addr::new(10, 0, 2, 5))
+        );
+        assert_eq!(
+            Some((cidr("192.168.7.0/24"), 0)),
+            t.nearest_match(Ipv4Addr::new(192, 168, 7, 1))
+        );
+        // The /16 and the /24 inside it are equally close, the /16 is less specific.
+        assert_eq!(
+            Some((cidr("192.168.0.0/16"), 17)),
+            t.nearest_match(Ipv4Addr::new(192, 169, 0, 1))
+        );
+        // The neighbouring /24 is closer than the shorter /16 far away.
+        assert_eq!(
+            Some((cidr("10.0.1.0/24"), 25)),
+            t.nearest_match(Ipv4Addr::new(11, 0, 0, 1))
+        );
+
+        t.insert_cidr("10.0.0.4/32", 4);
+        assert_eq!(
+            Some((cidr("10.0.0.4/32"), 1)),
+            t.nearest_match(Ipv4Addr::new(10, 0, 0, 5))
+        );
+        assert_eq!(
+            Some((cidr("10.0.0.4/32"), 8)),
+            t.nearest_match(Ipv4Addr::new(10, 0, 0, 200))
+        );
     }
 }
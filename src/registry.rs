@@ -0,0 +1,150 @@
+//! Several named tables served from one process, e.g. a geo, a threat and an asn
+//! table, each reloaded on its own schedule.
+
+use crate::concurrent::ConcurrentTrie;
+use crate::maintenance::{self, MaintainerHandle};
+use crate::radix_trie::Trie;
+
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Tables by name. The tables are shared, so readers looking up a table keep working
+/// on it while it is reloaded or replaced.
+pub struct TableRegistry<V> {
+    tables: RwLock<BTreeMap<String, ConcurrentTrie<V>>>,
+}
+
+impl<V> TableRegistry<V> {
+    /// Create a new registry without any tables.
+    pub fn new() -> Self {
+        TableRegistry {
+            tables: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Register the trie under the name and get the table. If the name is already
+    /// registered the trie is swapped into the existing table instead, so handles to
+    /// it keep seeing the current trie.
+    pub fn register(&self, name: impl Into<String>, trie: Trie<V>) -> ConcurrentTrie<V> {
+        let name: String = name.into();
+        let mut tables = self.tables.write().unwrap();
+        match tables.get(&name) {
+            Some(table) => {
+                table.swap(trie);
+                table.clone()
+            }
+            None => {
+                let table: ConcurrentTrie<V> = ConcurrentTrie::new(trie);
+                tables.insert(name, table.clone());
+                table
+            }
+        }
+    }
+
+    /// Get the table registered under the name, if any.
+    pub fn get(&self, name: &str) -> Option<ConcurrentTrie<V>> {
+        self.tables.read().unwrap().get(name).cloned()
+    }
+
+    /// Unregister the table, returning it if it was registered.
+    pub fn remove(&self, name: &str) -> Option<ConcurrentTrie<V>> {
+        self.tables.write().unwrap().remove(name)
+    }
+
+    /// Get the names of every registered table, in order.
+    pub fn names(&self) -> Vec<String> {
+        self.tables.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Run the closure on a snapshot of the table registered under the name, if any.
+    pub fn read<R>(&self, name: &str, f: impl FnOnce(&Trie<V>) -> R) -> Option<R> {
+        self.get(name).map(|table| table.read(f))
+    }
+}
+
+impl<V: Send + Sync + 'static> TableRegistry<V> {
+    /// Reload the table registered under the name every interval on a background
+    /// thread, until the returned handle is stopped. A failed load keeps the current
+    /// trie. Returns `None` if no table is registered under the name.
+    pub fn spawn_reload<E>(
+        &self,
+        name: &str,
+        interval: Duration,
+        load: impl Fn() -> Result<Trie<V>, E> + Send + 'static,
+    ) -> Option<MaintainerHandle> {
+        let table: ConcurrentTrie<V> = self.get(name)?;
+        Some(maintenance::spawn_periodic(interval, move || {
+            let _ = table.reload(&load);
+        }))
+    }
+}
+
+impl<V> Default for TableRegistry<V> {
+    fn default() -> Self {
+        TableRegistry::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::thread;
+
+    #[test]
+    fn named_tables() {
+        let trie = |cidr: &str, value: u32| {
+            let mut t: Trie<u32> = Trie::empty();
+            t.insert_cidr(cidr, value);
+            t
+        };
+
+        let registry: TableRegistry<u32> = TableRegistry::new();
+        let geo: ConcurrentTrie<u32> = registry.register("geo", trie("81.0.0.0/8", 1));
+        registry.register("threat", trie("10.0.0.0/8", 2));
+        assert_eq!(vec!["geo", "threat"], registry.names());
+
+        let ip = Ipv4Addr::new(10, 1, 2, 3);
+        let value = |name: &str| registry.read(name, |t| t.get(ip).first().map(|v| **v));
+        assert_eq!(Some(Some(2)), value("threat"));
+        assert_eq!(Some(false), registry.read("geo", |t| t.contains_ip(ip)));
+        assert_eq!(None, registry.read("asn", |t| t.contains_ip(ip)));
+
+        // Registering a name again swaps the trie into the existing table.
+        registry.register("geo", trie("10.0.0.0/8", 3));
+        assert!(geo.read(|t| t.contains_ip(ip)));
+
+        let reloads = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&reloads);
+        let handle = registry
+            .spawn_reload("threat", Duration::from_millis(1), move || {
+                let n: u32 = counter.fetch_add(1, Ordering::Relaxed);
+                if n.is_multiple_of(2) {
+                    Ok(trie("10.0.0.0/8", 10 + n))
+                } else {
+                    Err("unavailable")
+                }
+            })
+            .unwrap();
+        while reloads.load(Ordering::Relaxed) < 3 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        handle.stop();
+
+        // Failed loads kept the last loaded trie.
+        let reloaded: u32 = value("threat").flatten().unwrap();
+        assert!(reloaded >= 12 && reloaded.is_multiple_of(2));
+        assert!(
+            registry
+                .spawn_reload("asn", Duration::ZERO, || Err(()))
+                .is_none()
+        );
+
+        assert!(registry.remove("geo").is_some());
+        assert_eq!(vec!["threat"], registry.names());
+    }
+}
@@ -0,0 +1,292 @@
+//! Loaders for Tor exit node and VPN/proxy/hosting ip lists, and classification of
+//! ip addresses against them.
+//!
+//! The crate has no http client, so every list is fetched through a closure returning
+//! a reader over the list body, e.g. one wrapping the response of `reqwest` or `ureq`.
+
+use crate::maintenance::SharedTrie;
+use crate::radix_trie::{CidrBlock, Trie};
+
+use bincode::{Decode, Encode};
+
+use std::io::{self, BufRead};
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+/// The kind of ip addresses a list contains.
+#[derive(Clone, Copy, Debug, Decode, Encode, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ListKind {
+    Tor,
+    Vpn,
+    Hosting,
+}
+
+/// Classification of an ip address, from the most to the least specific.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Classification {
+    Tor,
+    Vpn,
+    Hosting,
+    /// The ip address is on none of the lists.
+    Residential,
+}
+
+/// The value stored for every block of a list.
+#[derive(Clone, Debug, Decode, Encode, Eq, Hash, PartialEq)]
+pub struct ListEntry {
+    /// Name of the list the block was loaded from.
+    pub list: String,
+    pub kind: ListKind,
+    /// Unix timestamp, in seconds, of when the list was fetched.
+    pub fetched_at: i64,
+}
+
+/// The format of a list body.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ListFormat {
+    /// One ip address or cidr block per line, e.g. the bulk Tor exit list or most
+    /// VPN and datacenter lists. Text after `#` or `;` is ignored.
+    Plain,
+    /// The TorDNSEL exit list, where exits are listed on `ExitAddress <ip> <date>` lines.
+    TorDnsel,
+}
+
+type Fetch = Box<dyn Fn() -> io::Result<Box<dyn BufRead>> + Send + Sync>;
+
+/// A named list and how to fetch it.
+pub struct ListSource {
+    pub name: String,
+    pub kind: ListKind,
+    pub format: ListFormat,
+    fetch: Fetch,
+}
+
+impl ListSource {
+    /// Create a list source fetching the list body through the closure.
+    pub fn new(
+        name: &str,
+        kind: ListKind,
+        format: ListFormat,
+        fetch: impl Fn() -> io::Result<Box<dyn BufRead>> + Send + Sync + 'static,
+    ) -> Self {
+        ListSource {
+            name: name.to_string(),
+            kind,
+            format,
+            fetch: Box::new(fetch),
+        }
+    }
+}
+
+/// Outcome of refreshing the lists.
+#[derive(Debug, Default)]
+pub struct RefreshSummary {
+    /// Number of blocks loaded per refreshed list.
+    pub loaded: Vec<(String, usize)>,
+    /// Lists that could not be fetched, whose previously loaded blocks were kept.
+    pub failed: Vec<(String, io::Error)>,
+}
+
+/// A set of ip lists loaded into a shared trie, which `refresh` rebuilds and swaps.
+pub struct ReputationLists {
+    sources: Vec<ListSource>,
+    table: SharedTrie<ListEntry>,
+}
+
+impl ReputationLists {
+    /// Create an empty set of lists, nothing is fetched until `refresh` is called.
+    pub fn new(sources: Vec<ListSource>) -> Self {
+        ReputationLists {
+            sources,
+            table: Arc::new(RwLock::new(Arc::new(Trie::empty()))),
+        }
+    }
+
+    /// Get the shared trie the lists are loaded into, e.g. for a `Maintainer`.
+    pub fn table(&self) -> SharedTrie<ListEntry> {
+        Arc::clone(&self.table)
+    }
+
+    /// Fetch every list, build a new trie from them and swap it in.
+    pub fn refresh(&self) -> RefreshSummary {
+        let current: Arc<Trie<ListEntry>> = Arc::clone(&self.table.read().unwrap());
+        let fetched_at: i64 = chrono::Utc::now().timestamp();
+
+        let mut summary = RefreshSummary::default();
+        let mut trie: Trie<ListEntry> = Trie::empty();
+
+        for source in self.sources.iter() {
+            let blocks: io::Result<Vec<CidrBlock>> =
+                (source.fetch)().and_then(|reader| parse_list(reader, source.format));
+
+            match blocks {
+                Ok(blocks) => {
+                    let entry = ListEntry {
+                        list: source.name.clone(),
+                        kind: source.kind,
+                        fetched_at,
+                    };
+                    summary.loaded.push((source.name.clone(), blocks.len()));
+                    for block in blocks.iter() {
+                        trie.try_insert(block, entry.clone()).unwrap();
+                    }
+                }
+                Err(e) => {
+                    for (block, entries) in current.iter() {
+                        for entry in entries.iter().filter(|e| e.list == source.name) {
+                            trie.try_insert(&block, entry.clone()).unwrap();
+                        }
+                    }
+                    summary.failed.push((source.name.clone(), e));
+                }
+            }
+        }
+
+        *self.table.write().unwrap() = Arc::new(trie);
+        summary
+    }
+
+    /// Classify the ip address, preferring Tor over VPN over hosting when it is on
+    /// lists of several kinds.
+    pub fn classify(&self, ip: Ipv4Addr) -> Classification {
+        classify(&self.table.read().unwrap(), ip)
+    }
+}
+
+/// Classify the ip address against a trie of loaded lists.
+pub fn classify(lists: &Trie<ListEntry>, ip: Ipv4Addr) -> Classification {
+    match lists.get(ip).into_iter().map(|e| e.kind).min() {
+        Some(ListKind::Tor) => Classification::Tor,
+        Some(ListKind::Vpn) => Classification::Vpn,
+        Some(ListKind::Hosting) => Classification::Hosting,
+        None => Classification::Residential,
+    }
+}
+
+/// Parse a list body into cidr blocks, single ip addresses become /32 blocks.
+///
+/// IPv6 addresses and lines that are neither ip addresses nor cidr blocks are skipped.
+pub fn parse_list<R: BufRead>(reader: R, format: ListFormat) -> io::Result<Vec<CidrBlock>> {
+    let mut blocks: Vec<CidrBlock> = Vec::new();
+
+    for line in reader.lines() {
+        let line: String = line?;
+        let token: Option<&str> = match format {
+            ListFormat::Plain => line.split(['#', ';']).next().map(str::trim),
+            ListFormat::TorDnsel => line
+                .strip_prefix("ExitAddress ")
+                .and_then(|rest| rest.split_whitespace().next()),
+        };
+
+        if let Some(block) = token.and_then(parse_block) {
+            blocks.push(block);
+        }
+    }
+
+    Ok(blocks)
+}
+
+fn parse_block(s: &str) -> Option<CidrBlock> {
+    if s.contains('/') {
+        CidrBlock::from_str(s).ok()
+    } else {
+        let ip: Ipv4Addr = s.parse().ok()?;
+        CidrBlock::new(ip, 32).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    const TORDNSEL: &str = "\
+ExitNode 0011BD2485AD45D984EC4159C88FC066E5E3300E
+Published 2024-01-01 10:00:00
+LastStatus 2024-01-01 11:00:00
+ExitAddress 185.220.101.1 2024-01-01 11:02:03
+";
+
+    const HOSTING: &str = "\
+# datacenter ranges
+3.0.0.0/9 ; aws
+185.220.0.0/16
+2600:1f00::/24
+";
+
+    fn source(name: &str, kind: ListKind, format: ListFormat, body: &'static str) -> ListSource {
+        ListSource::new(name, kind, format, move || {
+            Ok(Box::new(body.as_bytes()) as Box<dyn BufRead>)
+        })
+    }
+
+    #[test]
+    fn classify_ips() {
+        let lists = ReputationLists::new(vec![
+            source("tor", ListKind::Tor, ListFormat::TorDnsel, TORDNSEL),
+            source("vpn", ListKind::Vpn, ListFormat::Plain, "45.12.0.3\n"),
+            source("hosting", ListKind::Hosting, ListFormat::Plain, HOSTING),
+        ]);
+        assert_eq!(
+            Classification::Residential,
+            lists.classify(Ipv4Addr::new(185, 220, 101, 1))
+        );
+
+        let summary = lists.refresh();
+        assert!(summary.failed.is_empty());
+        assert_eq!(
+            vec![
+                ("tor".to_string(), 1),
+                ("vpn".to_string(), 1),
+                ("hosting".to_string(), 2)
+            ],
+            summary.loaded
+        );
+
+        let classify = |ip: [u8; 4]| lists.classify(Ipv4Addr::from(ip));
+        assert_eq!(Classification::Tor, classify([185, 220, 101, 1]));
+        assert_eq!(Classification::Hosting, classify([185, 220, 101, 2]));
+        assert_eq!(Classification::Vpn, classify([45, 12, 0, 3]));
+        assert_eq!(Classification::Hosting, classify([3, 100, 0, 1]));
+        assert_eq!(Classification::Residential, classify([3, 200, 0, 1]));
+    }
+
+    #[test]
+    fn refresh_keeps_failed_lists() {
+        let body: Arc<Mutex<Option<&'static str>>> = Arc::new(Mutex::new(Some("45.12.0.0/24")));
+        let fetched = Arc::clone(&body);
+        let lists = ReputationLists::new(vec![ListSource::new(
+            "vpn",
+            ListKind::Vpn,
+            ListFormat::Plain,
+            move || match *fetched.lock().unwrap() {
+                Some(body) => Ok(Box::new(body.as_bytes()) as Box<dyn BufRead>),
+                None => Err(io::Error::other("unreachable")),
+            },
+        )]);
+
+        lists.refresh();
+        *body.lock().unwrap() = None;
+        let summary = lists.refresh();
+        assert_eq!(1, summary.failed.len());
+        assert_eq!(
+            Classification::Vpn,
+            lists.classify(Ipv4Addr::new(45, 12, 0, 9))
+        );
+
+        *body.lock().unwrap() = Some("45.13.0.0/24");
+        lists.refresh();
+        let table = lists.table();
+        let table = table.read().unwrap();
+        assert_eq!(
+            Classification::Residential,
+            classify(&table, Ipv4Addr::new(45, 12, 0, 9))
+        );
+        assert_eq!(
+            Classification::Vpn,
+            classify(&table, Ipv4Addr::new(45, 13, 0, 9))
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! Importer for the delegated-extended statistics files published by the five RIRs.
+//!
+//! Records are of the form `registry|cc|type|start|value|date|status[|opaque-id]`,
+//! where IPv4 records describe a range of `value` addresses starting at `start`
+//! rather than a cidr block. Every range is split into the cidr blocks covering it.
+
+use crate::radix_trie::{CidrBlock, Trie};
+
+use bincode::{Decode, Encode};
+
+use std::fmt;
+use std::io::{self, BufRead};
+use std::net::Ipv4Addr;
+
+/// An IPv4 delegation record of a regional internet registry.
+#[derive(Clone, Debug, Decode, Encode, Eq, Hash, PartialEq)]
+pub struct RirAllocation {
+    /// Registry that published the record, e.g. `ripencc`.
+    pub registry: String,
+    /// ISO 3166 country code, empty for available and reserved space.
+    pub country: String,
+    pub status: AllocationStatus,
+    /// Date of the allocation as `yyyymmdd`, empty if unknown.
+    pub date: String,
+    /// Opaque identifier of the holder, empty in the non-extended format.
+    pub opaque_id: String,
+}
+
+#[derive(Clone, Debug, Decode, Encode, Eq, Hash, PartialEq)]
+pub enum AllocationStatus {
+    Allocated,
+    Assigned,
+    Available,
+    Reserved,
+    Other(String),
+}
+
+impl AllocationStatus {
+    fn parse(s: &str) -> Self {
+        match s {
+            "allocated" => AllocationStatus::Allocated,
+            "assigned" => AllocationStatus::Assigned,
+            "available" => AllocationStatus::Available,
+            "reserved" => AllocationStatus::Reserved,
+            other => AllocationStatus::Other(other.to_string()),
+        }
+    }
+
+    /// Get whether or not the space is delegated to a holder.
+    pub fn is_delegated(&self) -> bool {
+        matches!(
+            self,
+            AllocationStatus::Allocated | AllocationStatus::Assigned
+        )
+    }
+}
+
+/// A record that could not be imported.
+#[derive(Debug, Eq, PartialEq)]
+pub struct RirError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for RirError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for RirError {}
+
+/// Outcome of importing a delegated-extended file.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct ImportSummary {
+    /// Number of IPv4 records imported.
+    pub records: usize,
+    /// Number of cidr blocks inserted for those records.
+    pub blocks: usize,
+    /// Number of asn and ipv6 records skipped.
+    pub skipped: usize,
+    pub errors: Vec<RirError>,
+}
+
+/// Build a trie of every IPv4 record in the delegated-extended file.
+pub fn load<R: BufRead>(reader: R) -> io::Result<(Trie<RirAllocation>, ImportSummary)> {
+    let mut trie: Trie<RirAllocation> = Trie::empty();
+    let summary: ImportSummary = load_into(&mut trie, reader)?;
+    Ok((trie, summary))
+}
+
+/// Insert every IPv4 record of the delegated-extended file into an existing trie,
+/// e.g. to combine the files of all five registries.
+pub fn load_into<R: BufRead>(
+    trie: &mut Trie<RirAllocation>,
+    reader: R,
+) -> io::Result<ImportSummary> {
+    let mut summary = ImportSummary::default();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line: String = line?;
+        let fields: Vec<&str> = line.trim().split('|').collect();
+
+        // Skip comments, the version line and the per type summary lines.
+        let version: bool = fields[0].parse::<f32>().is_ok();
+        if line.starts_with('#') || version || fields.len() < 7 || fields[1] == "*" {
+            continue;
+        }
+
+        if fields[2] != "ipv4" {
+            summary.skipped += 1;
+            continue;
+        }
+
+        match record(&fields) {
+            Ok((blocks, allocation)) => {
+                summary.records += 1;
+                summary.blocks += blocks.len();
+                for block in blocks.iter() {
+                    trie.try_insert(block, allocation.clone()).unwrap();
+                }
+            }
+            Err(reason) => summary.errors.push(RirError {
+                line: i + 1,
+                reason,
+            }),
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Get the country of the most specific delegated record containing the ip address.
+pub fn country_of(trie: &Trie<RirAllocation>, ip: Ipv4Addr) -> Option<&str> {
+    trie.get(ip)
+        .into_iter()
+        .rev()
+        .find(|a| a.status.is_delegated() && !a.country.is_empty())
+        .map(|a| a.country.as_str())
+}
+
+fn record(fields: &[&str]) -> Result<(Vec<CidrBlock>, RirAllocation), String> {
+    let start: Ipv4Addr = fields[3]
+        .parse()
+        .map_err(|_| format!("invalid start address '{}'", fields[3]))?;
+    let count: u64 = fields[4]
+        .parse()
+        .ok()
+        .filter(|c| *c > 0)
+        .ok_or_else(|| format!("invalid address count '{}'", fields[4]))?;
+
+    let first: u32 = start.into();
+    let last: u64 = first as u64 + count - 1;
+    if last > u32::MAX as u64 {
+        return Err(format!(
+            "range of {} addresses exceeds the address space",
+            count
+        ));
+    }
+
+    let allocation = RirAllocation {
+        registry: fields[0].to_string(),
+        country: fields[1].to_string(),
+        status: AllocationStatus::parse(fields[6]),
+        date: fields[5].to_string(),
+        opaque_id: fields.get(7).unwrap_or(&"").to_string(),
+    };
+
+    Ok((CidrBlock::covering_range(first, last as u32), allocation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELEGATED: &str = "\
+2|ripencc|20240101|4|19830705|20240101|+0100
+ripencc|*|ipv4|*|3|summary
+ripencc|*|asn|*|1|summary
+ripencc|FR|ipv4|2.0.0.0|1048576|20100712|allocated|ab12
+ripencc|SE|ipv4|2.16.0.0|768|20110101|assigned|cd34
+ripencc|EU|asn|7|1|19930901|allocated|ef56
+ripencc||ipv4|2.20.0.0|256||available
+ripencc|NL|ipv4|2.30.0.0|0|20110101|assigned|x
+";
+
+    #[test]
+    fn load_delegated_file() {
+        let (trie, summary) = load(DELEGATED.as_bytes()).unwrap();
+        assert_eq!(3, summary.records);
+        assert_eq!(1 + 2 + 1, summary.blocks);
+        assert_eq!(1, summary.skipped);
+        assert_eq!(1, summary.errors.len());
+        assert_eq!(8, summary.errors[0].line);
+
+        assert_eq!(Some("FR"), country_of(&trie, Ipv4Addr::new(2, 3, 4, 5)));
+        assert_eq!(Some("SE"), country_of(&trie, Ipv4Addr::new(2, 16, 2, 255)));
+        assert_eq!(None, country_of(&trie, Ipv4Addr::new(2, 16, 3, 0)));
+        assert_eq!(None, country_of(&trie, Ipv4Addr::new(2, 20, 0, 1)));
+
+        let fr = trie.get(Ipv4Addr::new(2, 3, 4, 5));
+        assert_eq!(AllocationStatus::Allocated, fr[0].status);
+        assert_eq!("20100712", fr[0].date);
+        assert_eq!("ab12", fr[0].opaque_id);
+    }
+}
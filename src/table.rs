@@ -0,0 +1,297 @@
+//! Lookup table facade over the backends, so that application code stays the same
+//! when switching between the mutable trie and the read optimized representations.
+
+use crate::arena::ArenaTrie;
+use crate::backend::{BackendKind, LpmBackend};
+use crate::builder::FrozenTrie;
+use crate::multibit::MultibitTrie;
+use crate::radix_trie::{CidrBlock, IntoIpKey, Trie, TrieError};
+
+use bincode::config;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// A table of values looked up by ip address, backed by the backend picked at
+/// construction.
+///
+/// Every backend supports the same reads, and every backend but the frozen one the
+/// same writes. The radix backend stores the values in a trie and looks up on it.
+/// The frozen backend stores them in a [`FrozenTrie`] and rejects every write with
+/// [`TrieError::Frozen`], a frozen table is filled with [`Table::from_trie`] or by
+/// decoding and thawed with [`Table::switch_backend`]. The multibit backend keeps the
+/// trie as well and rebuilds its lookup representation from it after every write, so
+/// writes should be batched with [`Table::update`]. The arena backend stores the
+/// values in the arena alone: inserts and removes go to the arena, removes leave
+/// their nodes in place, and [`Table::trie`], [`Table::update`] and the encoding
+/// convert between the arena and a trie.
+#[derive(Clone, Debug)]
+pub struct Table<V> {
+    kind: BackendKind,
+    storage: Storage<V>,
+}
+
+/// Representations holding the values of a table.
+#[derive(Clone, Debug)]
+enum Storage<V> {
+    Trie(Trie<V>),
+    Frozen(FrozenTrie<V>),
+    Multibit(Trie<V>, MultibitTrie<V>),
+    Arena(ArenaTrie<V>),
+}
+
+impl<V: Clone> Storage<V> {
+    fn new(kind: BackendKind, trie: Trie<V>) -> Self {
+        match kind {
+            BackendKind::Radix => Storage::Trie(trie),
+            BackendKind::Frozen => Storage::Frozen(FrozenTrie::from(trie)),
+            BackendKind::Multibit => {
+                let multibit: MultibitTrie<V> = MultibitTrie::from_trie(&trie);
+                Storage::Multibit(trie, multibit)
+            }
+            BackendKind::Arena => Storage::Arena(ArenaTrie::from_trie(&trie)),
+        }
+    }
+}
+
+impl<V> Storage<V> {
+    fn into_trie(self) -> Trie<V> {
+        match self {
+            Storage::Trie(trie) | Storage::Multibit(trie, _) => trie,
+            Storage::Frozen(frozen) => frozen.thaw(),
+            Storage::Arena(arena) => arena.into_trie(),
+        }
+    }
+}
+
+impl<V: Clone> Table<V> {
+    /// Create a new empty table on the default backend, see [`BackendKind::default`].
+    pub fn new() -> Self {
+        Table::with_backend(BackendKind::default())
+    }
+
+    /// Create a new empty table on the backend.
+    pub fn with_backend(kind: BackendKind) -> Self {
+        Table::from_trie(kind, Trie::empty())
+    }
+
+    /// Create a new table on the backend holding the values of the trie.
+    pub fn from_trie(kind: BackendKind, trie: Trie<V>) -> Self {
+        Table {
+            kind,
+            storage: Storage::new(kind, trie),
+        }
+    }
+
+    /// Move the values out of the storage into a trie, leaving an empty trie behind.
+    fn take_trie(&mut self) -> Trie<V> {
+        std::mem::replace(&mut self.storage, Storage::Trie(Trie::empty())).into_trie()
+    }
+
+    /// Run the closure on the underlying trie mutably, updating the backend once
+    /// afterwards. Fails with [`TrieError::Frozen`] on the frozen backend.
+    pub fn update<R>(&mut self, f: impl FnOnce(&mut Trie<V>) -> R) -> Result<R, TrieError> {
+        if let Storage::Frozen(_) = self.storage {
+            return Err(TrieError::Frozen);
+        }
+        let mut trie: Trie<V> = self.take_trie();
+        let result: R = f(&mut trie);
+        self.storage = Storage::new(self.kind, trie);
+        Ok(result)
+    }
+
+    /// Insert the value at the cidr block. Fails with [`TrieError::Frozen`] on the
+    /// frozen backend.
+    pub fn try_insert(&mut self, cidr: &CidrBlock, value: V) -> Result<(), TrieError> {
+        match &mut self.storage {
+            Storage::Trie(trie) => trie.try_insert(cidr, value),
+            Storage::Frozen(_) => Err(TrieError::Frozen),
+            Storage::Multibit(..) => self.update(|trie| trie.try_insert(cidr, value))?,
+            Storage::Arena(arena) => arena.try_insert(cidr, value),
+        }
+    }
+
+    /// Insert the value at the cidr block given as `a.b.c.d/len`.
+    ///
+    /// Panics on the frozen backend, see [`Table::try_insert`].
+    pub fn insert_cidr(&mut self, cidr: &str, value: V) {
+        let cidr: CidrBlock = CidrBlock::from_str(cidr).unwrap();
+        self.try_insert(&cidr, value).unwrap();
+    }
+
+    /// Remove and return every value stored at exactly the cidr block. Fails with
+    /// [`TrieError::Frozen`] on the frozen backend.
+    pub fn remove(&mut self, cidr: &CidrBlock) -> Result<Vec<V>, TrieError> {
+        match &mut self.storage {
+            Storage::Trie(trie) => Ok(trie.remove(cidr)),
+            Storage::Frozen(_) => Err(TrieError::Frozen),
+            Storage::Multibit(..) => self.update(|trie| trie.remove(cidr)),
+            Storage::Arena(arena) => Ok(arena.remove(cidr)),
+        }
+    }
+
+    /// Move the table to another backend, e.g. off the frozen backend to write to it.
+    pub fn switch_backend(&mut self, kind: BackendKind) {
+        let trie: Trie<V> = self.take_trie();
+        self.kind = kind;
+        self.storage = Storage::new(kind, trie);
+    }
+
+    /// Get a trie holding the values of the table, which is built from the arena on
+    /// the arena backend.
+    pub fn trie(&self) -> Cow<'_, Trie<V>> {
+        match &self.storage {
+            Storage::Trie(trie) | Storage::Multibit(trie, _) => Cow::Borrowed(trie),
+            Storage::Frozen(frozen) => Cow::Borrowed(frozen),
+            Storage::Arena(arena) => Cow::Owned(arena.to_trie()),
+        }
+    }
+}
+
+impl<V> Table<V> {
+    pub fn kind(&self) -> BackendKind {
+        self.kind
+    }
+
+    pub fn into_trie(self) -> Trie<V> {
+        self.storage.into_trie()
+    }
+
+    fn backend(&self) -> &dyn LpmBackend<V> {
+        match &self.storage {
+            Storage::Trie(trie) => trie,
+            Storage::Frozen(frozen) => frozen,
+            Storage::Multibit(_, multibit) => multibit,
+            Storage::Arena(arena) => arena,
+        }
+    }
+
+    /// Get the values associated with the provided ip address.
+    pub fn get<K: IntoIpKey>(&self, ip: K) -> Vec<&V> {
+        match ip.into_ip_key() {
+            Some(ip) => self.backend().lookup(ip),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get whether or not the table contains the provided ip address.
+    pub fn contains_ip<K: IntoIpKey>(&self, ip: K) -> bool {
+        ip.into_ip_key()
+            .is_some_and(|ip| self.backend().contains(ip))
+    }
+}
+
+impl<V: Clone> Default for Table<V> {
+    fn default() -> Self {
+        Table::new()
+    }
+}
+
+impl<V> LpmBackend<V> for Table<V> {
+    fn name(&self) -> &'static str {
+        self.kind.name()
+    }
+
+    fn lookup(&self, ip: u32) -> Vec<&V> {
+        self.backend().lookup(ip)
+    }
+
+    fn contains(&self, ip: u32) -> bool {
+        self.backend().contains(ip)
+    }
+}
+
+impl<V: Clone + Encode> Table<V> {
+    /// Encode the table into the writer, returning the number of bytes written. The
+    /// backend kind is written ahead of the trie, so that the table decodes onto the
+    /// same backend.
+    pub fn encode_to_writer<W: Write>(&self, writer: &mut W) -> Result<usize, EncodeError> {
+        bincode::encode_into_std_write((&self.kind, &*self.trie()), writer, config::standard())
+    }
+}
+
+impl<V: Clone + Decode<()>> Table<V> {
+    /// Decode a table written by [`Table::encode_to_writer`] onto its backend.
+    pub fn decode_from_reader<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let (kind, trie): (BackendKind, Trie<V>) =
+            bincode::decode_from_std_read(reader, config::standard())?;
+        Ok(Table::from_trie(kind, trie))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn same_api_on_every_backend() {
+        for kind in BackendKind::ALL
+            .into_iter()
+            .filter(|k| *k != BackendKind::Frozen)
+        {
+            let mut table: Table<u32> = Table::with_backend(kind);
+            table.insert_cidr("10.0.0.0/8", 1);
+            table.insert_cidr("10.1.0.0/16", 2);
+            table
+                .update(|trie| trie.insert_cidr("192.168.0.0/16", 3))
+                .unwrap();
+
+            assert_eq!(kind, table.kind());
+            assert_eq!(vec![&1, &2], table.get(Ipv4Addr::new(10, 1, 2, 3)));
+            assert!(table.contains_ip(Ipv4Addr::new(192, 168, 1, 1)));
+            assert_eq!(
+                vec![2],
+                table
+                    .remove(&CidrBlock::from_str("10.1.0.0/16").unwrap())
+                    .unwrap()
+            );
+            assert_eq!(vec![&1], table.get(Ipv4Addr::new(10, 1, 2, 3)));
+            assert!(!table.contains_ip(Ipv4Addr::new(172, 16, 0, 1)));
+
+            let mut expected: Trie<u32> = Trie::empty();
+            expected.insert_cidr("10.0.0.0/8", 1);
+            expected.insert_cidr("192.168.0.0/16", 3);
+            assert_eq!(expected, *table.trie());
+            assert_eq!(expected, table.into_trie());
+        }
+    }
+
+    #[test]
+    fn frozen_backend_rejects_writes() {
+        let cidr = CidrBlock::from_str("10.0.0.0/8").unwrap();
+        let mut trie: Trie<u32> = Trie::empty();
+        trie.try_insert(&cidr, 1).unwrap();
+        let mut table: Table<u32> = Table::from_trie(BackendKind::Frozen, trie.clone());
+
+        assert_eq!(Err(TrieError::Frozen), table.try_insert(&cidr, 2));
+        assert_eq!(Err(TrieError::Frozen), table.remove(&cidr));
+        assert_eq!(Err(TrieError::Frozen), table.update(|t| t.remove(&cidr)));
+        assert_eq!(vec![&1], table.get(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(trie, *table.trie());
+
+        table.switch_backend(BackendKind::Radix);
+        assert_eq!(Ok(vec![1]), table.remove(&cidr));
+    }
+
+    #[test]
+    fn encoding_records_backend() {
+        let mut table: Table<u32> = Table::with_backend(BackendKind::Multibit);
+        table.insert_cidr("10.0.0.0/8", 1);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        table.encode_to_writer(&mut buffer).unwrap();
+        let decoded: Table<u32> = Table::decode_from_reader(&mut buffer.as_slice()).unwrap();
+        assert_eq!(BackendKind::Multibit, decoded.kind());
+        assert_eq!(vec![&1], decoded.get(Ipv4Addr::new(10, 0, 0, 1)));
+
+        let mut table: Table<u32> = decoded;
+        table.switch_backend(BackendKind::Arena);
+        assert_eq!(vec![&1], table.get(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(BackendKind::default(), Table::<u32>::new().kind());
+    }
+}
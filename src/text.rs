@@ -0,0 +1,173 @@
+//! Line based text format for storing tries in version control.
+//!
+//! Every value is written on its own line as the cidr block and the base64 encoded
+//! bincode encoding of the value, separated by a tab (shown as `->` below):
+//!
+//! ```text
+//! # mm2rtrie text v1
+//! 10.0.0.0/8->Ag==
+//! 10.1.0.0/16->BA==
+//! ```
+//!
+//! Blocks are written ordered by network and prefix length, and values of the same
+//! block in the order they are stored in the trie. Empty lines and lines starting
+//! with `#` are ignored. Reading the text back yields a trie matching the same values,
+//! in the same order, for every ip address.
+
+use crate::radix_trie::{CidrBlock, CidrError, Trie};
+use crate::util::{base64_decode, base64_encode};
+
+use bincode::error::DecodeError;
+use bincode::{Decode, Encode, config};
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+const HEADER: &str = "# mm2rtrie text v1";
+
+#[derive(Debug)]
+pub enum TextError {
+    /// Reading the text failed.
+    Io(io::Error),
+    /// The line has no tab separated value.
+    MissingValue { line: usize },
+    /// The cidr block of the line is invalid.
+    Cidr { line: usize, error: CidrError },
+    /// The value of the line is not valid base64.
+    Base64 { line: usize },
+    /// The value of the line could not be decoded.
+    Value { line: usize, error: DecodeError },
+}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextError::Io(e) => write!(f, "failed to read text: {}", e),
+            TextError::MissingValue { line } => write!(f, "line {}: missing value", line),
+            TextError::Cidr { line, error } => write!(f, "line {}: {}", line, error),
+            TextError::Base64 { line } => write!(f, "line {}: value is not valid base64", line),
+            TextError::Value { line, error } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
+
+impl Error for TextError {}
+
+impl From<io::Error> for TextError {
+    fn from(e: io::Error) -> Self {
+        TextError::Io(e)
+    }
+}
+
+impl<V: Encode> Trie<V> {
+    /// Write the trie in the text format, see the [module documentation](self).
+    pub fn write_text<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let config: config::Configuration = config::standard();
+        writeln!(writer, "{}", HEADER)?;
+        for (cidr, values) in self.iter() {
+            for value in values.iter() {
+                let bytes: Vec<u8> = bincode::encode_to_vec(value, config)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                writeln!(writer, "{}\t{}", cidr, base64_encode(&bytes))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the trie in the text format, see the [module documentation](self).
+    pub fn to_text(&self) -> String {
+        let mut text: Vec<u8> = Vec::new();
+        self.write_text(&mut text).unwrap();
+        String::from_utf8(text).unwrap()
+    }
+}
+
+impl<V: Decode<()>> Trie<V> {
+    /// Read a trie written in the text format, see the [module documentation](self).
+    pub fn read_text<R: BufRead>(reader: R) -> Result<Self, TextError> {
+        let config: config::Configuration = config::standard();
+        let mut trie: Trie<V> = Trie::empty();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line: String = line?;
+            let n: usize = i + 1;
+            let line: &str = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (cidr, value) = line
+                .split_once('\t')
+                .ok_or(TextError::MissingValue { line: n })?;
+            let cidr: CidrBlock =
+                CidrBlock::from_str(cidr).map_err(|error| TextError::Cidr { line: n, error })?;
+            let bytes: Vec<u8> = base64_decode(value).ok_or(TextError::Base64 { line: n })?;
+            let (value, _): (V, usize) = bincode::decode_from_slice(&bytes, config)
+                .map_err(|error| TextError::Value { line: n, error })?;
+
+            trie.try_insert(&cidr, value).unwrap();
+        }
+
+        Ok(trie)
+    }
+
+    /// Parse a trie from the text format, see the [module documentation](self).
+    pub fn from_text(text: &str) -> Result<Self, TextError> {
+        Self::read_text(text.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::util::generate_cidr_blocks;
+
+    #[derive(Debug, Decode, Encode, Eq, PartialEq)]
+    struct Tag {
+        name: String,
+        score: i32,
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut t: Trie<Tag> = Trie::empty();
+        for (i, (net, prefix)) in generate_cidr_blocks(500).into_iter().enumerate() {
+            let tag = Tag {
+                name: format!("tag-{}", i % 7),
+                score: i as i32 - 250,
+            };
+            t.insert_net_and_prefix(net, prefix, tag);
+        }
+
+        let text: String = t.to_text();
+        assert!(text.starts_with(HEADER));
+
+        let mut decoded: Trie<Tag> = Trie::from_text(&text).unwrap();
+        decoded.compact();
+        t.compact();
+        assert_eq!(t, decoded);
+        assert_eq!(text, decoded.to_text());
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        let text = "# comment\n\n10.0.0.0/8\tAg==\n10.0.0.0/33\tAg==\n";
+        match Trie::<u32>::from_text(text) {
+            Err(TextError::Cidr { line: 4, .. }) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        match Trie::<u32>::from_text("10.0.0.0/8 Ag==") {
+            Err(TextError::MissingValue { line: 1 }) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        match Trie::<u32>::from_text("10.0.0.0/8\tA!==") {
+            Err(TextError::Base64 { line: 1 }) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
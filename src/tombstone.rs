@@ -0,0 +1,245 @@
+//! Soft deletion through tombstones, so that deletions are explicit records that can
+//! be propagated to replicas before they are physically dropped.
+
+use crate::radix_trie::{CidrBlock, IntoIpKey, Trie, TrieError};
+use crate::ttl::now;
+
+use bincode::{Decode, Encode};
+
+use std::time::Duration;
+
+/// A stored value, and when it was deleted if it is a tombstone.
+#[derive(Clone, Debug, Decode, Encode, Eq, Hash, PartialEq)]
+pub struct Record<V> {
+    pub value: V,
+    /// Unix timestamp, in milliseconds, of the deletion.
+    pub deleted_at: Option<i64>,
+}
+
+impl<V> Record<V> {
+    pub fn is_tombstone(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+}
+
+/// A trie where removing a value writes a tombstone in its place, which lookups
+/// ignore and [`SoftDeleteTrie::vacuum`] drops once the retention has passed.
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct SoftDeleteTrie<V> {
+    trie: Trie<Record<V>>,
+    /// Retention of tombstones in milliseconds.
+    retention: i64,
+}
+
+impl<V> SoftDeleteTrie<V> {
+    /// Create a new empty trie keeping tombstones for the retention.
+    pub fn new(retention: Duration) -> Self {
+        SoftDeleteTrie {
+            trie: Trie::empty(),
+            retention: i64::try_from(retention.as_millis()).unwrap_or(i64::MAX),
+        }
+    }
+
+    /// Get the underlying trie, including tombstones.
+    pub fn trie(&self) -> &Trie<Record<V>> {
+        &self.trie
+    }
+
+    /// Insert a new cidr block with corresponding value to the trie.
+    pub fn insert(&mut self, cidr: &CidrBlock, value: V) -> Result<(), TrieError> {
+        let record = Record {
+            value,
+            deleted_at: None,
+        };
+        self.trie.try_insert(cidr, record)
+    }
+
+    /// Get the values associated with the ip address, ignoring tombstones.
+    pub fn get<K: IntoIpKey>(&self, ip: K) -> Vec<&V> {
+        self.trie
+            .get(ip)
+            .into_iter()
+            .filter(|r| !r.is_tombstone())
+            .map(|r| &r.value)
+            .collect()
+    }
+
+    /// Get every tombstone with its cidr block and deletion timestamp, e.g. to
+    /// propagate deletions to replicas with [`SoftDeleteTrie::apply_tombstone`].
+    pub fn tombstones(&self) -> impl Iterator<Item = (CidrBlock, &V, i64)> {
+        self.trie.iter().flat_map(|(cidr, records)| {
+            records
+                .iter()
+                .filter_map(move |r| r.deleted_at.map(|t| (cidr, &r.value, t)))
+        })
+    }
+
+    /// Turn every value of exactly the cidr block into a tombstone, returning the
+    /// number of values removed.
+    pub fn remove_all(&mut self, cidr: &CidrBlock) -> usize {
+        mark(self.trie.get_exact_mut(cidr), now(), |_| true)
+    }
+
+    /// Drop the tombstones whose retention has passed and prune the branches that
+    /// no longer lead to any values, returning the number of tombstones dropped.
+    pub fn vacuum(&mut self) -> usize {
+        self.vacuum_at(now())
+    }
+
+    /// Like [`SoftDeleteTrie::vacuum`], with the current time provided as a unix
+    /// timestamp in milliseconds.
+    pub fn vacuum_at(&mut self, now: i64) -> usize {
+        let retention: i64 = self.retention;
+        self.trie.retain(|_, r| match r.deleted_at {
+            Some(t) => t.saturating_add(retention) > now,
+            None => true,
+        })
+    }
+}
+
+impl<V: PartialEq> SoftDeleteTrie<V> {
+    /// Turn every value of exactly the cidr block equal to the provided one into a
+    /// tombstone, returning the number of values removed.
+    pub fn remove(&mut self, cidr: &CidrBlock, value: &V) -> usize {
+        mark(self.trie.get_exact_mut(cidr), now(), |v| v == value)
+    }
+
+    /// Apply a tombstone of another trie, e.g. of the primary of a replicated setup,
+    /// turning the equal values of the cidr block into tombstones. If there are none
+    /// the tombstone is stored as is, so that it can be propagated further.
+    ///
+    /// Applying a tombstone again is a no-op, apart from keeping the later deletion
+    /// time on the stored tombstone, so tombstones may be delivered more than once.
+    pub fn apply_tombstone(
+        &mut self,
+        cidr: &CidrBlock,
+        value: V,
+        deleted_at: i64,
+    ) -> Result<(), TrieError> {
+        if cidr.prefix > 32 {
+            return Err(TrieError::InvalidPrefix(cidr.prefix));
+        }
+
+        let records = self.trie.get_exact_mut(cidr);
+        if mark(records, deleted_at, |v| *v == value) > 0 {
+            return Ok(());
+        }
+
+        let existing = records
+            .iter_mut()
+            .find(|r| r.is_tombstone() && r.value == value);
+        if let Some(record) = existing {
+            record.deleted_at = record.deleted_at.max(Some(deleted_at));
+        } else {
+            let record = Record {
+                value,
+                deleted_at: Some(deleted_at),
+            };
+            self.trie.try_insert(cidr, record)?;
+        }
+        Ok(())
+    }
+}
+
+/// Mark the live records accepted by the predicate as deleted, returning their number.
+fn mark<V>(records: &mut [Record<V>], deleted_at: i64, pred: impl Fn(&V) -> bool) -> usize {
+    let mut marked: usize = 0;
+    for record in records.iter_mut().filter(|r| !r.is_tombstone()) {
+        if pred(&record.value) {
+            record.deleted_at = Some(deleted_at);
+            marked += 1;
+        }
+    }
+    marked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    #[test]
+    fn remove_and_vacuum() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut t: SoftDeleteTrie<&str> = SoftDeleteTrie::new(Duration::from_secs(60));
+        t.insert(&cidr("10.0.0.0/8"), "corp").unwrap();
+        t.insert(&cidr("10.1.0.0/16"), "c2").unwrap();
+        t.insert(&cidr("10.1.0.0/16"), "scanner").unwrap();
+
+        let ip = Ipv4Addr::new(10, 1, 2, 3);
+        assert_eq!(1, t.remove(&cidr("10.1.0.0/16"), &"c2"));
+        assert_eq!(0, t.remove(&cidr("10.1.0.0/16"), &"c2"));
+        assert_eq!(vec![&"corp", &"scanner"], t.get(ip));
+
+        let tombstones: Vec<(CidrBlock, &&str)> =
+            t.tombstones().map(|(cidr, v, _)| (cidr, v)).collect();
+        assert_eq!(vec![(cidr("10.1.0.0/16"), &"c2")], tombstones);
+
+        assert_eq!(0, t.vacuum());
+        assert_eq!(3, t.trie().get(ip).len());
+        assert_eq!(1, t.vacuum_at(now() + 61_000));
+        assert_eq!(2, t.trie().get(ip).len());
+
+        assert_eq!(1, t.remove_all(&cidr("10.1.0.0/16")));
+        assert_eq!(vec![&"corp"], t.get(ip));
+    }
+
+    #[test]
+    fn replicate_tombstones() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut primary: SoftDeleteTrie<u32> = SoftDeleteTrie::new(Duration::from_secs(60));
+        let mut replica: SoftDeleteTrie<u32> = SoftDeleteTrie::new(Duration::from_secs(60));
+        for t in [&mut primary, &mut replica] {
+            t.insert(&cidr("10.0.0.0/8"), 1).unwrap();
+            t.insert(&cidr("10.0.0.0/8"), 2).unwrap();
+        }
+
+        primary.remove(&cidr("10.0.0.0/8"), &1);
+        primary
+            .apply_tombstone(&cidr("20.0.0.0/8"), 3, 1000)
+            .unwrap();
+        for (block, value, deleted_at) in primary.tombstones() {
+            replica.apply_tombstone(&block, *value, deleted_at).unwrap();
+        }
+
+        assert_eq!(primary, replica);
+        assert_eq!(vec![&2], replica.get(Ipv4Addr::new(10, 0, 0, 1)));
+
+        let long = CidrBlock {
+            net: 0x0a000000,
+            prefix: 33,
+        };
+        assert_eq!(Err(TrieError::InvalidPrefix(33)), replica.insert(&long, 4));
+        assert_eq!(
+            Err(TrieError::InvalidPrefix(33)),
+            replica.apply_tombstone(&long, 4, 1000)
+        );
+        assert_eq!(primary, replica);
+    }
+
+    #[test]
+    fn apply_tombstone_twice() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let mut t: SoftDeleteTrie<u32> = SoftDeleteTrie::new(Duration::from_secs(60));
+        t.insert(&cidr("10.0.0.0/8"), 1).unwrap();
+
+        for deleted_at in [1000, 1000, 3000, 2000] {
+            t.apply_tombstone(&cidr("10.0.0.0/8"), 1, deleted_at)
+                .unwrap();
+            t.apply_tombstone(&cidr("20.0.0.0/8"), 2, deleted_at)
+                .unwrap();
+        }
+
+        let tombstones: Vec<(CidrBlock, &u32, i64)> = t.tombstones().collect();
+        assert_eq!(
+            vec![
+                (cidr("10.0.0.0/8"), &1, 3000),
+                (cidr("20.0.0.0/8"), &2, 3000)
+            ],
+            tombstones
+        );
+        assert!(t.get(Ipv4Addr::new(10, 0, 0, 1)).is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+//! Values with a time to live, skipped by lookups once expired and dropped by
+//! compaction, e.g. for threat intelligence indicators with natural lifetimes.
+
+use crate::maintenance::Maintainer;
+use crate::radix_trie::{CidrBlock, IntoIpKey, Trie, TrieError};
+
+use bincode::{Decode, Encode};
+
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A value together with when it expires.
+#[derive(Clone, Debug, Decode, Encode, Eq, Hash, PartialEq)]
+pub struct Expiring<V> {
+    pub value: V,
+    /// Unix timestamp, in milliseconds, from which on the value is expired.
+    pub expires_at: i64,
+}
+
+impl<V> Expiring<V> {
+    /// Wrap a value that never expires.
+    pub fn forever(value: V) -> Self {
+        Expiring {
+            value,
+            expires_at: i64::MAX,
+        }
+    }
+
+    /// Get whether or not the value is expired at the timestamp.
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// Get the current unix timestamp in milliseconds.
+pub fn now() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// A trie of values with a time to live.
+///
+/// Expired values are skipped by lookups right away, but only removed from the trie
+/// by [`TtlTrie::compact`], or by a [`Maintainer`] with [`Maintainer::with_expiry`].
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub struct TtlTrie<V> {
+    trie: Trie<Expiring<V>>,
+}
+
+impl<V> TtlTrie<V> {
+    /// Create a new empty trie.
+    pub fn empty() -> Self {
+        TtlTrie {
+            trie: Trie::empty(),
+        }
+    }
+
+    /// Get the underlying trie, including expired values not yet compacted away.
+    pub fn trie(&self) -> &Trie<Expiring<V>> {
+        &self.trie
+    }
+
+    /// Insert a new cidr block with a value that never expires.
+    pub fn insert_cidr(&mut self, cidr: &str, value: V) {
+        self.trie.insert_cidr(cidr, Expiring::forever(value));
+    }
+
+    /// Insert a new cidr block with a value that expires after the ttl.
+    pub fn insert_cidr_with_ttl(&mut self, cidr: &str, value: V, ttl: Duration) {
+        self.insert_with_ttl(&CidrBlock::from_str(cidr).unwrap(), value, ttl)
+            .unwrap();
+    }
+
+    /// Insert a new cidr block with a value that expires after the ttl.
+    pub fn insert_with_ttl(
+        &mut self,
+        cidr: &CidrBlock,
+        value: V,
+        ttl: Duration,
+    ) -> Result<(), TrieError> {
+        let ttl: i64 = i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX);
+        let value = Expiring {
+            value,
+            expires_at: now().saturating_add(ttl),
+        };
+        self.trie.try_insert(cidr, value)
+    }
+
+    /// Insert a new cidr block with a value that expires at the unix timestamp, in
+    /// milliseconds.
+    pub fn insert_until(
+        &mut self,
+        cidr: &CidrBlock,
+        value: V,
+        expires_at: i64,
+    ) -> Result<(), TrieError> {
+        self.trie.try_insert(cidr, Expiring { value, expires_at })
+    }
+
+    /// Get the values associated with the ip address that are not yet expired.
+    pub fn get<K: IntoIpKey>(&self, ip: K) -> Vec<&V> {
+        self.get_at(ip, now())
+    }
+
+    /// Get the values associated with the ip address that are not expired at the
+    /// unix timestamp, in milliseconds.
+    pub fn get_at<K: IntoIpKey>(&self, ip: K, now: i64) -> Vec<&V> {
+        self.trie
+            .get(ip)
+            .into_iter()
+            .filter(|v| !v.is_expired(now))
+            .map(|v| &v.value)
+            .collect()
+    }
+
+    /// Get whether or not any value not yet expired is associated with the ip address.
+    pub fn contains_ip<K: IntoIpKey>(&self, ip: K) -> bool {
+        let now: i64 = now();
+        self.trie.get(ip).iter().any(|v| !v.is_expired(now))
+    }
+
+    /// Remove expired values and prune the branches left without any values,
+    /// returning the number of values removed.
+    pub fn compact(&mut self) -> usize {
+        self.trie.prune_expired(now())
+    }
+}
+
+impl<V> Default for TtlTrie<V> {
+    fn default() -> Self {
+        TtlTrie::empty()
+    }
+}
+
+impl<V> Trie<Expiring<V>> {
+    /// Remove the values expired at the unix timestamp, in milliseconds, and prune the
+    /// branches left without any values. Returns the number of values removed.
+    pub fn prune_expired(&mut self, now: i64) -> usize {
+        self.retain(|_, v| !v.is_expired(now))
+    }
+}
+
+impl<V: Clone + 'static> Maintainer<Expiring<V>> {
+    /// Add a pass removing expired values.
+    pub fn with_expiry(self) -> Self {
+        self.with_pass(|trie| {
+            trie.prune_expired(now());
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn expired_values_are_skipped_and_compacted() {
+        let mut t: TtlTrie<&str> = TtlTrie::empty();
+        t.insert_cidr("10.0.0.0/8", "corp");
+        t.insert_cidr_with_ttl("10.1.0.0/16", "c2", Duration::from_secs(3600));
+        t.insert_until(&CidrBlock::from_str("10.1.2.0/24").unwrap(), "scanner", 0)
+            .unwrap();
+
+        let ip = Ipv4Addr::new(10, 1, 2, 3);
+        assert_eq!(vec![&"corp", &"c2"], t.get(ip));
+        assert_eq!(vec![&"corp", &"c2", &"scanner"], t.get_at(ip, -1));
+        assert_eq!(vec![&"corp"], t.get_at(ip, now() + 3_600_001));
+        assert_eq!(3, t.trie().get(ip).len());
+
+        assert_eq!(1, t.compact());
+        assert_eq!(2, t.trie().get(ip).len());
+        assert!(t.contains_ip(ip));
+
+        let long = CidrBlock { net: 0, prefix: 33 };
+        assert_eq!(
+            Err(TrieError::InvalidPrefix(33)),
+            t.insert_with_ttl(&long, "x", Duration::from_secs(1))
+        );
+        assert_eq!(
+            Err(TrieError::InvalidPrefix(33)),
+            t.insert_until(&long, "x", 0)
+        );
+    }
+}
@@ -29,17 +29,120 @@ pub fn cidr_to_u32_parts(cidr: &str) -> (u32, u32) {
     let ip = parts.next().unwrap();
     let prefix: u32 = parts.next().unwrap().parse().unwrap();
 
-    let ip_parts: Vec<u32> = ip
-        .split(".")
-        .into_iter()
-        .map(|p| p.parse().unwrap())
-        .collect();
+    let ip_parts: Vec<u32> = ip.split(".").map(|p| p.parse().unwrap()).collect();
 
     let mut ipint: u32 = 0;
 
     for (i, num) in ip_parts.iter().enumerate() {
-        ipint += num * (256u32.pow(3u32 - (i as u32)) as u32);
+        ipint += num * 256u32.pow(3u32 - (i as u32));
     }
 
     (ipint, prefix)
 }
+
+/// Get the narrowest CIDR block containing all the IPv4 numbers as its u32 parts
+/// (net, prefix), `None` if there are no IPv4 numbers.
+pub fn common_supernet(ips: &[u32]) -> Option<(u32, u32)> {
+    let first: u32 = *ips.first()?;
+    let diff: u32 = ips.iter().fold(0, |diff, ip| diff | (ip ^ first));
+    let prefix: u32 = diff.leading_zeros();
+    let mask: u32 = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+    Some((first & mask, prefix))
+}
+
+/// Initial state of the 64 bit FNV-1a hash.
+pub const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// Feed bytes into a 64 bit FNV-1a hash, starting from [`FNV_OFFSET_BASIS`].
+/// Unlike the std hashers the result is stable across platforms and releases.
+pub fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes as padded standard base64.
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b: [u8; 3] = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n: u32 = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decode padded standard base64, returning `None` if the input is not valid base64.
+pub fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s: &[u8] = s.as_bytes();
+    if !s.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut out: Vec<u8> = Vec::with_capacity(s.len() / 4 * 3);
+    for (i, chunk) in s.chunks(4).enumerate() {
+        let padding: usize = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && i != s.len() / 4 - 1) {
+            return None;
+        }
+
+        let mut n: u32 = 0;
+        for c in chunk[..4 - padding].iter() {
+            let sextet = BASE64_ALPHABET.iter().position(|a| a == c)? as u32;
+            n = (n << 6) | sextet;
+        }
+        n <<= 6 * padding as u32;
+
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_round_trip() {
+        for (raw, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(encoded, base64_encode(raw.as_bytes()));
+            assert_eq!(Some(raw.as_bytes().to_vec()), base64_decode(encoded));
+        }
+
+        assert_eq!(None, base64_decode("Zg="));
+        assert_eq!(None, base64_decode("Zg==Zg=="));
+        assert_eq!(None, base64_decode("Z!=="));
+    }
+
+    #[test]
+    fn common_supernet_parts() {
+        assert_eq!(None, common_supernet(&[]));
+        assert_eq!(Some((0x0a010203, 32)), common_supernet(&[0x0a010203]));
+        assert_eq!(
+            Some((0x0a010200, 24)),
+            common_supernet(&[0x0a010203, 0x0a0102ff, 0x0a010280])
+        );
+        assert_eq!(Some((0, 0)), common_supernet(&[0x0a000000, 0xc0a80000]));
+    }
+}
@@ -0,0 +1,213 @@
+//! Validation of prefix list files, so bad feed files are caught before they are built
+//! into a table.
+
+use crate::radix_trie::{CidrBlock, CidrError, Trie};
+
+use std::fmt;
+use std::io::{self, BufRead};
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+/// Options of a prefix list in CSV.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ValidateOptions {
+    /// Column of the cidr block, counting from 0.
+    pub column: usize,
+    /// Whether or not the first line is a header to skip.
+    pub header: bool,
+}
+
+/// A problem with a line of a prefix list, by line number counting from 1.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Finding {
+    /// The line has no valid cidr block, host bits set included.
+    Malformed { line: usize, error: CidrError },
+    /// The block was already listed on an earlier line.
+    Duplicate {
+        line: usize,
+        first: usize,
+        cidr: CidrBlock,
+    },
+    /// The block is inside a less specific block listed on another line.
+    Overlap {
+        line: usize,
+        cidr: CidrBlock,
+        outer_line: usize,
+        outer: CidrBlock,
+    },
+}
+
+impl Finding {
+    /// Get whether or not the finding makes the prefix list invalid. Overlaps are
+    /// only reported, since nested blocks are common in feeds.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, Finding::Overlap { .. })
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::Malformed { line, error } => write!(f, "line {}: {}", line, error),
+            Finding::Duplicate { line, first, cidr } => {
+                write!(f, "line {}: duplicate of {} on line {}", line, cidr, first)
+            }
+            Finding::Overlap {
+                line,
+                cidr,
+                outer_line,
+                outer,
+            } => write!(
+                f,
+                "line {}: {} overlaps {} on line {}",
+                line, cidr, outer, outer_line
+            ),
+        }
+    }
+}
+
+/// Outcome of validating a prefix list.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct ValidationReport {
+    /// Number of valid blocks, duplicates included.
+    pub blocks: usize,
+    /// Malformed lines in order, followed by duplicates and overlaps ordered by block.
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    /// Get whether or not the prefix list has no malformed lines and no duplicates.
+    pub fn is_ok(&self) -> bool {
+        !self.findings.iter().any(Finding::is_error)
+    }
+}
+
+/// Validate the prefix list, one block per line in the column of comma separated
+/// fields. Empty lines and lines starting with `#` are skipped.
+pub fn validate<R: BufRead>(reader: R, options: &ValidateOptions) -> io::Result<ValidationReport> {
+    let mut report = ValidationReport::default();
+    let mut lines: Trie<usize> = Trie::empty();
+
+    for (i, line) in reader.lines().enumerate().skip(usize::from(options.header)) {
+        let line: String = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let field: &str = line.split(',').nth(options.column).unwrap_or("").trim();
+        let parsed = CidrBlock::from_str(field)
+            .and_then(|cidr| CidrBlock::new_strict(Ipv4Addr::from(cidr.net), cidr.prefix));
+        match parsed {
+            Ok(cidr) => {
+                report.blocks += 1;
+                lines.try_insert(&cidr, i + 1).unwrap();
+            }
+            Err(error) => report
+                .findings
+                .push(Finding::Malformed { line: i + 1, error }),
+        }
+    }
+
+    for (cidr, at) in lines.iter() {
+        let first: usize = at[0];
+        for &line in at[1..].iter() {
+            report
+                .findings
+                .push(Finding::Duplicate { line, first, cidr });
+        }
+
+        for (outer, outer_lines) in lines.get_blocks(cidr.net) {
+            if outer.prefix < cidr.prefix {
+                report.findings.push(Finding::Overlap {
+                    line: first,
+                    cidr,
+                    outer_line: outer_lines[0],
+                    outer,
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_prefix_list() {
+        let cidr = |s: &str| CidrBlock::from_str(s).unwrap();
+        let input: &str = "cidr,label
+10.0.0.0/8,private
+# comment
+10.1.0.0/16,lab
+not a block,oops
+
+10.0.0.1/24,host bits
+192.168.0.0/16,home
+10.0.0.0/8,again
+10.1.2.0/24,nested
+";
+        let options = ValidateOptions {
+            column: 0,
+            header: true,
+        };
+        let report: ValidationReport = validate(input.as_bytes(), &options).unwrap();
+
+        assert_eq!(5, report.blocks);
+        assert!(!report.is_ok());
+        assert_eq!(
+            vec![
+                Finding::Malformed {
+                    line: 5,
+                    error: CidrError::Malformed("not a block".to_string())
+                },
+                Finding::Malformed {
+                    line: 7,
+                    error: CidrError::HostBitsSet(CidrBlock {
+                        net: 0x0a000001,
+                        prefix: 24
+                    })
+                },
+                Finding::Duplicate {
+                    line: 9,
+                    first: 2,
+                    cidr: cidr("10.0.0.0/8")
+                },
+                Finding::Overlap {
+                    line: 4,
+                    cidr: cidr("10.1.0.0/16"),
+                    outer_line: 2,
+                    outer: cidr("10.0.0.0/8")
+                },
+                Finding::Overlap {
+                    line: 10,
+                    cidr: cidr("10.1.2.0/24"),
+                    outer_line: 2,
+                    outer: cidr("10.0.0.0/8")
+                },
+                Finding::Overlap {
+                    line: 10,
+                    cidr: cidr("10.1.2.0/24"),
+                    outer_line: 4,
+                    outer: cidr("10.1.0.0/16")
+                },
+            ],
+            report.findings
+        );
+        assert_eq!(
+            "line 10: 10.1.2.0/24 overlaps 10.1.0.0/16 on line 4",
+            report.findings[5].to_string()
+        );
+
+        let labels = ValidateOptions {
+            column: 1,
+            header: false,
+        };
+        let report: ValidationReport =
+            validate("a,10.0.0.0/8\nb,10.1.0.0/16\n".as_bytes(), &labels).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(1, report.findings.len());
+    }
+}